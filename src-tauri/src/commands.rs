@@ -4,22 +4,208 @@ use serde_json::Value;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Mutex;
-use tauri::{command, Manager, State};
+use tauri::{command, Emitter, Manager, State};
 use tauri_plugin_updater::UpdaterExt;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_util::sync::CancellationToken;
+
+use log::{debug, error, info, warn};
 
 use chrono::Utc;
 use hmac::{Hmac, Mac};
 use sha1::Sha1;
 
 // 定义前端传过来的数据结构
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RequestOptions {
     method: String,
     url: String,
     headers: Option<HashMap<String, String>>,
     body: Option<Value>,
     token: Option<String>,
+    user_agent: Option<String>, // 自定义 User-Agent
+    #[serde(default)]
+    timeout_secs: Option<u64>, // 请求超时（秒），默认 480
+    #[serde(default)]
+    connect_timeout_secs: Option<u64>, // 连接超时（秒），默认 300
+    #[serde(default)]
+    follow_redirects: Option<bool>, // 是否自动跟随重定向，默认 true
+}
+
+// 全局默认请求头（合并到每个代理请求中，单次请求的同名请求头优先）
+#[derive(Debug, Default)]
+pub struct DefaultHeaders(pub Mutex<HashMap<String, String>>);
+
+// 取消操作时返回的错误，前端据此区分取消与真实失败
+const CANCELLED_ERROR: &str = "cancelled";
+
+// 正在进行的网络操作登记表（用于一键取消全部操作）
+#[derive(Debug, Default)]
+pub struct ActiveOperations {
+    next_id: std::sync::atomic::AtomicU64,
+    tokens: Mutex<HashMap<u64, CancellationToken>>,
+}
+
+impl ActiveOperations {
+    // 登记一个操作，返回的守卫在离开作用域时自动注销
+    fn register(&self) -> OperationGuard<'_> {
+        let id = self
+            .next_id
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let token = CancellationToken::new();
+        if let Ok(mut tokens) = self.tokens.lock() {
+            tokens.insert(id, token.clone());
+        }
+        OperationGuard {
+            operations: self,
+            id,
+            token,
+        }
+    }
+
+    // 在登记状态下执行操作，被取消时立即返回 CANCELLED_ERROR
+    async fn run<T, F>(&self, operation: F) -> Result<T, String>
+    where
+        F: std::future::Future<Output = Result<T, String>>,
+    {
+        let guard = self.register();
+        tokio::select! {
+            result = operation => result,
+            _ = guard.token.cancelled() => Err(CANCELLED_ERROR.to_string()),
+        }
+    }
+
+    // 取消所有已登记的操作，返回取消数量
+    fn cancel_all(&self) -> usize {
+        match self.tokens.lock() {
+            Ok(tokens) => {
+                for token in tokens.values() {
+                    token.cancel();
+                }
+                tokens.len()
+            }
+            Err(_) => 0,
+        }
+    }
+}
+
+struct OperationGuard<'a> {
+    operations: &'a ActiveOperations,
+    id: u64,
+    token: CancellationToken,
+}
+
+impl Drop for OperationGuard<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut tokens) = self.operations.tokens.lock() {
+            tokens.remove(&self.id);
+        }
+    }
+}
+
+// 默认最多同时进行的出站网络请求数
+const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 16;
+
+// 全局出站请求限流：代理请求、上传、下载、图片缓存在发送前都需获得一个许可，避免耗尽套接字/文件句柄
+struct NetworkLimiter {
+    permits: tokio::sync::Semaphore,
+    max_concurrent: std::sync::atomic::AtomicUsize,
+    active: std::sync::atomic::AtomicUsize,
+    queued: std::sync::atomic::AtomicUsize,
+}
+
+static NETWORK_LIMITER: NetworkLimiter = NetworkLimiter {
+    permits: tokio::sync::Semaphore::const_new(DEFAULT_MAX_CONCURRENT_REQUESTS),
+    max_concurrent: std::sync::atomic::AtomicUsize::new(DEFAULT_MAX_CONCURRENT_REQUESTS),
+    active: std::sync::atomic::AtomicUsize::new(0),
+    queued: std::sync::atomic::AtomicUsize::new(0),
+};
+
+// 持有期间计入活跃请求数，离开作用域时归还许可
+struct NetworkPermit {
+    _permit: tokio::sync::SemaphorePermit<'static>,
+}
+
+impl Drop for NetworkPermit {
+    fn drop(&mut self) {
+        NETWORK_LIMITER
+            .active
+            .fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+// 排队计数守卫：等待被取消时自动减少排队数
+struct QueuedRequest;
+
+impl Drop for QueuedRequest {
+    fn drop(&mut self) {
+        NETWORK_LIMITER
+            .queued
+            .fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+// 等待一个出站请求许可
+async fn acquire_network_permit() -> Result<NetworkPermit, String> {
+    use std::sync::atomic::Ordering;
+
+    NETWORK_LIMITER.queued.fetch_add(1, Ordering::Relaxed);
+    let queued = QueuedRequest;
+    let permit = NETWORK_LIMITER
+        .permits
+        .acquire()
+        .await
+        .map_err(|e| format!("网络请求限流器已关闭: {}", e))?;
+    drop(queued);
+    NETWORK_LIMITER.active.fetch_add(1, Ordering::Relaxed);
+    Ok(NetworkPermit { _permit: permit })
+}
+
+// 设置最多同时进行的出站请求数，调小时等待进行中的请求结束后逐步生效
+#[command]
+pub fn set_max_concurrent_requests(n: usize) -> Result<(), String> {
+    use std::sync::atomic::Ordering;
+
+    if n == 0 || n > tokio::sync::Semaphore::MAX_PERMITS {
+        return Err(format!("无效的最大并发请求数: {}", n));
+    }
+    let previous = NETWORK_LIMITER.max_concurrent.swap(n, Ordering::SeqCst);
+    if n > previous {
+        NETWORK_LIMITER.permits.add_permits(n - previous);
+    } else if n < previous {
+        let deficit = previous - n;
+        let remaining = deficit - NETWORK_LIMITER.permits.forget_permits(deficit);
+        // 空闲许可不足时，等进行中的请求归还后再回收
+        if remaining > 0 {
+            tauri::async_runtime::spawn(async move {
+                if let Ok(permits) = NETWORK_LIMITER.permits.acquire_many(remaining as u32).await {
+                    permits.forget();
+                }
+            });
+        }
+    }
+    info!("[Network] 最大并发请求数: {} -> {}", previous, n);
+    Ok(())
+}
+
+// 网络请求统计
+#[derive(Debug, Serialize)]
+pub struct NetworkStats {
+    pub active: usize,
+    pub queued: usize,
+    pub max_concurrent: usize,
+}
+
+// 获取当前进行中与排队中的出站请求数
+#[command]
+pub fn network_stats() -> NetworkStats {
+    use std::sync::atomic::Ordering;
+
+    NetworkStats {
+        active: NETWORK_LIMITER.active.load(Ordering::Relaxed),
+        queued: NETWORK_LIMITER.queued.load(Ordering::Relaxed),
+        max_concurrent: NETWORK_LIMITER.max_concurrent.load(Ordering::Relaxed),
+    }
 }
 
 // 定义返回给前端的数据结构
@@ -27,16 +213,25 @@ pub struct RequestOptions {
 pub struct ApiResponse {
     status: u16,
     data: Value,
+    headers: HashMap<String, String>, // 响应头（名称小写，多值合并）
 }
 
 // 文件上传选项
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UploadOptions {
     pub file_path: String,
     pub upload_url: String,
     pub field_name: String,
     pub response_format: Option<String>, // "url" 或 "json"
     pub proxy_url: Option<String>,       // 代理地址
+    pub content_type: Option<String>,    // 覆盖自动推断的 MIME 类型
+    pub json_fields: Option<HashMap<String, Value>>, // 以 application/json 类型附加的字段
+    pub extra_fields: Option<HashMap<String, String>>, // 附加的文本字段（如 catbox 的 reqtype）
+    pub verify: Option<bool>,            // 上传成功后确认返回的 URL 可访问
+    pub upload_id: Option<String>,       // 传入时发送 upload-progress 事件
+    pub max_retries: Option<u32>,        // 最多尝试次数，默认 3
+    pub retry_backoff_ms: Option<u64>,   // 重试退避基数（毫秒），按 2 的幂递增，默认 3000
+    pub url_json_path: Option<String>,   // JSON 响应中 URL 的点分路径，如 data.url
 }
 
 // 文件上传响应
@@ -47,11 +242,26 @@ pub struct UploadResponse {
     pub error: Option<String>,
 }
 
+// 更新检查状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateCheckStatus {
+    UpdateAvailable, // 有新版本
+    UpToDate,        // 已是最新版本
+    NoUpdater,       // 未配置更新服务器
+    ReleaseNotFound, // 更新服务器上没有适用于当前平台的版本，视为无可用更新
+    NetworkError,    // 网络错误或更新服务器不可达
+    SignatureError,  // 签名/公钥校验失败
+    Unknown,         // 其他错误
+}
+
 // 更新检查响应
 #[derive(Debug, Serialize)]
 pub struct UpdateCheckResponse {
+    pub status: UpdateCheckStatus,
     pub should_update: bool,
     pub manifest: Option<UpdateManifest>,
+    pub error: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -61,15 +271,306 @@ pub struct UpdateManifest {
     pub date: String,
 }
 
+// 设置全局默认请求头（覆盖之前的设置）
+#[command]
+pub fn set_default_headers(
+    state: State<'_, DefaultHeaders>,
+    headers: HashMap<String, String>,
+) -> Result<(), String> {
+    let mut defaults = state.0.lock().map_err(|e| e.to_string())?;
+    info!("[API] 设置默认请求头: {} 项", headers.len());
+    *defaults = headers;
+    Ok(())
+}
+
+// 合并默认请求头与单次请求头（按名称忽略大小写，单次请求优先）
+fn merge_headers(
+    defaults: &HashMap<String, String>,
+    overrides: Option<&HashMap<String, String>>,
+) -> HashMap<String, String> {
+    let mut merged = defaults.clone();
+    if let Some(overrides) = overrides {
+        for (k, v) in overrides {
+            merged.retain(|existing, _| !existing.eq_ignore_ascii_case(k));
+            merged.insert(k.clone(), v.clone());
+        }
+    }
+    merged
+}
+
+// Token 自动刷新配置：请求返回 401 时调用 refresh_url，从响应的 token_json_path 读取新 token
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuthRefreshConfig {
+    pub refresh_url: String,
+    pub token_json_path: String,
+    pub method: Option<String>, // 默认 POST
+    pub headers: Option<HashMap<String, String>>,
+    pub body: Option<Value>,
+}
+
+// Token 刷新状态：已失效的 token 会被自动替换为最新 token
+#[derive(Default)]
+pub struct AuthRefresh {
+    config: Mutex<Option<AuthRefreshConfig>>,
+    tokens: Mutex<AuthTokens>,
+    // 同一时间只执行一次刷新，其余 401 请求等待并复用结果
+    refreshing: tokio::sync::Mutex<()>,
+}
+
+#[derive(Default)]
+struct AuthTokens {
+    current: Option<String>,
+    stale: std::collections::HashSet<String>,
+}
+
+impl AuthRefresh {
+    fn tokens(&self) -> std::sync::MutexGuard<'_, AuthTokens> {
+        self.tokens.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // 请求携带的 token 已被刷新过时替换为最新 token
+    fn latest_token(&self, token: Option<String>) -> Option<String> {
+        let tokens = self.tokens();
+        match token {
+            Some(token) if tokens.stale.contains(&token) => tokens.current.clone().or(Some(token)),
+            other => other,
+        }
+    }
+
+    // 刷新失效的 token，返回新 token；其他请求已完成刷新时直接复用
+    async fn refresh(
+        &self,
+        app: &tauri::AppHandle,
+        default_headers: &HashMap<String, String>,
+        expired: &str,
+    ) -> Result<String, String> {
+        let _refreshing = self.refreshing.lock().await;
+        if let Some(token) = self.latest_token(Some(expired.to_string())) {
+            if token != expired {
+                return Ok(token);
+            }
+        }
+
+        let config = self
+            .config
+            .lock()
+            .map_err(|e| e.to_string())?
+            .clone()
+            .ok_or("未配置 token 刷新")?;
+        info!("[AuthRefresh] 收到 401，刷新 token: {}", config.refresh_url);
+
+        let response = send_proxy_request(
+            default_headers,
+            RequestOptions {
+                method: config.method.unwrap_or_else(|| "POST".to_string()),
+                url: config.refresh_url,
+                headers: config.headers,
+                body: config.body,
+                token: None,
+                user_agent: None,
+                timeout_secs: None,
+                connect_timeout_secs: None,
+                follow_redirects: None,
+            },
+        )
+        .await?;
+        if !(200..300).contains(&response.status) {
+            return Err(format!("刷新 token 失败: HTTP {}", response.status));
+        }
+        let token = json_path_get(&response.data, &config.token_json_path)
+            .and_then(|v| v.as_str())
+            .filter(|t| !t.is_empty())
+            .ok_or_else(|| format!("刷新响应中没有 token: {}", config.token_json_path))?
+            .to_string();
+
+        {
+            let mut tokens = self.tokens();
+            tokens.stale.insert(expired.to_string());
+            if let Some(previous) = tokens.current.replace(token.clone()) {
+                tokens.stale.insert(previous);
+            }
+            tokens.stale.remove(&token);
+        }
+        // 通知前端保存新 token
+        if let Err(e) = app.emit("auth-token-refreshed", &token) {
+            warn!("[AuthRefresh] 发送 auth-token-refreshed 事件失败: {}", e);
+        }
+        info!("[AuthRefresh] token 已刷新");
+        Ok(token)
+    }
+}
+
+// 设置 token 自动刷新配置，传入 None 关闭自动刷新
+#[command]
+pub fn set_auth_refresh(
+    auth_refresh: State<'_, AuthRefresh>,
+    config: Option<AuthRefreshConfig>,
+) -> Result<(), String> {
+    match &config {
+        Some(config) => info!("[AuthRefresh] 设置刷新地址: {}", config.refresh_url),
+        None => info!("[AuthRefresh] 关闭自动刷新"),
+    }
+    *auth_refresh.config.lock().map_err(|e| e.to_string())? = config;
+    *auth_refresh.tokens() = AuthTokens::default();
+    Ok(())
+}
+
 // 核心指令：代理 HTTP 请求
+// 配置了 token 自动刷新时，带 token 的请求收到 401 会刷新一次 token 并重试
 #[command]
-pub async fn proxy_http_request(options: RequestOptions) -> Result<ApiResponse, String> {
-    // 创建客户端，设置 8 分钟超时
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(480)) // 8 分钟请求超时
-        .connect_timeout(std::time::Duration::from_secs(300)) // 5 分钟连接超时
-        .build()
+pub async fn proxy_http_request(
+    app: tauri::AppHandle,
+    default_headers: State<'_, DefaultHeaders>,
+    operations: State<'_, ActiveOperations>,
+    auth_refresh: State<'_, AuthRefresh>,
+    mut options: RequestOptions,
+) -> Result<ApiResponse, String> {
+    let defaults = default_headers.0.lock().map_err(|e| e.to_string())?.clone();
+    let refresh_enabled = auth_refresh
+        .config
+        .lock()
+        .map_err(|e| e.to_string())?
+        .is_some();
+    options.token = auth_refresh.latest_token(options.token);
+
+    operations
+        .run(async {
+            let response = send_proxy_request(&defaults, options.clone()).await?;
+            let expired = match options.token.as_deref() {
+                Some(token) if refresh_enabled && response.status == 401 => token,
+                _ => return Ok(response),
+            };
+
+            let token = auth_refresh.refresh(&app, &defaults, expired).await?;
+            options.token = Some(token);
+            send_proxy_request(&defaults, options).await
+        })
+        .await
+}
+
+// 发送代理请求（proxy_http_request 与轮询等命令共用）
+async fn send_proxy_request(
+    default_headers: &HashMap<String, String>,
+    options: RequestOptions,
+) -> Result<ApiResponse, String> {
+    let _permit = acquire_network_permit().await?;
+    let is_head = options.method == "HEAD";
+    let response = build_proxy_request(default_headers, options)?
+        .send()
+        .await
         .map_err(|e| e.to_string())?;
+    let status = response.status().as_u16();
+    let headers = collect_response_headers(response.headers());
+
+    // HEAD 响应没有 Body，直接返回空对象
+    if is_head {
+        return Ok(ApiResponse {
+            status,
+            data: serde_json::json!({}),
+            headers,
+        });
+    }
+
+    // 二进制响应（如图片）以 base64 返回，避免按文本解码损坏数据
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+    if !is_text_content_type(content_type.as_deref()) {
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|e| format!("读取响应失败: {}", e))?;
+        debug!(
+            "[API] 二进制响应: {} ({} bytes)",
+            content_type.as_deref().unwrap_or_default(),
+            bytes.len()
+        );
+        let encoded = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &bytes);
+        let data = serde_json::json!({
+            "binary_base64": encoded,
+            "content_type": content_type,
+        });
+        return Ok(ApiResponse {
+            status,
+            data,
+            headers,
+        });
+    }
+
+    // 先尝试获取文本，然后再尝试 JSON 解析
+    let response_text = response.text().await.unwrap_or_default();
+
+    // 尝试解析 JSON
+    let data: Value = match serde_json::from_str(&response_text) {
+        Ok(json) => json,
+        Err(_) => {
+            debug!("[API] 非 JSON 响应: {}", response_text);
+            // 返回一个带有原始文本的 JSON 对象
+            serde_json::json!({ "raw_response": response_text, "status": status })
+        }
+    };
+
+    Ok(ApiResponse {
+        status,
+        data,
+        headers,
+    })
+}
+
+// 收集响应头：同名多值以 ", " 合并（Set-Cookie 含逗号，改用换行），非 UTF-8 的值跳过
+fn collect_response_headers(header_map: &reqwest::header::HeaderMap) -> HashMap<String, String> {
+    let mut headers: HashMap<String, String> = HashMap::new();
+    for (name, value) in header_map {
+        let Ok(value) = value.to_str() else {
+            debug!("[API] 跳过非 UTF-8 响应头: {}", name);
+            continue;
+        };
+        let separator = if name == reqwest::header::SET_COOKIE {
+            "\n"
+        } else {
+            ", "
+        };
+        headers
+            .entry(name.as_str().to_string())
+            .and_modify(|existing| {
+                existing.push_str(separator);
+                existing.push_str(value);
+            })
+            .or_insert_with(|| value.to_string());
+    }
+    headers
+}
+
+// 构建代理请求（请求头、Token 与 Body 处理与 proxy_http_request 一致）
+fn build_proxy_request(
+    default_headers: &HashMap<String, String>,
+    options: RequestOptions,
+) -> Result<reqwest::RequestBuilder, String> {
+    // 创建客户端，默认 8 分钟请求超时、5 分钟连接超时，可按请求覆盖
+    let timeout_secs = options.timeout_secs.unwrap_or(480);
+    let connect_timeout_secs = options.connect_timeout_secs.unwrap_or(300);
+    let mut client_builder = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(timeout_secs))
+        .connect_timeout(std::time::Duration::from_secs(connect_timeout_secs));
+
+    // 自定义 User-Agent（部分服务商会根据 UA 拦截请求）
+    if let Some(user_agent) = options.user_agent.as_deref() {
+        if !user_agent.is_empty() {
+            client_builder = client_builder.user_agent(user_agent);
+        }
+    }
+
+    // 不跟随重定向时直接返回 3xx 响应，前端可读取 Location 头
+    if options.follow_redirects == Some(false) {
+        client_builder = client_builder.redirect(reqwest::redirect::Policy::none());
+    }
+
+    let client = client_builder.build().map_err(|e| e.to_string())?;
+
+    // 合并全局默认请求头
+    let headers = merge_headers(default_headers, options.headers.as_ref());
 
     // 构建请求
     let mut builder = match options.method.as_str() {
@@ -77,26 +578,28 @@ pub async fn proxy_http_request(options: RequestOptions) -> Result<ApiResponse,
         "POST" => client.post(&options.url),
         "PUT" => client.put(&options.url),
         "DELETE" => client.delete(&options.url),
+        "PATCH" => client.patch(&options.url),
+        "HEAD" => client.head(&options.url),
         _ => return Err(format!("不支持的请求方法: {}", options.method)),
     };
 
     // Check for multipart flag and add headers
-    let is_multipart = if let Some(ref headers) = options.headers {
-        headers
-            .get("x-use-multipart")
-            .or(headers.get("X-Use-Multipart"))
-            .map(|v| v == "true")
-            .unwrap_or(false)
-    } else {
-        false
-    };
-
-    // Add headers, skipping the multipart flag
-    if let Some(ref headers) = options.headers {
-        for (k, v) in headers.clone() {
-            if !k.to_lowercase().starts_with("x-use-multipart") {
-                builder = builder.header(k, v);
-            }
+    let is_multipart = headers
+        .get("x-use-multipart")
+        .or(headers.get("X-Use-Multipart"))
+        .map(|v| v == "true")
+        .unwrap_or(false);
+    let is_urlencoded = headers
+        .get("x-use-urlencoded")
+        .or(headers.get("X-Use-Urlencoded"))
+        .map(|v| v == "true")
+        .unwrap_or(false);
+
+    // Add headers, skipping the multipart / urlencoded flags
+    for (k, v) in headers {
+        let lower = k.to_lowercase();
+        if !lower.starts_with("x-use-multipart") && !lower.starts_with("x-use-urlencoded") {
+            builder = builder.header(k, v);
         }
     }
 
@@ -105,8 +608,8 @@ pub async fn proxy_http_request(options: RequestOptions) -> Result<ApiResponse,
         builder = builder.header("Authorization", format!("Bearer {}", token));
     }
 
-    // 添加 Body (如果存在且不是 GET)
-    if options.method != "GET" {
+    // 添加 Body (如果存在且不是 GET/HEAD)
+    if options.method != "GET" && options.method != "HEAD" {
         if let Some(body) = options.body {
             if is_multipart {
                 // Handle multipart/form-data
@@ -125,6 +628,22 @@ pub async fn proxy_http_request(options: RequestOptions) -> Result<ApiResponse,
                     // Fallback to JSON if not object
                     builder = builder.json(&body);
                 }
+            } else if is_urlencoded {
+                // Handle application/x-www-form-urlencoded
+                if let serde_json::Value::Object(map) = body {
+                    let fields: HashMap<String, String> = map
+                        .into_iter()
+                        .map(|(key, value)| match value {
+                            serde_json::Value::String(s) => (key, s),
+                            // For non-string values, convert to string
+                            other => (key, other.to_string()),
+                        })
+                        .collect();
+                    builder = builder.form(&fields);
+                } else {
+                    // Fallback to JSON if not object
+                    builder = builder.json(&body);
+                }
             } else {
                 // Default to JSON
                 builder = builder.json(&body);
@@ -132,1124 +651,9331 @@ pub async fn proxy_http_request(options: RequestOptions) -> Result<ApiResponse,
         }
     }
 
-    // 发送请求 (await)
-    let response = builder.send().await.map_err(|e| e.to_string())?;
-    let status = response.status().as_u16();
-
-    // 先尝试获取文本，然后再尝试 JSON 解析
-    let response_text = response.text().await.unwrap_or_default();
+    Ok(builder)
+}
 
-    // 尝试解析 JSON
-    let data: Value = match serde_json::from_str(&response_text) {
-        Ok(json) => json,
-        Err(_) => {
-            println!("[API] 非 JSON 响应: {}", response_text);
-            // 返回一个带有原始文本的 JSON 对象
-            serde_json::json!({ "raw_response": response_text, "status": status })
-        }
-    };
+// 流式响应分片事件
+#[derive(Debug, Clone, Serialize)]
+pub struct HttpStreamChunk {
+    pub stream_id: String,
+    pub data: String,
+}
 
-    Ok(ApiResponse { status, data })
+// 流式响应结束事件（error 为空表示正常结束）
+#[derive(Debug, Clone, Serialize)]
+pub struct HttpStreamEnd {
+    pub stream_id: String,
+    pub status: u16,
+    pub error: Option<String>,
 }
 
-// 检查更新指令 - Tauri v2 API
+// 流式代理请求（SSE 等）：每收到一段数据发送 http-stream-chunk 事件，结束时发送 http-stream-end
+// 请求头、Token 与 Body 处理与 proxy_http_request 一致，返回响应状态码
 #[command]
-pub async fn check_for_updates(app: tauri::AppHandle) -> Result<UpdateCheckResponse, String> {
-    // 使用 Tauri v2 内置的 updater API
-    let updater = app
-        .updater()
-        .map_err(|e| format!("获取更新器失败: {}", e))?;
-    let update = updater
-        .check()
-        .await
-        .map_err(|e| format!("检查更新失败: {}", e))?;
-
-    match update {
-        Some(update) => {
-            // date 是 Option<OffsetDateTime>
-            let date_str = update
-                .date
-                .map(|d| d.to_string())
-                .unwrap_or_else(|| String::from("unknown"));
-            Ok(UpdateCheckResponse {
-                should_update: true,
-                manifest: Some(UpdateManifest {
-                    version: update.version,
-                    body: update.body.unwrap_or_default(),
-                    date: date_str,
-                }),
-            })
-        }
-        None => Ok(UpdateCheckResponse {
-            should_update: false,
-            manifest: None,
-        }),
+pub async fn proxy_http_stream(
+    app: tauri::AppHandle,
+    default_headers: State<'_, DefaultHeaders>,
+    operations: State<'_, ActiveOperations>,
+    options: RequestOptions,
+    stream_id: String,
+) -> Result<u16, String> {
+    let defaults = default_headers.0.lock().map_err(|e| e.to_string())?.clone();
+    let mut status = 0;
+    let result = operations
+        .run(stream_proxy_response(
+            &app,
+            &defaults,
+            options,
+            &stream_id,
+            &mut status,
+        ))
+        .await;
+
+    let end = HttpStreamEnd {
+        stream_id: stream_id.clone(),
+        status,
+        error: result.as_ref().err().cloned(),
+    };
+    if let Err(e) = app.emit("http-stream-end", end) {
+        warn!("[API] 发送 http-stream-end 事件失败: {}", e);
     }
+    result.map(|_| status)
 }
 
-// 安装更新指令 - Tauri v2 API
-#[command]
-pub async fn install_update(app: tauri::AppHandle) -> Result<(), String> {
-    // 下载并安装更新
-    let updater = app
-        .updater()
-        .map_err(|e| format!("获取更新器失败: {}", e))?;
-    let result = updater
-        .check()
+async fn stream_proxy_response(
+    app: &tauri::AppHandle,
+    default_headers: &HashMap<String, String>,
+    options: RequestOptions,
+    stream_id: &str,
+    status: &mut u16,
+) -> Result<(), String> {
+    use futures_util::StreamExt;
+
+    let _permit = acquire_network_permit().await?;
+    let response = build_proxy_request(default_headers, options)?
+        .send()
         .await
-        .map_err(|e| format!("检查更新失败: {}", e))?;
-
-    if let Some(update) = result {
-        // 下载并安装更新，传入进度回调和重启回调
-        update
-            .download_and_install(
-                |_downloaded, _total| {
-                    // 进度回调，可以在这里更新 UI
-                },
-                || {
-                    // 更新完成后重启应用
-                    std::process::exit(0);
-                },
-            )
-            .await
-            .map_err(|e| format!("安装更新失败: {}", e))?;
+        .map_err(|e| e.to_string())?;
+    *status = response.status().as_u16();
+    info!("[API] 开始接收流式响应: {} ({})", stream_id, status);
+
+    // 分片可能截断多字节字符，未完整的尾部留到下一片再解码
+    let mut pending: Vec<u8> = Vec::new();
+    let mut stream = response.bytes_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| format!("读取流式响应失败: {}", e))?;
+        pending.extend_from_slice(&chunk);
+        let valid = match std::str::from_utf8(&pending) {
+            Ok(_) => pending.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            // 非法字节直接按有损方式解码，避免阻塞后续数据
+            Err(_) => pending.len(),
+        };
+        if valid == 0 {
+            continue;
+        }
+        let data = String::from_utf8_lossy(&pending[..valid]).into_owned();
+        pending.drain(..valid);
+        emit_stream_chunk(app, stream_id, data);
+    }
+    if !pending.is_empty() {
+        emit_stream_chunk(
+            app,
+            stream_id,
+            String::from_utf8_lossy(&pending).into_owned(),
+        );
     }
 
+    info!("[API] 流式响应结束: {}", stream_id);
     Ok(())
 }
 
-// 重启应用指令
-#[command]
-pub async fn relaunch_app() {
-    std::process::exit(0);
+fn emit_stream_chunk(app: &tauri::AppHandle, stream_id: &str, data: String) {
+    let event = HttpStreamChunk {
+        stream_id: stream_id.to_string(),
+        data,
+    };
+    if let Err(e) = app.emit("http-stream-chunk", event) {
+        warn!("[API] 发送 http-stream-chunk 事件失败: {}", e);
+    }
 }
 
-// 读取文件为 base64（用于前端上传）
-#[derive(Debug, Deserialize)]
-pub struct ReadFileBase64Options {
-    pub path: String,
+// 判断响应是否为文本类型（未声明 Content-Type 时按文本处理）
+fn is_text_content_type(content_type: Option<&str>) -> bool {
+    let Some(content_type) = content_type else {
+        return true;
+    };
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    mime.is_empty()
+        || mime.starts_with("text/")
+        || mime.ends_with("/json")
+        || mime.ends_with("+json")
+        || mime.ends_with("/xml")
+        || mime.ends_with("+xml")
+        || mime.ends_with("/javascript")
+        || mime == "application/x-www-form-urlencoded"
+        || mime == "application/x-ndjson"
 }
 
-#[command]
-pub async fn read_file_base64(options: ReadFileBase64Options) -> Result<String, String> {
-    let content = std::fs::read(&options.path).map_err(|e| format!("无法读取文件: {}", e))?;
+// 服务商预设：保存常用的基础地址、请求头与鉴权方式，供插件复用
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderPreset {
+    pub name: String,
+    pub base_url: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    pub auth_type: Option<String>, // "bearer" / "x-api-key" / "none"，默认 none
+    pub api_key: Option<String>,   // 鉴权使用的密钥
+}
 
-    Ok(base64::Engine::encode(
-        &base64::engine::general_purpose::STANDARD,
-        content,
-    ))
+fn provider_presets_path(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_config_dir()
+        .map(|dir| dir.join("provider_presets.json"))
+        .map_err(|e| format!("无法解析配置目录: {}", e))
 }
 
-// 启动本地 HTTP 服务器提供文件访问
-#[command]
-pub async fn start_file_server(path: String, port: u16) -> Result<String, String> {
-    use tokio::net::TcpListener;
+// 读取服务商预设，文件不存在时返回空列表
+fn load_provider_presets(app: &tauri::AppHandle) -> Result<Vec<ProviderPreset>, String> {
+    let path = provider_presets_path(app)?;
+    match std::fs::read_to_string(&path) {
+        Ok(content) => {
+            serde_json::from_str(&content).map_err(|e| format!("服务商预设文件格式错误: {}", e))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(format!("无法读取服务商预设: {}", e)),
+    }
+}
 
-    let addr = format!("127.0.0.1:{}", port);
+fn save_provider_presets(app: &tauri::AppHandle, presets: &[ProviderPreset]) -> Result<(), String> {
+    let path = provider_presets_path(app)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("无法创建配置目录: {}", e))?;
+    }
+    let content = serde_json::to_string_pretty(presets)
+        .map_err(|e| format!("无法序列化服务商预设: {}", e))?;
+    std::fs::write(&path, content).map_err(|e| format!("无法保存服务商预设: {}", e))
+}
 
-    // 创建一个简单的 HTTP 服务器
-    let listener = TcpListener::bind(&addr)
+// 创建或更新服务商预设（同名预设会被覆盖）
+#[command]
+pub fn save_provider_preset(app: tauri::AppHandle, preset: ProviderPreset) -> Result<(), String> {
+    let name = preset.name.trim().to_string();
+    if name.is_empty() {
+        return Err("预设名称不能为空".to_string());
+    }
+    let base_url = preset.base_url.trim().to_string();
+    reqwest::Url::parse(&base_url).map_err(|e| format!("无效的基础地址 {}: {}", base_url, e))?;
+    let auth_type = preset
+        .auth_type
+        .map(|t| t.trim().to_ascii_lowercase())
+        .filter(|t| !t.is_empty());
+    if let Some(auth_type) = auth_type.as_deref() {
+        if !matches!(auth_type, "bearer" | "x-api-key" | "none") {
+            return Err(format!("不支持的鉴权方式: {}", auth_type));
+        }
+    }
+
+    let preset = ProviderPreset {
+        name: name.clone(),
+        base_url,
+        headers: preset.headers,
+        auth_type,
+        api_key: preset.api_key.filter(|key| !key.is_empty()),
+    };
+
+    let mut presets = load_provider_presets(&app)?;
+    match presets.iter_mut().find(|p| p.name == name) {
+        Some(existing) => *existing = preset,
+        None => presets.push(preset),
+    }
+    save_provider_presets(&app, &presets)?;
+
+    info!("[Preset] 已保存服务商预设: {}", name);
+    Ok(())
+}
+
+// 获取所有服务商预设
+#[command]
+pub fn get_provider_presets(app: tauri::AppHandle) -> Result<Vec<ProviderPreset>, String> {
+    load_provider_presets(&app)
+}
+
+// 拼接基础地址与路径，路径为完整 URL 时直接使用
+fn join_preset_url(base_url: &str, path: &str) -> String {
+    if path.starts_with("http://") || path.starts_with("https://") {
+        return path.to_string();
+    }
+    match (base_url.ends_with('/'), path.starts_with('/')) {
+        (true, true) => format!("{}{}", base_url, &path[1..]),
+        (false, false) if !path.is_empty() => format!("{}/{}", base_url, path),
+        _ => format!("{}{}", base_url, path),
+    }
+}
+
+// 使用服务商预设发送代理请求：拼接地址、合并预设请求头并按鉴权方式附加密钥
+#[command]
+pub async fn proxy_with_preset(
+    app: tauri::AppHandle,
+    default_headers: State<'_, DefaultHeaders>,
+    operations: State<'_, ActiveOperations>,
+    preset_name: String,
+    path: String,
+    method: String,
+    body: Option<Value>,
+) -> Result<ApiResponse, String> {
+    let preset = load_provider_presets(&app)?
+        .into_iter()
+        .find(|p| p.name == preset_name)
+        .ok_or_else(|| format!("服务商预设不存在: {}", preset_name))?;
+
+    let mut headers = preset.headers.clone();
+    let mut token = None;
+    if let Some(api_key) = preset.api_key.clone() {
+        match preset.auth_type.as_deref() {
+            Some("bearer") => token = Some(api_key),
+            Some("x-api-key") => {
+                headers.retain(|k, _| !k.eq_ignore_ascii_case("x-api-key"));
+                headers.insert("x-api-key".to_string(), api_key);
+            }
+            _ => {}
+        }
+    }
+
+    let options = RequestOptions {
+        method: method.to_ascii_uppercase(),
+        url: join_preset_url(&preset.base_url, &path),
+        headers: Some(headers),
+        body,
+        token,
+        user_agent: None,
+        timeout_secs: None,
+        connect_timeout_secs: None,
+        follow_redirects: None,
+    };
+    debug!(
+        "[Preset] 使用预设 {} 请求: {} {}",
+        preset.name, options.method, options.url
+    );
+
+    let defaults = default_headers.0.lock().map_err(|e| e.to_string())?.clone();
+    operations.run(send_proxy_request(&defaults, options)).await
+}
+
+// 按点分路径读取 JSON 字段，数字段用于数组下标，如 "data.items.0.status"
+fn json_path_get<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .filter(|segment| !segment.is_empty())
+        .try_fold(value, |current, segment| match current {
+            Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => current.get(segment),
+        })
+}
+
+// 轮询进度事件
+#[derive(Debug, Clone, Serialize)]
+pub struct PollTickEvent {
+    pub url: String,
+    pub attempt: u32,
+    pub status: Option<u16>,
+    pub elapsed_ms: u64,
+}
+
+// 轮询间隔：从 1 秒开始指数退避，最长 30 秒
+const POLL_INITIAL_DELAY_MS: u64 = 1000;
+const POLL_MAX_DELAY_MS: u64 = 30_000;
+
+// 反复发送请求直到 done_json_path 处的值等于 done_value，或超过 max_wait_secs
+#[command]
+pub async fn poll_until(
+    app: tauri::AppHandle,
+    default_headers: State<'_, DefaultHeaders>,
+    operations: State<'_, ActiveOperations>,
+    options: RequestOptions,
+    done_json_path: String,
+    done_value: Value,
+    max_wait_secs: u64,
+) -> Result<ApiResponse, String> {
+    let defaults = default_headers.0.lock().map_err(|e| e.to_string())?.clone();
+    let started = std::time::Instant::now();
+    let max_wait = std::time::Duration::from_secs(max_wait_secs);
+    let mut delay_ms = POLL_INITIAL_DELAY_MS;
+    let mut attempt: u32 = 0;
+
+    info!(
+        "[Poll] 开始轮询: {} (路径 {} == {})",
+        options.url, done_json_path, done_value
+    );
+
+    operations
+        .run(async {
+            loop {
+                attempt += 1;
+                let result = send_proxy_request(&defaults, options.clone()).await;
+
+                let tick = PollTickEvent {
+                    url: options.url.clone(),
+                    attempt,
+                    status: result.as_ref().ok().map(|r| r.status),
+                    elapsed_ms: started.elapsed().as_millis() as u64,
+                };
+                if let Err(e) = app.emit("poll-tick", tick) {
+                    warn!("[Poll] 发送 poll-tick 事件失败: {}", e);
+                }
+
+                match result {
+                    Ok(response) => {
+                        if json_path_get(&response.data, &done_json_path) == Some(&done_value) {
+                            info!("[Poll] 轮询完成: 第 {} 次请求", attempt);
+                            return Ok(response);
+                        }
+                    }
+                    // 单次请求失败不终止轮询，直到超时
+                    Err(e) => warn!("[Poll] 第 {} 次请求失败: {}", attempt, e),
+                }
+
+                let elapsed = started.elapsed();
+                if elapsed >= max_wait {
+                    return Err(format!(
+                        "轮询超时: {} 秒内未满足完成条件 (共请求 {} 次)",
+                        max_wait_secs, attempt
+                    ));
+                }
+
+                let remaining = max_wait - elapsed;
+                let delay = std::time::Duration::from_millis(delay_ms).min(remaining);
+                tokio::time::sleep(delay).await;
+                delay_ms = (delay_ms * 2).min(POLL_MAX_DELAY_MS);
+            }
+        })
+        .await
+}
+
+// 根据 updater 错误类型归类检查结果
+fn classify_update_error(error: &tauri_plugin_updater::Error) -> UpdateCheckStatus {
+    use tauri_plugin_updater::Error as UpdaterError;
+
+    match error {
+        UpdaterError::EmptyEndpoints | UpdaterError::InsecureTransportProtocol => {
+            UpdateCheckStatus::NoUpdater
+        }
+        UpdaterError::Reqwest(_)
+        | UpdaterError::Network(_)
+        | UpdaterError::Http(_)
+        | UpdaterError::Io(_) => UpdateCheckStatus::NetworkError,
+        UpdaterError::ReleaseNotFound => UpdateCheckStatus::ReleaseNotFound,
+        UpdaterError::Minisign(_) | UpdaterError::Base64(_) | UpdaterError::SignatureUtf8(_) => {
+            UpdateCheckStatus::SignatureError
+        }
+        _ => UpdateCheckStatus::Unknown,
+    }
+}
+
+// 构造失败的更新检查响应
+fn update_check_failure(context: &str, error: tauri_plugin_updater::Error) -> UpdateCheckResponse {
+    let status = classify_update_error(&error);
+    warn!("[Updater] {} ({:?}): {}", context, status, error);
+    UpdateCheckResponse {
+        status,
+        should_update: false,
+        manifest: None,
+        error: Some(format!("{}: {}", context, error)),
+    }
+}
+
+// 检查更新指令 - Tauri v2 API
+// 错误不会以 Err 返回，而是通过 status 区分，便于前端展示对应提示
+#[command]
+pub async fn check_for_updates(app: tauri::AppHandle) -> Result<UpdateCheckResponse, String> {
+    emit_update_status(&app, UpdateStage::Checking, 0, None);
+    let response = check_for_updates_impl(&app).await;
+
+    // 每条退出路径都发送结束阶段，便于前端关闭加载状态
+    let stage = match response.status {
+        UpdateCheckStatus::UpdateAvailable => UpdateStage::Available,
+        UpdateCheckStatus::UpToDate | UpdateCheckStatus::ReleaseNotFound => UpdateStage::UpToDate,
+        _ => UpdateStage::Error,
+    };
+    emit_update_status(&app, stage, 0, None);
+    Ok(response)
+}
+
+async fn check_for_updates_impl(app: &tauri::AppHandle) -> UpdateCheckResponse {
+    // 使用 Tauri v2 内置的 updater API
+    let updater = match app.updater() {
+        Ok(updater) => updater,
+        Err(e) => return update_check_failure("获取更新器失败", e),
+    };
+    let update = match updater.check().await {
+        Ok(update) => update,
+        Err(e) => return update_check_failure("检查更新失败", e),
+    };
+
+    match update {
+        Some(update) => {
+            // date 是 Option<OffsetDateTime>
+            let date_str = update
+                .date
+                .map(|d| d.to_string())
+                .unwrap_or_else(|| String::from("unknown"));
+            UpdateCheckResponse {
+                status: UpdateCheckStatus::UpdateAvailable,
+                should_update: true,
+                manifest: Some(UpdateManifest {
+                    version: update.version,
+                    body: update.body.unwrap_or_default(),
+                    date: date_str,
+                }),
+                error: None,
+            }
+        }
+        None => UpdateCheckResponse {
+            status: UpdateCheckStatus::UpToDate,
+            should_update: false,
+            manifest: None,
+            error: None,
+        },
+    }
+}
+
+// 更新流程阶段
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateStage {
+    Checking,    // 正在检查更新
+    Downloading, // 正在下载更新包
+    Installing,  // 正在安装
+    Ready,       // 更新包已下载并校验，等待安装
+    Available,   // 检查完成，有可用更新
+    UpToDate,    // 检查完成，已是最新版本
+    Error,       // 检查、下载或安装失败
+}
+
+// update-status 事件
+#[derive(Debug, Clone, Serialize)]
+pub struct UpdateStatusEvent {
+    pub stage: UpdateStage,
+    pub downloaded: u64,
+    pub total: Option<u64>,
+}
+
+// 已下载、等待安装的更新包
+#[derive(Default)]
+pub struct PendingUpdate(Mutex<Option<(tauri_plugin_updater::Update, Vec<u8>)>>);
+
+fn emit_update_status(
+    app: &tauri::AppHandle,
+    stage: UpdateStage,
+    downloaded: u64,
+    total: Option<u64>,
+) {
+    let event = UpdateStatusEvent {
+        stage,
+        downloaded,
+        total,
+    };
+    if let Err(e) = app.emit("update-status", event) {
+        warn!("[Updater] 发送 update-status 事件失败: {}", e);
+    }
+}
+
+// 下载更新包（不安装），下载过程中发送 update-status 进度，返回是否有可用更新
+#[command]
+pub async fn download_update(
+    app: tauri::AppHandle,
+    pending: State<'_, PendingUpdate>,
+) -> Result<bool, String> {
+    emit_update_status(&app, UpdateStage::Checking, 0, None);
+    let result = download_update_impl(&app, &pending).await;
+    // 成功下载时已发送 ready，其余退出路径在此发送结束阶段
+    match &result {
+        Ok(true) => {}
+        Ok(false) => emit_update_status(&app, UpdateStage::UpToDate, 0, None),
+        Err(e) => {
+            warn!("[Updater] {}", e);
+            emit_update_status(&app, UpdateStage::Error, 0, None);
+        }
+    }
+    result
+}
+
+async fn download_update_impl(
+    app: &tauri::AppHandle,
+    pending: &PendingUpdate,
+) -> Result<bool, String> {
+    let updater = app
+        .updater()
+        .map_err(|e| format!("获取更新器失败: {}", e))?;
+    let Some(update) = updater
+        .check()
+        .await
+        .map_err(|e| format!("检查更新失败: {}", e))?
+    else {
+        return Ok(false);
+    };
+
+    info!("[Updater] 开始下载更新: {}", update.version);
+    emit_update_status(app, UpdateStage::Downloading, 0, None);
+    let mut downloaded: u64 = 0;
+    let mut last_emit = std::time::Instant::now();
+    let bytes = update
+        .download(
+            |chunk_len, total| {
+                downloaded += chunk_len as u64;
+                // 限制事件频率，避免大量小块时刷屏
+                if last_emit.elapsed() >= std::time::Duration::from_millis(200) {
+                    last_emit = std::time::Instant::now();
+                    emit_update_status(app, UpdateStage::Downloading, downloaded, total);
+                }
+            },
+            || {},
+        )
+        .await
+        .map_err(|e| format!("下载更新失败: {}", e))?;
+
+    let size = bytes.len() as u64;
+    *pending.0.lock().map_err(|e| e.to_string())? = Some((update, bytes));
+    emit_update_status(app, UpdateStage::Ready, size, Some(size));
+    info!("[Updater] 更新包已就绪: {} bytes", size);
+    Ok(true)
+}
+
+// 安装已下载的更新包并退出应用
+#[command]
+pub async fn apply_update(
+    app: tauri::AppHandle,
+    pending: State<'_, PendingUpdate>,
+    shutdown: State<'_, ShutdownCoordinator>,
+) -> Result<(), String> {
+    let (update, bytes) = pending
+        .0
+        .lock()
+        .map_err(|e| e.to_string())?
+        .take()
+        .ok_or("没有已下载的更新，请先下载更新")?;
+
+    let size = bytes.len() as u64;
+    emit_update_status(&app, UpdateStage::Installing, size, Some(size));
+
+    // 安装程序可能直接结束当前进程，安装前先等待进行中的写入完成
+    shutdown.drain().await;
+    if let Err(e) = update.install(bytes) {
+        // 安装失败时应用继续运行，恢复写入
+        shutdown.cancel();
+        emit_update_status(&app, UpdateStage::Error, size, Some(size));
+        return Err(format!("安装更新失败: {}", e));
+    }
+
+    // 更新完成后退出应用
+    info!("[Updater] 更新安装完成，退出应用");
+    app.exit(0);
+    Ok(())
+}
+
+// 安装更新指令 - Tauri v2 API（下载并立即安装）
+#[command]
+pub async fn install_update(
+    app: tauri::AppHandle,
+    pending: State<'_, PendingUpdate>,
+    shutdown: State<'_, ShutdownCoordinator>,
+) -> Result<(), String> {
+    if download_update(app.clone(), pending.clone()).await? {
+        apply_update(app, pending, shutdown).await?;
+    }
+    Ok(())
+}
+
+// 等待写入完成的最长时间，超时后仍然退出
+const SHUTDOWN_WRITE_TIMEOUT_SECS: u64 = 15;
+
+// 退出协调：登记进行中的文件写入，退出前等待它们完成，避免留下损坏的文件
+#[derive(Default)]
+pub struct ShutdownCoordinator {
+    in_flight: std::sync::atomic::AtomicUsize,
+    shutting_down: std::sync::atomic::AtomicBool,
+    idle: tokio::sync::Notify,
+}
+
+// 进行中的写入，离开作用域时注销
+pub struct PendingWrite<'a>(&'a ShutdownCoordinator);
+
+impl Drop for PendingWrite<'_> {
+    fn drop(&mut self) {
+        self.0
+            .in_flight
+            .fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+        self.0.idle.notify_waiters();
+    }
+}
+
+impl ShutdownCoordinator {
+    // 登记一次写入，正在退出时拒绝新的写入
+    fn track_write(&self) -> Result<PendingWrite<'_>, String> {
+        use std::sync::atomic::Ordering;
+
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let pending = PendingWrite(self);
+        if self.shutting_down.load(Ordering::SeqCst) {
+            return Err("应用正在退出，已拒绝新的写入".to_string());
+        }
+        Ok(pending)
+    }
+
+    // 进入退出状态并等待进行中的写入完成，超时返回 false
+    async fn drain(&self) -> bool {
+        use std::sync::atomic::Ordering;
+
+        self.shutting_down.store(true, Ordering::SeqCst);
+        let wait_idle = async {
+            loop {
+                // 先登记通知再检查计数，避免错过写入结束的通知
+                let notified = self.idle.notified();
+                tokio::pin!(notified);
+                notified.as_mut().enable();
+                if self.in_flight.load(Ordering::SeqCst) == 0 {
+                    return;
+                }
+                notified.await;
+            }
+        };
+
+        let pending = self.in_flight.load(Ordering::SeqCst);
+        if pending > 0 {
+            info!("[Shutdown] 等待 {} 个写入完成后退出", pending);
+        }
+        let timeout = std::time::Duration::from_secs(SHUTDOWN_WRITE_TIMEOUT_SECS);
+        let drained = tokio::time::timeout(timeout, wait_idle).await.is_ok();
+        if !drained {
+            warn!(
+                "[Shutdown] 等待写入超时，仍有 {} 个写入未完成",
+                self.in_flight.load(Ordering::SeqCst)
+            );
+        }
+        drained
+    }
+
+    // 取消退出状态（退出未能完成时调用），重新允许写入
+    fn cancel(&self) {
+        if self
+            .shutting_down
+            .swap(false, std::sync::atomic::Ordering::SeqCst)
+        {
+            info!("[Shutdown] 退出已取消，恢复写入");
+        }
+    }
+}
+
+// 处理窗口关闭等触发的退出请求：有进行中的写入时返回 true，由调用方阻止本次退出，
+// 写入完成（或超时）后再次退出
+pub fn defer_exit_for_pending_writes(app: &tauri::AppHandle) -> bool {
+    use std::sync::atomic::Ordering;
+
+    let shutdown = app.state::<ShutdownCoordinator>();
+    // 已经在退出流程中（等待过写入）时直接放行
+    if shutdown.shutting_down.load(Ordering::SeqCst) {
+        return false;
+    }
+    // 没有进行中的写入时不进入退出状态，退出若被其他处理取消也不会拒绝后续写入
+    if shutdown.in_flight.load(Ordering::SeqCst) == 0 {
+        return false;
+    }
+    shutdown.shutting_down.store(true, Ordering::SeqCst);
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        app.state::<ShutdownCoordinator>().drain().await;
+        app.exit(0);
+    });
+    true
+}
+
+// 请求退出应用：等待进行中的写入完成（最多 SHUTDOWN_WRITE_TIMEOUT_SECS 秒）后退出
+#[command]
+pub async fn request_shutdown(
+    app: tauri::AppHandle,
+    shutdown: State<'_, ShutdownCoordinator>,
+) -> Result<(), String> {
+    shutdown.drain().await;
+    info!("[Shutdown] 应用退出");
+    app.exit(0);
+    Ok(())
+}
+
+// 重启应用指令
+#[command]
+pub async fn relaunch_app(
+    app: tauri::AppHandle,
+    shutdown: State<'_, ShutdownCoordinator>,
+) -> Result<(), String> {
+    request_shutdown(app, shutdown).await
+}
+
+// 版本信息（用于关于页面和问题反馈）
+#[derive(Debug, Serialize)]
+pub struct VersionInfo {
+    pub app_version: String,
+    pub tauri_version: String,
+    pub webview_version: Option<String>,
+    pub build_target: String,
+}
+
+// 获取应用、Tauri 和 WebView 的实际版本
+#[command]
+pub fn get_version_info(app: tauri::AppHandle) -> Result<VersionInfo, String> {
+    let webview_version = match tauri::webview_version() {
+        Ok(version) => Some(version),
+        Err(e) => {
+            warn!("[VersionInfo] 获取 WebView 版本失败: {}", e);
+            None
+        }
+    };
+
+    Ok(VersionInfo {
+        app_version: app.package_info().version.to_string(),
+        tauri_version: tauri::VERSION.to_string(),
+        webview_version,
+        build_target: format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS),
+    })
+}
+
+// 运行时调整日志级别：off / error / warn / info / debug / trace
+#[command]
+pub fn set_log_level(level: String) -> Result<String, String> {
+    let filter: log::LevelFilter = level
+        .trim()
+        .parse()
+        .map_err(|_| format!("无效的日志级别: {}", level))?;
+    log::set_max_level(filter);
+    info!("[Log] 日志级别已设置为 {}", filter);
+    Ok(filter.to_string())
+}
+
+// 读取文件为 base64（用于前端上传）
+#[derive(Debug, Deserialize)]
+pub struct ReadFileBase64Options {
+    pub path: String,
+}
+
+#[command]
+pub async fn read_file_base64(options: ReadFileBase64Options) -> Result<String, String> {
+    let content = std::fs::read(&options.path).map_err(|e| format!("无法读取文件: {}", e))?;
+
+    Ok(base64::Engine::encode(
+        &base64::engine::general_purpose::STANDARD,
+        content,
+    ))
+}
+
+// 读取文本文件末尾的若干行（用于查看日志），从文件尾部分块向前扫描，无需读取整个文件
+#[command]
+pub fn read_file_tail(path: String, lines: usize) -> Result<Vec<String>, String> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    const CHUNK_SIZE: u64 = 8192;
+
+    if lines == 0 {
+        return Ok(Vec::new());
+    }
+
+    let mut file = std::fs::File::open(&path).map_err(|e| format!("无法打开文件: {}", e))?;
+    let file_len = file
+        .metadata()
+        .map_err(|e| format!("无法读取文件信息: {}", e))?
+        .len();
+
+    // 从末尾向前读取，直到收集到足够的换行符或到达文件开头
+    let mut tail: Vec<u8> = Vec::new();
+    let mut position = file_len;
+    let mut newline_count = 0;
+    while position > 0 && newline_count <= lines {
+        let read_len = CHUNK_SIZE.min(position);
+        position -= read_len;
+
+        let mut chunk = vec![0u8; read_len as usize];
+        file.seek(SeekFrom::Start(position))
+            .and_then(|_| file.read_exact(&mut chunk))
+            .map_err(|e| format!("无法读取文件: {}", e))?;
+
+        // 文件末尾的换行符不算作新的一行
+        let counted = if position + read_len == file_len {
+            chunk.strip_suffix(b"\n").unwrap_or(&chunk)
+        } else {
+            &chunk[..]
+        };
+        newline_count += counted.iter().filter(|&&b| b == b'\n').count();
+
+        chunk.extend_from_slice(&tail);
+        tail = chunk;
+    }
+
+    if tail.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let text = String::from_utf8_lossy(&tail);
+    let all_lines: Vec<&str> = text
+        .strip_suffix('\n')
+        .unwrap_or(&text)
+        .split('\n')
+        .collect();
+    let skip = all_lines.len().saturating_sub(lines);
+
+    Ok(all_lines[skip..]
+        .iter()
+        .map(|line| line.strip_suffix('\r').unwrap_or(line).to_string())
+        .collect())
+}
+
+// 根据扩展名推断 MIME 类型（上传与文件服务器共用）
+fn guess_mime(ext: &str) -> Option<&'static str> {
+    let mime = match ext.to_lowercase().as_str() {
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "mov" => "video/quicktime",
+        "avi" => "video/x-msvideo",
+        "mkv" => "video/x-matroska",
+        "flv" => "video/x-flv",
+        "png" => "image/png",
+        "apng" => "image/apng",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        "avif" => "image/avif",
+        "heic" => "image/heic",
+        "heif" => "image/heif",
+        "svg" => "image/svg+xml",
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "m4a" => "audio/mp4",
+        "ogg" => "audio/ogg",
+        "json" => "application/json",
+        "txt" => "text/plain",
+        _ => return None,
+    };
+    Some(mime)
+}
+
+// 根据文件头（magic bytes）识别 MIME 类型
+fn sniff_mime(content: &[u8]) -> Option<&'static str> {
+    infer::get(content).map(|kind| kind.mime_type())
+}
+
+// 读取请求行中的查询参数开关（如 ?download=1），未出现时返回 None
+fn query_flag(request: &str, name: &str) -> Option<bool> {
+    let target = request.lines().next()?.split_whitespace().nth(1)?;
+    let (_, query) = target.split_once('?')?;
+    query.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        (key == name).then_some(!matches!(value, "0" | "false" | "no"))
+    })
+}
+
+// 解码 URL 中的 %XX 转义，结果不是合法 UTF-8 时返回 None
+fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = input.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+// 对 URL 路径做 %XX 编码（保留 / 与非保留字符）
+fn percent_encode_path(input: &str) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for &byte in input.as_bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~' | b'/') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+// 将请求路径映射到根目录下的文件
+// 包含 .. 或越出根目录（含符号链接）时返回 403，文件不存在时返回 404
+fn resolve_served_path(root: &std::path::Path, request: &str) -> Result<PathBuf, u16> {
+    let target = request
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .ok_or(400u16)?;
+    let raw_path = target.split(['?', '#']).next().unwrap_or("");
+    let decoded = percent_decode(raw_path).ok_or(400u16)?;
+
+    let mut path = root.to_path_buf();
+    for segment in decoded.split('/').filter(|s| !s.is_empty() && *s != ".") {
+        if segment == ".." || segment.contains(['\\', ':']) {
+            return Err(403);
+        }
+        path.push(segment);
+    }
+
+    let resolved = path.canonicalize().map_err(|_| 404u16)?;
+    if !resolved.starts_with(root) {
+        return Err(403);
+    }
+    if !resolved.is_file() {
+        return Err(404);
+    }
+    Ok(resolved)
+}
+
+// 生成 Content-Disposition 头：ASCII 回退文件名 + RFC 5987 编码的 UTF-8 文件名
+fn content_disposition(file_path: &std::path::Path, attachment: bool) -> String {
+    let disposition = if attachment { "attachment" } else { "inline" };
+    let file_name = file_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("download");
+
+    let fallback: String = file_name
+        .chars()
+        .map(|c| {
+            if (c.is_ascii_graphic() || c == ' ') && c != '"' && c != '\\' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let encoded: String = file_name
+        .bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) {
+                (b as char).to_string()
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect();
+
+    format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        disposition, fallback, encoded
+    )
+}
+
+// 文件服务器默认允许的来源：各平台的 webview 来源与开发服务器
+const DEFAULT_CORS_ORIGINS: &[&str] = &[
+    "tauri://localhost",
+    "http://tauri.localhost",
+    "https://tauri.localhost",
+    "http://localhost:1420",
+];
+
+// 生成 CORS 响应头：请求来源在允许列表中时回显该来源，列表包含 "*" 时允许任意来源
+// 同时暴露 Range 播放所需的响应头并允许 Range 请求头
+fn cors_headers(request: &str, allowed_origins: &[String]) -> String {
+    let origin = request.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("origin")
+            .then(|| value.trim())
+    });
+
+    let mut headers = String::new();
+    if allowed_origins.iter().any(|o| o == "*") {
+        headers.push_str("Access-Control-Allow-Origin: *\r\n");
+    } else if let Some(origin) = origin.filter(|o| allowed_origins.iter().any(|a| a == o)) {
+        headers.push_str(&format!(
+            "Access-Control-Allow-Origin: {}\r\nVary: Origin\r\n",
+            origin
+        ));
+    }
+    headers.push_str(
+        "Access-Control-Allow-Methods: GET, HEAD, OPTIONS\r\n\
+         Access-Control-Allow-Headers: Range\r\n\
+         Access-Control-Expose-Headers: Content-Range, Content-Length, Accept-Ranges, Content-Disposition\r\n",
+    );
+    headers
+}
+
+// 运行中的文件服务器
+struct FileServerEntry {
+    path: String,
+    shutdown: CancellationToken,
+}
+
+// 按端口登记的文件服务器
+#[derive(Default)]
+pub struct FileServers(Mutex<HashMap<u16, FileServerEntry>>);
+
+impl FileServers {
+    fn servers(&self) -> std::sync::MutexGuard<'_, HashMap<u16, FileServerEntry>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// 文件服务器信息
+#[derive(Debug, Serialize)]
+pub struct FileServerInfo {
+    pub port: u16,
+    pub path: String,
+}
+
+// 列出所有运行中的文件服务器
+#[command]
+pub fn list_file_servers(servers: State<'_, FileServers>) -> Vec<FileServerInfo> {
+    let mut list: Vec<FileServerInfo> = servers
+        .servers()
+        .iter()
+        .map(|(port, entry)| FileServerInfo {
+            port: *port,
+            path: entry.path.clone(),
+        })
+        .collect();
+    list.sort_by_key(|info| info.port);
+    list
+}
+
+// 关闭指定端口上的文件服务器
+#[command]
+pub fn stop_file_server(servers: State<'_, FileServers>, port: u16) -> Result<(), String> {
+    let entry = servers
+        .servers()
+        .remove(&port)
+        .ok_or_else(|| format!("端口 {} 上没有运行中的文件服务器", port))?;
+    entry.shutdown.cancel();
+    info!("[FileServer] 正在关闭端口 {} 上的文件服务器", port);
+    Ok(())
+}
+
+// 关闭所有文件服务器，返回关闭的数量
+#[command]
+pub fn stop_all_file_servers(servers: State<'_, FileServers>) -> usize {
+    let stopped: Vec<(u16, FileServerEntry)> = servers.servers().drain().collect();
+    for (_, entry) in &stopped {
+        entry.shutdown.cancel();
+    }
+    info!("[FileServer] 已关闭 {} 个文件服务器", stopped.len());
+    stopped.len()
+}
+
+// 启动本地 HTTP 服务器提供 root_dir 目录下的文件访问（请求路径相对于 root_dir）
+// attachment 为默认的下载方式（attachment/inline），请求中的 ?download=1/0 可覆盖
+// allowed_origins 为允许跨域访问的来源（默认为 webview 来源），传入 ["*"] 允许任意来源
+#[command]
+pub async fn start_file_server(
+    servers: State<'_, FileServers>,
+    root_dir: String,
+    port: u16,
+    attachment: Option<bool>,
+    allowed_origins: Option<Vec<String>>,
+) -> Result<String, String> {
+    use tokio::net::TcpListener;
+
+    let root = PathBuf::from(&root_dir)
+        .canonicalize()
+        .map_err(|e| format!("目录不存在: {} ({})", root_dir, e))?;
+    if !root.is_dir() {
+        return Err(format!("不是目录: {}", root_dir));
+    }
+
+    let addr = format!("127.0.0.1:{}", port);
+
+    // 创建一个简单的 HTTP 服务器
+    let listener = TcpListener::bind(&addr)
+        .await
+        .map_err(|e| format!("无法启动服务器: {}", e))?;
+    let port = listener.local_addr().map(|a| a.port()).unwrap_or(port);
+
+    info!("[FileServer] 已在 127.0.0.1:{} 启动文件服务器", port);
+
+    // 登记服务器，便于列出和统一关闭
+    let shutdown = CancellationToken::new();
+    servers.servers().insert(
+        port,
+        FileServerEntry {
+            path: root_dir.clone(),
+            shutdown: shutdown.clone(),
+        },
+    );
+
+    let allowed_origins = std::sync::Arc::new(allowed_origins.unwrap_or_else(|| {
+        DEFAULT_CORS_ORIGINS
+            .iter()
+            .map(|origin| origin.to_string())
+            .collect()
+    }));
+
+    // 在后台任务中处理请求
+    let root = std::sync::Arc::new(root);
+    tokio::spawn(async move {
+        loop {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = shutdown.cancelled() => {
+                    info!("[FileServer] 已关闭端口 {} 上的文件服务器", port);
+                    break;
+                }
+            };
+            if let Ok((mut stream, addr)) = accepted {
+                debug!("[FileServer] 收到来自 {} 的请求", addr);
+
+                let root = root.clone();
+                let default_attachment = attachment.unwrap_or(false);
+                let allowed_origins = allowed_origins.clone();
+                tokio::spawn(async move {
+                    let mut buffer = [0u8; 1024];
+                    if let Ok(n) = stream.read(&mut buffer).await {
+                        if n > 0 {
+                            let request = String::from_utf8_lossy(&buffer[..n]);
+                            debug!(
+                                "[FileServer] 请求内容: {}",
+                                request.lines().next().unwrap_or("")
+                            );
+
+                            let cors = cors_headers(&request, &allowed_origins);
+
+                            // 预检请求只返回 CORS 头
+                            if request.starts_with("OPTIONS ") {
+                                let response = format!(
+                                    "HTTP/1.1 204 No Content\r\n{}Access-Control-Max-Age: 600\r\nContent-Length: 0\r\n\r\n",
+                                    cors
+                                );
+                                let _ = stream.write_all(response.as_bytes()).await;
+                                return;
+                            }
+
+                            let file_path = match resolve_served_path(&root, &request) {
+                                Ok(file_path) => file_path,
+                                Err(status) => {
+                                    let reason = match status {
+                                        400 => "Bad Request",
+                                        403 => "Forbidden",
+                                        _ => "Not Found",
+                                    };
+                                    debug!("[FileServer] 拒绝请求: {} {}", status, reason);
+                                    let response = format!(
+                                        "HTTP/1.1 {} {}\r\n{}Content-Length: 0\r\n\r\n",
+                                        status, reason, cors
+                                    );
+                                    let _ = stream.write_all(response.as_bytes()).await;
+                                    return;
+                                }
+                            };
+
+                            // Range 请求按区间返回（用于视频拖动），HEAD 只返回响应头，
+                            // 其余分块读取文件并返回（客户端断开时直接结束）
+                            let attachment =
+                                query_flag(&request, "download").unwrap_or(default_attachment);
+                            let head_only = request.starts_with("HEAD ");
+                            let result = if !head_only && parse_range_header(&request).is_some() {
+                                let file_len =
+                                    tokio::fs::metadata(&file_path).await.map(|m| m.len()).ok();
+                                respond_with_partial_file(
+                                    &mut stream,
+                                    &request,
+                                    &file_path,
+                                    file_len,
+                                    attachment,
+                                    &cors,
+                                )
+                                .await
+                            } else {
+                                respond_with_file(
+                                    &mut stream,
+                                    &file_path,
+                                    attachment,
+                                    head_only,
+                                    &cors,
+                                )
+                                .await
+                            };
+                            if let Err(e) = result {
+                                debug!("[FileServer] 发送文件中断 {}: {}", file_path.display(), e);
+                            }
+                        }
+                    }
+                });
+            }
+        }
+    });
+
+    Ok(format!("http://127.0.0.1:{}", port))
+}
+
+// 文件服务器每次读取和发送的块大小
+const FILE_SERVER_CHUNK_SIZE: usize = 64 * 1024;
+
+// 以固定大小的块发送整个文件，避免大视频整体读入内存；head_only 时只发送响应头
+async fn respond_with_file(
+    stream: &mut tokio::net::TcpStream,
+    file_path: &std::path::Path,
+    attachment: bool,
+    head_only: bool,
+    cors: &str,
+) -> std::io::Result<()> {
+    let mut file = match tokio::fs::File::open(file_path).await {
+        Ok(file) => file,
+        Err(_) => {
+            let response = format!(
+                "HTTP/1.1 404 Not Found\r\n{}Content-Length: 0\r\n\r\n",
+                cors
+            );
+            return stream.write_all(response.as_bytes()).await;
+        }
+    };
+    let length = file.metadata().await?.len();
+
+    // 读取第一块，扩展名无法识别时据此判断类型
+    let mut buffer = vec![0u8; FILE_SERVER_CHUNK_SIZE];
+    let mut filled = 0;
+    while filled < buffer.len() {
+        let n = file.read(&mut buffer[filled..]).await?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+
+    let content_type = file_path
+        .extension()
+        .and_then(|e| e.to_str())
+        .and_then(guess_mime)
+        .or_else(|| sniff_mime(&buffer[..filled]))
+        .unwrap_or("application/octet-stream");
+    let header = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\nContent-Disposition: {}\r\n{}\r\n",
+        content_type,
+        length,
+        content_disposition(file_path, attachment),
+        cors
+    );
+    stream.write_all(header.as_bytes()).await?;
+    if head_only {
+        return stream.flush().await;
+    }
+
+    // 只发送 Content-Length 声明的字节数，文件在发送期间变长也不会超出
+    let mut remaining = length;
+    let mut chunk_len = filled;
+    while remaining > 0 && chunk_len > 0 {
+        let send = chunk_len.min(remaining as usize);
+        stream.write_all(&buffer[..send]).await?;
+        remaining -= send as u64;
+        chunk_len = file.read(&mut buffer).await?;
+    }
+    stream.flush().await
+}
+
+// 解析 Range 请求头（仅支持单个区间）：bytes=start-end / bytes=start- / bytes=-suffix
+// 返回 (start, end)，start 为 None 表示后缀区间，此时 end 为后缀长度
+fn parse_range_header(request: &str) -> Option<(Option<u64>, Option<u64>)> {
+    let value = request.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("range")
+            .then(|| value.trim())
+    })?;
+    let spec = value.strip_prefix("bytes=")?.split(',').next()?.trim();
+    let (start, end) = spec.split_once('-')?;
+    let start = start.trim();
+    let end = end.trim();
+    match (start.is_empty(), end.is_empty()) {
+        (false, _) => Some((
+            Some(start.parse().ok()?),
+            if end.is_empty() {
+                None
+            } else {
+                Some(end.parse().ok()?)
+            },
+        )),
+        (true, false) => Some((None, Some(end.parse().ok()?))),
+        (true, true) => None,
+    }
+}
+
+// 等待范围起点的数据落盘的最长时间（下载中的文件）
+const PARTIAL_RANGE_WAIT_MS: u64 = 10_000;
+
+// 计算区间起点与闭区间终点：后缀区间（bytes=-N）按总长度计算起点，终点为当前已有数据末尾
+fn partial_range_bounds(
+    range: (Option<u64>, Option<u64>),
+    available: u64,
+    expected_len: Option<u64>,
+) -> (u64, Option<u64>) {
+    match range {
+        (Some(start), inclusive_end) => (start, inclusive_end),
+        (None, suffix) => (
+            expected_len
+                .unwrap_or(available)
+                .saturating_sub(suffix.unwrap_or(0)),
+            None,
+        ),
+    }
+}
+
+// 按已有数据长度裁剪区间，返回 [start, end)；起点超出已有数据或终点在起点之前时返回 None
+fn satisfiable_range(start: u64, inclusive_end: Option<u64>, available: u64) -> Option<(u64, u64)> {
+    if start >= available || inclusive_end.is_some_and(|end| end < start) {
+        return None;
+    }
+    let end = inclusive_end
+        .map(|end| end.saturating_add(1).min(available))
+        .unwrap_or(available);
+    Some((start, end))
+}
+
+// 以 Range 方式提供文件：只提供已写入磁盘的字节，expected_len 为下载完成后的总长度（未知为 None）
+async fn respond_with_partial_file(
+    stream: &mut tokio::net::TcpStream,
+    request: &str,
+    file_path: &std::path::Path,
+    expected_len: Option<u64>,
+    attachment: bool,
+    cors: &str,
+) -> std::io::Result<()> {
+    use tokio::io::AsyncSeekExt;
+
+    let available_len =
+        |path: &std::path::Path| std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+
+    let content_type = file_path
+        .extension()
+        .and_then(|e| e.to_str())
+        .and_then(guess_mime)
+        .unwrap_or("application/octet-stream");
+    let total = expected_len
+        .map(|len| len.to_string())
+        .unwrap_or_else(|| "*".to_string());
+
+    let mut available = available_len(file_path);
+    let range = parse_range_header(request);
+
+    let (status, start, end) = match range {
+        None => ("200 OK", 0, available),
+        Some(range) => {
+            let (start, inclusive_end) = partial_range_bounds(range, available, expected_len);
+
+            // 起点尚未下载到时稍作等待，超出总长度或终点在起点之前则直接拒绝
+            let within_total = expected_len.is_none_or(|len| start < len);
+            let reversed = inclusive_end.is_some_and(|end| end < start);
+            let waited = std::time::Instant::now();
+            while start >= available
+                && within_total
+                && !reversed
+                && waited.elapsed() < std::time::Duration::from_millis(PARTIAL_RANGE_WAIT_MS)
+            {
+                tokio::time::sleep(std::time::Duration::from_millis(250)).await;
+                available = available_len(file_path);
+            }
+
+            let Some((start, end)) = satisfiable_range(start, inclusive_end, available) else {
+                let header = format!(
+                    "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */{}\r\nRetry-After: 1\r\n{}Content-Length: 0\r\n\r\n",
+                    expected_len.unwrap_or(available),
+                    cors
+                );
+                return stream.write_all(header.as_bytes()).await;
+            };
+            ("206 Partial Content", start, end)
+        }
+    };
+
+    let length = end.saturating_sub(start);
+    let mut header = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\nContent-Disposition: {}\r\n{}",
+        status,
+        content_type,
+        length,
+        content_disposition(file_path, attachment),
+        cors
+    );
+    if range.is_some() {
+        header.push_str(&format!(
+            "Content-Range: bytes {}-{}/{}\r\n",
+            start,
+            end.saturating_sub(1),
+            total
+        ));
+    }
+    header.push_str("\r\n");
+    stream.write_all(header.as_bytes()).await?;
+
+    let mut file = tokio::fs::File::open(file_path).await?;
+    file.seek(std::io::SeekFrom::Start(start)).await?;
+    tokio::io::copy(&mut file.take(length), stream).await?;
+    Ok(())
+}
+
+// 边下边播缓存响应
+#[derive(Debug, Serialize)]
+pub struct ProgressiveCacheResponse {
+    pub url: String,
+    pub local_path: String,
+}
+
+// 缓存下载进度事件
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheVideoProgressEvent {
+    pub file_name: String,
+    pub downloaded: u64,
+    pub total: Option<u64>,
+    pub done: bool,
+    pub error: Option<String>,
+}
+
+// 后台下载远程视频到临时缓存，同时立即启动指向该文件的本地服务器，实现边下边播
+// 服务器登记在 FileServers 中，可通过 stop_file_server 关闭
+#[command]
+pub async fn cache_video_progressive(
+    app: tauri::AppHandle,
+    servers: State<'_, FileServers>,
+    url: String,
+    file_name: String,
+) -> Result<ProgressiveCacheResponse, String> {
+    use tokio::net::TcpListener;
+
+    // 只保留文件名部分并替换非法字符，防止写出缓存目录
+    let file_name = normalize_file_name(&file_name, "_")?;
+    let cache_dir = std::env::temp_dir().join("matrix-gen").join("temp");
+    std::fs::create_dir_all(&cache_dir).map_err(|e| format!("无法创建缓存目录: {}", e))?;
+    let file_path = cache_dir.join(&file_name);
+
+    // 许可在整个后台下载期间持有，与其他下载一致
+    let permit = acquire_network_permit().await?;
+    let client = reqwest::Client::builder()
+        .connect_timeout(std::time::Duration::from_secs(60))
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+    let mut response = client
+        .get(&url)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to download file: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "Download failed with status: {}",
+            response.status()
+        ));
+    }
+    let expected_len = response.content_length();
+
+    // 先创建空文件，服务器可以立即开始响应
+    let mut file = tokio::fs::File::create(&file_path)
+        .await
+        .map_err(|e| format!("无法创建缓存文件: {}", e))?;
+
+    // 使用系统分配的端口启动服务器
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .map_err(|e| format!("无法启动服务器: {}", e))?;
+    let port = listener
+        .local_addr()
+        .map_err(|e| format!("无法获取服务器端口: {}", e))?
+        .port();
+
+    // 登记服务器，便于列出和关闭
+    let shutdown = CancellationToken::new();
+    servers.servers().insert(
+        port,
+        FileServerEntry {
+            path: file_path.to_string_lossy().to_string(),
+            shutdown: shutdown.clone(),
+        },
+    );
+    let allowed_origins: std::sync::Arc<Vec<String>> = std::sync::Arc::new(
+        DEFAULT_CORS_ORIGINS
+            .iter()
+            .map(|origin| origin.to_string())
+            .collect(),
+    );
+
+    let served_path = file_path.clone();
+    tokio::spawn(async move {
+        loop {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = shutdown.cancelled() => {
+                    info!("[ProgressiveCache] 已关闭端口 {} 上的服务器", port);
+                    break;
+                }
+            };
+            let mut stream = match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    // 避免持续出错时空转
+                    warn!("[ProgressiveCache] 接受连接失败: {}", e);
+                    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+                    continue;
+                }
+            };
+            let file_path = served_path.clone();
+            let allowed_origins = allowed_origins.clone();
+            tokio::spawn(async move {
+                let mut buffer = [0u8; 4096];
+                if let Ok(n) = stream.read(&mut buffer).await {
+                    if n > 0 {
+                        let request = String::from_utf8_lossy(&buffer[..n]).to_string();
+                        let cors = cors_headers(&request, &allowed_origins);
+
+                        // 预检请求只返回 CORS 头
+                        if request.starts_with("OPTIONS ") {
+                            let response = format!(
+                                "HTTP/1.1 204 No Content\r\n{}Access-Control-Max-Age: 600\r\nContent-Length: 0\r\n\r\n",
+                                cors
+                            );
+                            let _ = stream.write_all(response.as_bytes()).await;
+                            return;
+                        }
+
+                        let attachment = query_flag(&request, "download").unwrap_or(false);
+                        if let Err(e) = respond_with_partial_file(
+                            &mut stream,
+                            &request,
+                            &file_path,
+                            expected_len,
+                            attachment,
+                            &cors,
+                        )
+                        .await
+                        {
+                            debug!("[ProgressiveCache] 连接中断: {}", e);
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    // 后台下载并发送进度
+    let progress_name = file_name.clone();
+    let download_app = app.clone();
+    tokio::spawn(async move {
+        let _permit = permit;
+        let operations = download_app.state::<ActiveOperations>();
+        let mut downloaded: u64 = 0;
+        let mut last_emit: Option<std::time::Instant> = None;
+        let result = operations
+            .run(async {
+                while let Some(chunk) = response
+                    .chunk()
+                    .await
+                    .map_err(|e| format!("Failed to read response body: {}", e))?
+                {
+                    file.write_all(&chunk)
+                        .await
+                        .map_err(|e| format!("Failed to write file: {}", e))?;
+                    downloaded += chunk.len() as u64;
+
+                    // 限制事件频率，完成事件在下载结束后单独发送
+                    let due = last_emit.is_none_or(|t| {
+                        t.elapsed()
+                            >= std::time::Duration::from_millis(DOWNLOAD_PROGRESS_INTERVAL_MS)
+                    });
+                    if !due {
+                        continue;
+                    }
+                    last_emit = Some(std::time::Instant::now());
+                    let _ = download_app.emit(
+                        "cache-video-progress",
+                        CacheVideoProgressEvent {
+                            file_name: progress_name.clone(),
+                            downloaded,
+                            total: expected_len,
+                            done: false,
+                            error: None,
+                        },
+                    );
+                }
+                file.flush()
+                    .await
+                    .map_err(|e| format!("Failed to write file: {}", e))
+            })
+            .await;
+
+        match &result {
+            Ok(()) => info!(
+                "[ProgressiveCache] 下载完成: {} ({} bytes)",
+                progress_name, downloaded
+            ),
+            Err(e) => warn!("[ProgressiveCache] 下载失败: {}: {}", progress_name, e),
+        }
+        let _ = download_app.emit(
+            "cache-video-progress",
+            CacheVideoProgressEvent {
+                file_name: progress_name,
+                downloaded,
+                total: expected_len,
+                done: true,
+                error: result.err(),
+            },
+        );
+    });
+
+    let served_url = format!(
+        "http://127.0.0.1:{}/{}",
+        port,
+        percent_encode_path(&file_name)
+    );
+    info!("[ProgressiveCache] 开始边下边播: {} -> {}", url, served_url);
+
+    Ok(ProgressiveCacheResponse {
+        url: served_url,
+        local_path: file_path.to_string_lossy().to_string(),
+    })
+}
+
+// 读取环境变量（同时兼容大写与小写形式）
+fn proxy_env_var(name: &str) -> Option<String> {
+    [name.to_uppercase(), name.to_lowercase()]
+        .iter()
+        .find_map(|key| std::env::var(key).ok())
+        .filter(|value| !value.trim().is_empty())
+}
+
+// 判断目标主机是否命中 NO_PROXY（逗号分隔，支持 "*"、域名后缀和 host:port）
+fn host_matches_no_proxy(host: &str, port: Option<u16>, no_proxy: &str) -> bool {
+    let host = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_lowercase();
+    no_proxy
+        .split(',')
+        .map(|entry| entry.trim().to_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| {
+            if entry == "*" {
+                return true;
+            }
+            // 带端口的条目只匹配对应端口
+            let (entry_host, entry_port) = match entry.rsplit_once(':') {
+                Some((h, p)) if !h.contains(':') => (h.to_string(), p.parse::<u16>().ok()),
+                _ => (entry.clone(), None),
+            };
+            if entry_port.is_some() && entry_port != port {
+                return false;
+            }
+            let suffix = entry_host.trim_start_matches("*.").trim_start_matches('.');
+            host == suffix || host.ends_with(&format!(".{}", suffix))
+        })
+}
+
+// 代理选择结果
+#[derive(Debug, PartialEq, Eq)]
+enum ProxyChoice {
+    Proxy(String), // 使用指定代理
+    Direct,        // 命中 NO_PROXY，强制直连
+    SystemDefault, // 未配置代理，沿用 reqwest 默认行为（含系统代理）
+}
+
+// 根据目标 URL 选择代理：显式代理优先，否则按协议读取 HTTPS_PROXY / HTTP_PROXY，最后是 ALL_PROXY
+// 目标主机命中 NO_PROXY 时直连
+fn resolve_proxy_for(target_url: &str, explicit_proxy: Option<&str>) -> ProxyChoice {
+    let parsed = reqwest::Url::parse(target_url).ok();
+
+    if let (Some(url), Some(no_proxy)) = (&parsed, proxy_env_var("NO_PROXY")) {
+        if let Some(host) = url.host_str() {
+            if host_matches_no_proxy(host, url.port_or_known_default(), &no_proxy) {
+                return ProxyChoice::Direct;
+            }
+        }
+    }
+
+    if let Some(proxy) = explicit_proxy.filter(|p| !p.trim().is_empty()) {
+        return ProxyChoice::Proxy(proxy.to_string());
+    }
+
+    let scheme_var = match parsed.as_ref().map(|url| url.scheme()) {
+        Some("https") | Some("wss") => "HTTPS_PROXY",
+        _ => "HTTP_PROXY",
+    };
+    proxy_env_var(scheme_var)
+        .or_else(|| proxy_env_var("ALL_PROXY"))
+        .map(ProxyChoice::Proxy)
+        .unwrap_or(ProxyChoice::SystemDefault)
+}
+
+// 为客户端配置代理
+fn apply_proxy(
+    client_builder: reqwest::ClientBuilder,
+    target_url: &str,
+    explicit_proxy: Option<&str>,
+    tag: &str,
+) -> Result<reqwest::ClientBuilder, String> {
+    match resolve_proxy_for(target_url, explicit_proxy) {
+        ProxyChoice::Proxy(proxy_url) => {
+            info!("[{}] 使用代理: {}", tag, proxy_url);
+            let proxy =
+                reqwest::Proxy::all(&proxy_url).map_err(|e| format!("代理配置失败: {}", e))?;
+            Ok(client_builder.proxy(proxy))
+        }
+        ProxyChoice::Direct => {
+            info!("[{}] 目标主机命中 NO_PROXY，直连: {}", tag, target_url);
+            Ok(client_builder.no_proxy())
+        }
+        ProxyChoice::SystemDefault => Ok(client_builder),
+    }
+}
+
+// 代理测试默认访问的地址（返回 204 的轻量端点）
+const DEFAULT_PROXY_TEST_URL: &str = "https://www.gstatic.com/generate_204";
+
+// 代理连通性测试结果（收到任何 HTTP 响应即视为代理可用）
+#[derive(Debug, Serialize)]
+pub struct ProxyTestResult {
+    pub ok: bool,
+    pub latency_ms: Option<u64>,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+}
+
+// 通过指定代理发送一次 GET 请求，测试代理是否可用
+#[command]
+pub async fn test_proxy(
+    proxy_url: String,
+    test_url: Option<String>,
+) -> Result<ProxyTestResult, String> {
+    let test_url = test_url
+        .filter(|url| !url.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_PROXY_TEST_URL.to_string());
+
+    // 不经过 NO_PROXY 判断，始终走被测试的代理
+    let proxy = match reqwest::Proxy::all(&proxy_url) {
+        Ok(proxy) => proxy,
+        Err(e) => {
+            return Ok(ProxyTestResult {
+                ok: false,
+                latency_ms: None,
+                status: None,
+                error: Some(format!("代理地址无效: {}", e)),
+            })
+        }
+    };
+    let client = reqwest::Client::builder()
+        .proxy(proxy)
+        .timeout(std::time::Duration::from_secs(10))
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    info!("[ProxyTest] 测试代理 {} -> {}", proxy_url, test_url);
+    let _permit = acquire_network_permit().await?;
+    let started = std::time::Instant::now();
+    let result = match client.get(&test_url).send().await {
+        Ok(response) => ProxyTestResult {
+            ok: true,
+            latency_ms: Some(started.elapsed().as_millis() as u64),
+            status: Some(response.status().as_u16()),
+            error: None,
+        },
+        Err(e) => ProxyTestResult {
+            ok: false,
+            latency_ms: None,
+            status: None,
+            error: Some(if e.is_timeout() {
+                "连接代理超时".to_string()
+            } else {
+                format!("通过代理请求失败: {}", e)
+            }),
+        },
+    };
+
+    info!(
+        "[ProxyTest] 结果: ok={}, 延迟 {:?} ms",
+        result.ok, result.latency_ms
+    );
+    Ok(result)
+}
+
+// WebSocket 连接选项
+#[derive(Debug, Deserialize)]
+pub struct WsConnectOptions {
+    pub url: String,
+    pub headers: Option<HashMap<String, String>>,
+    pub proxy_url: Option<String>,
+}
+
+// 推送给前端的 WebSocket 消息（二进制消息以 base64 传递）
+#[derive(Debug, Clone, Serialize)]
+pub struct WsMessageEvent {
+    pub kind: String, // "text" 或 "binary"
+    pub data: String,
+}
+
+// 已建立的 WebSocket 连接：连接 ID -> 发送通道
+#[derive(Default)]
+pub struct WebSocketConnections {
+    next_id: std::sync::atomic::AtomicU64,
+    senders: Mutex<
+        HashMap<
+            String,
+            tokio::sync::mpsc::UnboundedSender<tokio_tungstenite::tungstenite::Message>,
+        >,
+    >,
+}
+
+// 经 HTTP 代理建立 CONNECT 隧道
+async fn open_proxy_tunnel(
+    proxy_url: &str,
+    target_host: &str,
+    target_port: u16,
+) -> Result<tokio::net::TcpStream, String> {
+    let proxy = reqwest::Url::parse(proxy_url).map_err(|e| format!("代理地址无效: {}", e))?;
+    if proxy.scheme() != "http" {
+        return Err(format!(
+            "WebSocket 仅支持 HTTP 代理，当前代理: {}",
+            proxy_url
+        ));
+    }
+    let proxy_host = proxy.host_str().ok_or("代理地址缺少主机名")?;
+    let proxy_port = proxy.port_or_known_default().unwrap_or(80);
+
+    let mut stream = tokio::net::TcpStream::connect((proxy_host, proxy_port))
+        .await
+        .map_err(|e| format!("无法连接代理: {}", e))?;
+
+    let authority = format!("{}:{}", target_host, target_port);
+    let mut connect_request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+    if !proxy.username().is_empty() {
+        let credentials = format!(
+            "{}:{}",
+            proxy.username(),
+            proxy.password().unwrap_or_default()
+        );
+        connect_request.push_str(&format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            base64::Engine::encode(&base64::engine::general_purpose::STANDARD, credentials)
+        ));
+    }
+    connect_request.push_str("\r\n");
+    stream
+        .write_all(connect_request.as_bytes())
+        .await
+        .map_err(|e| format!("代理握手失败: {}", e))?;
+
+    // 读取代理响应头（逐字节读取，避免读入隧道后的数据）
+    let mut response = Vec::new();
+    let mut byte = [0u8; 1];
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() > 8192 {
+            return Err("代理响应头过长".to_string());
+        }
+        let n = stream
+            .read(&mut byte)
+            .await
+            .map_err(|e| format!("代理握手失败: {}", e))?;
+        if n == 0 {
+            return Err("代理在握手时关闭了连接".to_string());
+        }
+        response.push(byte[0]);
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or("");
+    if status_line.split_whitespace().nth(1) != Some("200") {
+        return Err(format!("代理拒绝建立隧道: {}", status_line));
+    }
+    Ok(stream)
+}
+
+// 建立 WebSocket 连接（合并全局默认请求头并应用代理设置），返回连接 ID
+// 收到的消息以 ws-message-<id> 事件推送，连接关闭时发送 ws-closed-<id> 事件
+#[command]
+pub async fn ws_connect(
+    app: tauri::AppHandle,
+    default_headers: State<'_, DefaultHeaders>,
+    connections: State<'_, WebSocketConnections>,
+    options: WsConnectOptions,
+) -> Result<String, String> {
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+    use tokio_tungstenite::tungstenite::http::{HeaderName, HeaderValue};
+    use tokio_tungstenite::tungstenite::Message;
+
+    let url = reqwest::Url::parse(&options.url).map_err(|e| format!("无效的 URL: {}", e))?;
+    if !matches!(url.scheme(), "ws" | "wss") {
+        return Err(format!("不支持的 WebSocket 协议: {}", url.scheme()));
+    }
+    let host = url.host_str().ok_or("URL 缺少主机名")?.to_string();
+    let port = url
+        .port_or_known_default()
+        .unwrap_or(if url.scheme() == "wss" { 443 } else { 80 });
+
+    let mut request = options
+        .url
+        .as_str()
+        .into_client_request()
+        .map_err(|e| format!("无效的 WebSocket 请求: {}", e))?;
+    let defaults = default_headers
+        .0
+        .lock()
+        .map(|h| h.clone())
+        .unwrap_or_default();
+    for (key, value) in merge_headers(&defaults, options.headers.as_ref()) {
+        let name = HeaderName::from_bytes(key.as_bytes())
+            .map_err(|e| format!("无效的请求头 {}: {}", key, e))?;
+        let value =
+            HeaderValue::from_str(&value).map_err(|e| format!("无效的请求头值 {}: {}", key, e))?;
+        request.headers_mut().insert(name, value);
+    }
+
+    // 许可只覆盖建立连接与握手，长连接建立后即归还
+    let permit = acquire_network_permit().await?;
+
+    // 建立底层 TCP 连接（显式代理 > 环境变量代理，命中 NO_PROXY 时直连）
+    let stream = match resolve_proxy_for(&options.url, options.proxy_url.as_deref()) {
+        ProxyChoice::Proxy(proxy_url) => {
+            info!("[WebSocket] 使用代理: {}", proxy_url);
+            open_proxy_tunnel(&proxy_url, &host, port).await?
+        }
+        ProxyChoice::Direct | ProxyChoice::SystemDefault => {
+            tokio::net::TcpStream::connect((host.as_str(), port))
+                .await
+                .map_err(|e| format!("无法连接服务器: {}", e))?
+        }
+    };
+
+    let (ws_stream, _) = tokio_tungstenite::client_async_tls(request, stream)
+        .await
+        .map_err(|e| format!("WebSocket 握手失败: {}", e))?;
+    drop(permit);
+
+    let id = format!(
+        "ws_{}",
+        connections
+            .next_id
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    );
+    let (sender, mut outgoing) = tokio::sync::mpsc::unbounded_channel::<Message>();
+    if let Ok(mut senders) = connections.senders.lock() {
+        senders.insert(id.clone(), sender);
+    }
+    info!("[WebSocket] 已连接 {}: {}", id, options.url);
+
+    let connection_id = id.clone();
+    tauri::async_runtime::spawn(async move {
+        let (mut sink, mut incoming) = ws_stream.split();
+        let message_event = format!("ws-message-{}", connection_id);
+
+        loop {
+            tokio::select! {
+                message = outgoing.recv() => {
+                    // 发送通道关闭（ws_close）时主动关闭连接
+                    let Some(message) = message else {
+                        let _ = sink.send(Message::Close(None)).await;
+                        break;
+                    };
+                    if let Err(e) = sink.send(message).await {
+                        warn!("[WebSocket] {} 发送失败: {}", connection_id, e);
+                        break;
+                    }
+                }
+                message = incoming.next() => {
+                    let payload = match message {
+                        Some(Ok(Message::Text(text))) => WsMessageEvent {
+                            kind: "text".to_string(),
+                            data: text,
+                        },
+                        Some(Ok(Message::Binary(data))) => WsMessageEvent {
+                            kind: "binary".to_string(),
+                            data: base64::Engine::encode(
+                                &base64::engine::general_purpose::STANDARD,
+                                data,
+                            ),
+                        },
+                        Some(Ok(Message::Close(_))) | None => break,
+                        Some(Ok(_)) => continue,
+                        Some(Err(e)) => {
+                            warn!("[WebSocket] {} 接收失败: {}", connection_id, e);
+                            break;
+                        }
+                    };
+                    if let Err(e) = app.emit(&message_event, payload) {
+                        debug!("[WebSocket] 发送事件失败: {}", e);
+                    }
+                }
+            }
+        }
+
+        if let Ok(mut senders) = app.state::<WebSocketConnections>().senders.lock() {
+            senders.remove(&connection_id);
+        }
+        info!("[WebSocket] 连接已关闭 {}", connection_id);
+        let _ = app.emit(&format!("ws-closed-{}", connection_id), ());
+    });
+
+    Ok(id)
+}
+
+// 通过已建立的 WebSocket 连接发送文本消息
+#[command]
+pub fn ws_send(
+    connections: State<'_, WebSocketConnections>,
+    id: String,
+    text: String,
+) -> Result<(), String> {
+    let senders = connections
+        .senders
+        .lock()
+        .map_err(|_| "WebSocket 连接状态不可用".to_string())?;
+    let sender = senders
+        .get(&id)
+        .ok_or_else(|| format!("WebSocket 连接不存在: {}", id))?;
+    sender
+        .send(tokio_tungstenite::tungstenite::Message::Text(text))
+        .map_err(|_| format!("WebSocket 连接已关闭: {}", id))
+}
+
+// 关闭 WebSocket 连接，返回连接是否存在
+#[command]
+pub fn ws_close(connections: State<'_, WebSocketConnections>, id: String) -> bool {
+    // 丢弃发送通道后，后台任务会发送 Close 帧并退出
+    let removed = connections
+        .senders
+        .lock()
+        .map(|mut senders| senders.remove(&id).is_some())
+        .unwrap_or(false);
+    if removed {
+        info!("[WebSocket] 正在关闭连接 {}", id);
+    }
+    removed
+}
+
+// 文件上传指令 - 支持多种图床和代理
+#[command]
+pub async fn upload_file(
+    app: tauri::AppHandle,
+    operations: State<'_, ActiveOperations>,
+    options: UploadOptions,
+) -> Result<UploadResponse, String> {
+    operations.run(upload_file_impl(options, Some(app))).await
+}
+
+// 上传文件，启用 verify 时确认返回的 URL 可访问后才报告成功
+async fn upload_file_impl(
+    options: UploadOptions,
+    app: Option<tauri::AppHandle>,
+) -> Result<UploadResponse, String> {
+    let verify = options.verify.unwrap_or(false);
+    let proxy_url = options.proxy_url.clone();
+    let response = send_upload(options, app).await?;
+
+    let url = match response.url.as_deref() {
+        Some(url) if verify && response.success => url,
+        _ => return Ok(response),
+    };
+    match verify_upload_retrievable(url, proxy_url.as_deref()).await {
+        Ok(()) => Ok(response),
+        Err(e) => Ok(UploadResponse {
+            success: false,
+            url: response.url.clone(),
+            error: Some(format!("上传成功但暂时无法访问: {}", e)),
+        }),
+    }
+}
+
+// 批量上传文件夹选项（字段含义与 UploadOptions 相同）
+#[derive(Debug, Deserialize)]
+pub struct UploadFolderOptions {
+    pub dir: String,
+    pub upload_url: String,
+    pub field_name: String,
+    pub response_format: Option<String>,
+    pub proxy_url: Option<String>,
+    pub json_fields: Option<HashMap<String, Value>>,
+    pub extra_fields: Option<HashMap<String, String>>,
+    pub url_json_path: Option<String>,
+    pub verify: Option<bool>,
+    pub concurrency: Option<usize>,      // 同时上传的文件数，默认 3
+    pub max_attempts: Option<u32>,       // 每个文件的最多尝试次数，默认 3
+    pub recursive: Option<bool>,         // 是否包含子文件夹
+    pub extensions: Option<Vec<String>>, // 只上传这些扩展名的文件（不含点，忽略大小写）
+}
+
+// 批量上传中单个文件的结果
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadFolderResult {
+    pub path: String,
+    pub url: Option<String>,
+    pub error: Option<String>,
+}
+
+// 批量上传进度事件
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadFolderProgressEvent {
+    pub done: usize,
+    pub total: usize,
+    pub result: UploadFolderResult,
+}
+
+// 上传单个文件（重试由 upload_file 内部按 max_retries 完成）
+async fn upload_folder_file(options: UploadOptions) -> UploadFolderResult {
+    let path = options.file_path.clone();
+    let (url, error) = match upload_file_impl(options, None).await {
+        Ok(UploadResponse {
+            success: true, url, ..
+        }) => (url, None),
+        Ok(response) => (
+            None,
+            Some(response.error.unwrap_or_else(|| "上传失败".to_string())),
+        ),
+        Err(e) => (None, Some(e)),
+    };
+    UploadFolderResult { path, url, error }
+}
+
+// 批量上传文件夹中的文件，逐个发送 upload-folder-progress 事件，返回与文件顺序一致的结果
+#[command]
+pub async fn upload_folder(
+    app: tauri::AppHandle,
+    operations: State<'_, ActiveOperations>,
+    options: UploadFolderOptions,
+) -> Result<Vec<UploadFolderResult>, String> {
+    let dir = PathBuf::from(&options.dir);
+    if !dir.is_dir() {
+        return Err(format!("文件夹不存在: {}", options.dir));
+    }
+    let concurrency = options.concurrency.unwrap_or(3).clamp(1, 16);
+    let max_attempts = options.max_attempts.unwrap_or(3).clamp(1, 10);
+    let extensions: Option<Vec<String>> = options.extensions.as_ref().map(|exts| {
+        exts.iter()
+            .map(|e| e.trim_start_matches('.').to_ascii_lowercase())
+            .collect()
+    });
+
+    let mut files: Vec<PathBuf> = if options.recursive.unwrap_or(false) {
+        let mut found = Vec::new();
+        collect_files_recursive(&dir, &mut found);
+        found.into_iter().map(|(path, _, _)| path).collect()
+    } else {
+        std::fs::read_dir(&dir)
+            .map_err(|e| format!("无法读取文件夹: {}", e))?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .collect()
+    };
+    // 跳过隐藏文件（含 .thumbs 缩略图目录中的文件）以及不匹配的扩展名
+    files.retain(|path| {
+        let hidden = path
+            .strip_prefix(&dir)
+            .map(|relative| {
+                relative
+                    .components()
+                    .any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+            })
+            .unwrap_or(false);
+        let ext_ok = extensions.as_ref().is_none_or(|exts| {
+            path.extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| exts.contains(&e.to_ascii_lowercase()))
+        });
+        !hidden && ext_ok
+    });
+    files.sort();
+
+    let total = files.len();
+    info!(
+        "[UploadFolder] 开始上传 {} 个文件: {} (并发 {})",
+        total, options.dir, concurrency
+    );
+
+    operations
+        .run(async {
+            let semaphore = std::sync::Arc::new(tokio::sync::Semaphore::new(concurrency));
+            let mut tasks = tokio::task::JoinSet::new();
+            for (index, path) in files.iter().enumerate() {
+                let upload_options = UploadOptions {
+                    file_path: path.to_string_lossy().to_string(),
+                    upload_url: options.upload_url.clone(),
+                    field_name: options.field_name.clone(),
+                    response_format: options.response_format.clone(),
+                    proxy_url: options.proxy_url.clone(),
+                    content_type: None,
+                    json_fields: options.json_fields.clone(),
+                    extra_fields: options.extra_fields.clone(),
+                    verify: options.verify,
+                    upload_id: None,
+                    max_retries: Some(max_attempts),
+                    retry_backoff_ms: None,
+                    url_json_path: options.url_json_path.clone(),
+                };
+                let semaphore = semaphore.clone();
+                tasks.spawn(async move {
+                    let _permit = semaphore.acquire_owned().await;
+                    (index, upload_folder_file(upload_options).await)
+                });
+            }
+
+            let mut results: Vec<Option<UploadFolderResult>> = files.iter().map(|_| None).collect();
+            let mut done = 0;
+            while let Some(joined) = tasks.join_next().await {
+                let Ok((index, result)) = joined else {
+                    continue;
+                };
+                done += 1;
+                let event = UploadFolderProgressEvent {
+                    done,
+                    total,
+                    result: result.clone(),
+                };
+                if let Err(e) = app.emit("upload-folder-progress", event) {
+                    warn!("[UploadFolder] 发送进度事件失败: {}", e);
+                }
+                results[index] = Some(result);
+            }
+
+            let results: Vec<UploadFolderResult> = results
+                .into_iter()
+                .zip(&files)
+                .map(|(result, path)| {
+                    result.unwrap_or_else(|| UploadFolderResult {
+                        path: path.to_string_lossy().to_string(),
+                        url: None,
+                        error: Some("上传任务异常退出".to_string()),
+                    })
+                })
+                .collect();
+            let failed = results.iter().filter(|r| r.error.is_some()).count();
+            info!(
+                "[UploadFolder] 上传完成: 成功 {}, 失败 {}",
+                total - failed,
+                failed
+            );
+            Ok(results)
+        })
+        .await
+}
+
+// 以短暂重试确认上传后的 URL 可访问（部分图床存在最终一致性延迟）
+async fn verify_upload_retrievable(url: &str, proxy_url: Option<&str>) -> Result<(), String> {
+    const RETRY_DELAYS_SECS: [u64; 5] = [1, 2, 3, 5, 8];
+
+    let client_builder = reqwest::Client::builder().timeout(std::time::Duration::from_secs(30));
+    let client = apply_proxy(client_builder, url, proxy_url, "UploadVerify")?
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let mut last_error = String::new();
+    for (attempt, delay) in RETRY_DELAYS_SECS.iter().enumerate() {
+        // 每次尝试单独获取许可，等待重试期间不占用
+        let permit = acquire_network_permit().await?;
+        // 部分服务器不支持 HEAD，此时改用只请求首字节的 GET
+        let result = match client.head(url).send().await {
+            Ok(response)
+                if matches!(
+                    response.status(),
+                    reqwest::StatusCode::METHOD_NOT_ALLOWED | reqwest::StatusCode::NOT_IMPLEMENTED
+                ) =>
+            {
+                client
+                    .get(url)
+                    .header(reqwest::header::RANGE, "bytes=0-0")
+                    .send()
+                    .await
+            }
+            other => other,
+        };
+        drop(permit);
+
+        match result {
+            Ok(response) if response.status().is_success() => {
+                debug!("[UploadVerify] URL 可访问 (尝试 {}): {}", attempt + 1, url);
+                return Ok(());
+            }
+            Ok(response) => last_error = format!("HTTP {}", response.status()),
+            Err(e) => last_error = e.to_string(),
+        }
+
+        debug!(
+            "[UploadVerify] URL 暂不可访问 (尝试 {}/{}): {}",
+            attempt + 1,
+            RETRY_DELAYS_SECS.len(),
+            last_error
+        );
+        if attempt + 1 < RETRY_DELAYS_SECS.len() {
+            tokio::time::sleep(std::time::Duration::from_secs(*delay)).await;
+        }
+    }
+
+    warn!("[UploadVerify] URL 验证失败 {}: {}", url, last_error);
+    Err(last_error)
+}
+
+// 从上传响应 JSON 中提取 URL：指定路径时只读该路径，否则尝试常见字段
+fn extract_upload_url(json: &Value, url_json_path: Option<&str>) -> Option<String> {
+    const COMMON_URL_PATHS: &[&str] = &[
+        "url",
+        "data",
+        "data.url",
+        "data.link",
+        "image.url",
+        "image.display_url",
+    ];
+    let paths = match url_json_path.filter(|path| !path.is_empty()) {
+        Some(path) => vec![path],
+        None => COMMON_URL_PATHS.to_vec(),
+    };
+    paths
+        .into_iter()
+        .find_map(|path| json_path_get(json, path).and_then(|v| v.as_str()))
+        .map(|s| s.to_string())
+}
+
+// 上传进度事件发送间隔（毫秒）
+const UPLOAD_PROGRESS_INTERVAL_MS: u64 = 100;
+// 上传时文件内容的分块大小
+const UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+// upload_file 单次调用内默认的最多尝试次数
+const UPLOAD_MAX_RETRIES: u32 = 3;
+// upload_file 默认的重试退避基数
+const UPLOAD_RETRY_BACKOFF_MS: u64 = 3000;
+// upload_file 重试退避上限
+const UPLOAD_MAX_BACKOFF_MS: u64 = 60 * 1000;
+
+// 上传进度事件（stage: start / progress / retry / finish；attempt 为当前尝试序号，finish 时为 0）
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadProgressEvent {
+    pub upload_id: String,
+    pub stage: &'static str,
+    pub sent: u64,
+    pub total: u64,
+    pub attempt: u32,
+    pub max_attempts: u32,
+    pub error: Option<String>,
+}
+
+// 上传进度目标（仅在传入 upload_id 时发送事件）
+#[derive(Clone)]
+struct UploadProgress {
+    app: tauri::AppHandle,
+    upload_id: String,
+    total: u64,
+    max_attempts: u32,
+}
+
+impl UploadProgress {
+    fn emit(&self, stage: &'static str, sent: u64, attempt: u32, error: Option<String>) {
+        let event = UploadProgressEvent {
+            upload_id: self.upload_id.clone(),
+            stage,
+            sent,
+            total: self.total,
+            attempt,
+            max_attempts: self.max_attempts,
+            error,
+        };
+        if let Err(e) = self.app.emit("upload-progress", event) {
+            warn!("[Upload] 发送 upload-progress 事件失败: {}", e);
+        }
+    }
+}
+
+// 打开文件并包装为分块读取的请求体（每次尝试重新打开），传入进度目标时报告已发送字节数
+async fn upload_file_body(
+    path: &str,
+    progress: Option<UploadProgress>,
+    attempt: u32,
+) -> Result<reqwest::Body, String> {
+    let file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| format!("无法读取文件: {}", e))?;
+    let state = (file, 0u64, None::<std::time::Instant>, progress);
+    let stream = futures_util::stream::try_unfold(
+        state,
+        move |(mut file, mut sent, mut last_emit, progress)| async move {
+            let mut chunk = vec![0u8; UPLOAD_CHUNK_SIZE];
+            let n = file.read(&mut chunk).await?;
+            if n == 0 {
+                return Ok::<_, std::io::Error>(None);
+            }
+            chunk.truncate(n);
+            sent += n as u64;
+            if let Some(progress) = progress.as_ref() {
+                let due = last_emit.is_none_or(|t| {
+                    t.elapsed() >= std::time::Duration::from_millis(UPLOAD_PROGRESS_INTERVAL_MS)
+                });
+                if due || sent == progress.total {
+                    last_emit = Some(std::time::Instant::now());
+                    progress.emit("progress", sent, attempt, None);
+                }
+            }
+            Ok(Some((chunk, (file, sent, last_emit, progress))))
+        },
+    );
+    Ok(reqwest::Body::wrap_stream(stream))
+}
+
+// 读取文件开头用于识别文件类型
+fn read_file_head(path: &str, len: u64) -> std::io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut head = Vec::new();
+    std::fs::File::open(path)?
+        .take(len)
+        .read_to_end(&mut head)?;
+    Ok(head)
+}
+
+// 发送上传请求，传入 upload_id 时发送开始、进度、重试与结束事件
+async fn send_upload(
+    options: UploadOptions,
+    app: Option<tauri::AppHandle>,
+) -> Result<UploadResponse, String> {
+    let progress = match (app, options.upload_id.clone()) {
+        (Some(app), Some(upload_id)) => {
+            let total = std::fs::metadata(&options.file_path)
+                .map(|m| m.len())
+                .unwrap_or(0);
+            Some(UploadProgress {
+                app,
+                upload_id,
+                total,
+                max_attempts: upload_max_retries(&options),
+            })
+        }
+        _ => None,
+    };
+
+    if let Some(progress) = progress.as_ref() {
+        progress.emit("start", 0, 1, None);
+    }
+    let result = send_upload_attempts(options, progress.as_ref()).await;
+    if let Some(progress) = progress.as_ref() {
+        let error = match &result {
+            Ok(response) => response.error.clone(),
+            Err(e) => Some(e.clone()),
+        };
+        let sent = match &result {
+            Ok(response) if response.success => progress.total,
+            _ => 0,
+        };
+        progress.emit("finish", sent, 0, error);
+    }
+    result
+}
+
+fn upload_max_retries(options: &UploadOptions) -> u32 {
+    options.max_retries.unwrap_or(UPLOAD_MAX_RETRIES).max(1)
+}
+
+// 第 attempt 次失败后的退避时间：base * 2^(attempt-1)，不超过上限
+fn upload_retry_delay(base_ms: u64, attempt: u32) -> std::time::Duration {
+    let factor = 1u64 << attempt.saturating_sub(1).min(16);
+    std::time::Duration::from_millis(base_ms.saturating_mul(factor).min(UPLOAD_MAX_BACKOFF_MS))
+}
+
+async fn send_upload_attempts(
+    options: UploadOptions,
+    progress: Option<&UploadProgress>,
+) -> Result<UploadResponse, String> {
+    // 检查文件是否存在
+    let file_path = options.file_path.clone();
+    let metadata = std::fs::metadata(&file_path).map_err(|e| format!("无法读取文件: {}", e))?;
+
+    if !metadata.is_file() {
+        return Ok(UploadResponse {
+            success: false,
+            url: None,
+            error: Some("指定的路径不是文件".to_string()),
+        });
+    }
+
+    // 文件内容在每次尝试时从磁盘流式读取，避免整个文件驻留内存
+    let file_len = metadata.len();
+
+    // 获取文件名
+    let file_name: String = std::path::Path::new(&file_path)
+        .file_name()
+        .and_then(|n| n.to_str().map(|s| s.to_string()))
+        .unwrap_or_else(|| "file.mp4".to_string());
+
+    // 确定 MIME 类型：显式指定 > 扩展名 > 文件头识别
+    let mime_type = match options.content_type.as_deref().filter(|ct| !ct.is_empty()) {
+        Some(content_type) => content_type.to_string(),
+        None => std::path::Path::new(&file_name)
+            .extension()
+            .and_then(|e| e.to_str())
+            .and_then(guess_mime)
+            .or_else(|| {
+                read_file_head(&file_path, 8192)
+                    .ok()
+                    .and_then(|head| sniff_mime(&head))
+            })
+            .unwrap_or("application/octet-stream")
+            .to_string(),
+    };
+
+    let _permit = acquire_network_permit().await?;
+
+    // 创建 HTTP 客户端_builder
+    let mut client_builder = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(480)) // 8 分钟请求超时
+        .connect_timeout(std::time::Duration::from_secs(300)); // 5 分钟连接超时
+
+    // 配置代理（显式代理 > 与协议对应的环境变量，命中 NO_PROXY 时直连）
+    client_builder = apply_proxy(
+        client_builder,
+        &options.upload_url,
+        options.proxy_url.as_deref(),
+        "Upload",
+    )?;
+
+    let client = client_builder.build().map_err(|e| e.to_string())?;
+
+    let max_retries = upload_max_retries(&options);
+    let backoff_ms = options.retry_backoff_ms.unwrap_or(UPLOAD_RETRY_BACKOFF_MS);
+    let mut last_error = String::new();
+
+    // 根据上传 URL 判断图床类型
+    let response_format = options.response_format.as_deref().unwrap_or("url");
+
+    for attempt in 1..=max_retries {
+        // 构建 multipart 请求（文件分块流式发送）
+        let body = upload_file_body(&file_path, progress.cloned(), attempt).await?;
+        let part = reqwest::multipart::Part::stream_with_length(body, file_len)
+            .file_name(file_name.clone())
+            .mime_str(&mime_type)
+            .map_err(|e| e.to_string())?;
+
+        let mut multipart_form = reqwest::multipart::Form::new();
+
+        // 附加文本字段（不同图床要求不同，由调用方显式传入）
+        for (name, value) in options.extra_fields.iter().flatten() {
+            multipart_form = multipart_form.text(name.clone(), value.clone());
+        }
+
+        // JSON 字段序列化后作为 application/json 分段附加（放在文件之前，S3 POST policy 要求文件为最后一个字段）
+        for (name, value) in options.json_fields.iter().flatten() {
+            let json_part = reqwest::multipart::Part::text(value.to_string())
+                .mime_str("application/json")
+                .map_err(|e| e.to_string())?;
+            multipart_form = multipart_form.part(name.clone(), json_part);
+        }
+
+        let multipart_form = multipart_form.part(options.field_name.clone(), part);
+
+        match client
+            .post(&options.upload_url)
+            .multipart(multipart_form)
+            .send()
+            .await
+        {
+            Ok(response) => {
+                let status = response.status();
+
+                if status.is_success() {
+                    match response_format {
+                        "json" => {
+                            // JSON 响应格式
+                            let json: Value = response.json().await.map_err(|e| e.to_string())?;
+                            let url = extract_upload_url(&json, options.url_json_path.as_deref());
+
+                            if let Some(url) = url {
+                                return Ok(UploadResponse {
+                                    success: true,
+                                    url: Some(url),
+                                    error: None,
+                                });
+                            } else {
+                                return Ok(UploadResponse {
+                                    success: false,
+                                    url: None,
+                                    error: Some(format!("JSON 响应中未找到 URL: {}", json)),
+                                });
+                            }
+                        }
+                        _ => {
+                            // 直接返回 URL 文本
+                            let response_text = response.text().await.map_err(|e| e.to_string())?;
+                            if response_text.starts_with("https://")
+                                || response_text.starts_with("http://")
+                            {
+                                return Ok(UploadResponse {
+                                    success: true,
+                                    url: Some(response_text.trim().to_string()),
+                                    error: None,
+                                });
+                            } else {
+                                return Ok(UploadResponse {
+                                    success: false,
+                                    url: None,
+                                    error: Some(response_text),
+                                });
+                            }
+                        }
+                    }
+                } else {
+                    let response_text = response.text().await.unwrap_or_default();
+                    last_error = format!("上传失败 ({}): {}", status, response_text);
+                    // 4xx 重试也不会成功（429 限流除外），直接返回失败
+                    if status.is_client_error() && status != reqwest::StatusCode::TOO_MANY_REQUESTS
+                    {
+                        break;
+                    }
+                }
+            }
+            Err(e) => {
+                last_error = format!("上传请求失败 (尝试 {}/{}): {}", attempt, max_retries, e);
+            }
+        }
+
+        if attempt < max_retries {
+            let delay = upload_retry_delay(backoff_ms, attempt);
+            warn!(
+                "[Upload] {}，{} ms 后重试 ({}/{})",
+                last_error,
+                delay.as_millis(),
+                attempt + 1,
+                max_retries
+            );
+            if let Some(progress) = progress {
+                progress.emit("retry", 0, attempt + 1, Some(last_error.clone()));
+            }
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    Ok(UploadResponse {
+        success: false,
+        url: None,
+        error: Some(last_error),
+    })
+}
+
+// OSS 上传选项（阿里云 OSS / S3 兼容的 V1 签名）
+#[derive(Debug, Deserialize)]
+pub struct OssUploadOptions {
+    pub file_path: String,
+    pub access_key: String,
+    pub secret_key: String,
+    pub bucket: String,
+    pub region: String,               // 如 oss-cn-hangzhou
+    pub object_key: String,           // 对象路径，如 images/a.png
+    pub content_type: Option<String>, // 覆盖自动推断的 MIME 类型
+    pub endpoint: Option<String>,     // 自定义域名（默认 {region}.aliyuncs.com）
+    pub proxy_url: Option<String>,    // 代理地址
+}
+
+// 计算 OSS V1 签名：Base64(HMAC-SHA1(secret, VERB\nContent-MD5\nContent-Type\nDate\nResource))
+fn oss_signature(
+    secret_key: &str,
+    method: &str,
+    content_type: &str,
+    date: &str,
+    resource: &str,
+) -> Result<String, String> {
+    let string_to_sign = format!("{}\n\n{}\n{}\n{}", method, content_type, date, resource);
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret_key.as_bytes())
+        .map_err(|e| format!("无法初始化签名: {}", e))?;
+    mac.update(string_to_sign.as_bytes());
+    Ok(base64::Engine::encode(
+        &base64::engine::general_purpose::STANDARD,
+        mac.finalize().into_bytes(),
+    ))
+}
+
+// 使用签名直传文件到 OSS 存储桶，成功后返回对象的公开 URL
+#[command]
+pub async fn upload_to_oss(
+    operations: State<'_, ActiveOperations>,
+    options: OssUploadOptions,
+) -> Result<String, String> {
+    operations.run(upload_to_oss_impl(options)).await
+}
+
+async fn upload_to_oss_impl(options: OssUploadOptions) -> Result<String, String> {
+    let object_key = options.object_key.trim_start_matches('/');
+    if object_key.is_empty() {
+        return Err("对象路径不能为空".to_string());
+    }
+    let file_len = std::fs::metadata(&options.file_path)
+        .map_err(|e| format!("无法读取文件: {}", e))?
+        .len();
+
+    // 确定 MIME 类型：显式指定 > 扩展名 > 文件头识别
+    let content_type = match options.content_type.as_deref().filter(|ct| !ct.is_empty()) {
+        Some(content_type) => content_type.to_string(),
+        None => std::path::Path::new(object_key)
+            .extension()
+            .and_then(|e| e.to_str())
+            .and_then(guess_mime)
+            .or_else(|| {
+                read_file_head(&options.file_path, 8192)
+                    .ok()
+                    .and_then(|head| sniff_mime(&head))
+            })
+            .unwrap_or("application/octet-stream")
+            .to_string(),
+    };
+
+    let endpoint = options
+        .endpoint
+        .as_deref()
+        .filter(|e| !e.is_empty())
+        .map(|e| {
+            e.trim_start_matches("https://")
+                .trim_start_matches("http://")
+                .trim_end_matches('/')
+                .to_string()
+        })
+        .unwrap_or_else(|| format!("{}.aliyuncs.com", options.region));
+    let url = format!(
+        "https://{}.{}/{}",
+        options.bucket,
+        endpoint,
+        percent_encode_path(object_key)
+    );
+
+    let date = Utc::now().format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+    let resource = format!("/{}/{}", options.bucket, object_key);
+    let signature = oss_signature(&options.secret_key, "PUT", &content_type, &date, &resource)?;
+
+    let _permit = acquire_network_permit().await?;
+
+    let client_builder = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(480)) // 8 分钟请求超时
+        .connect_timeout(std::time::Duration::from_secs(300)); // 5 分钟连接超时
+    let client = apply_proxy(client_builder, &url, options.proxy_url.as_deref(), "OSS")?
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    info!("[OSS] 开始上传: {} -> {}", options.file_path, url);
+    // 分块流式发送文件，避免大文件整体读入内存
+    let body = upload_file_body(&options.file_path, None, 1).await?;
+    let response = client
+        .put(&url)
+        .header("Date", &date)
+        .header(reqwest::header::CONTENT_TYPE, &content_type)
+        .header(
+            reqwest::header::AUTHORIZATION,
+            format!("OSS {}:{}", options.access_key, signature),
+        )
+        .header(reqwest::header::CONTENT_LENGTH, file_len)
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| format!("上传请求失败: {}", e))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let response_text = response.text().await.unwrap_or_default();
+        error!("[OSS] 上传失败 ({}): {}", status, response_text);
+        return Err(format!("上传失败 ({}): {}", status, response_text));
+    }
+
+    info!("[OSS] 上传成功: {}", url);
+    Ok(url)
+}
+
+// 上传队列单个任务的默认最大尝试次数（任务的 max_retries 可覆盖）
+// 队列自行按长间隔退避，每次尝试只发送一次请求，避免与 upload_file 内部重试叠加
+const UPLOAD_QUEUE_MAX_ATTEMPTS: u32 = 6;
+
+fn upload_queue_max_attempts(options: &UploadOptions) -> u32 {
+    options
+        .max_retries
+        .unwrap_or(UPLOAD_QUEUE_MAX_ATTEMPTS)
+        .max(1)
+}
+
+// 上传队列重试退避上限
+const UPLOAD_QUEUE_MAX_BACKOFF_SECS: u64 = 30 * 60;
+
+// 上传队列任务状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadJobStatus {
+    Pending,
+    Uploading,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+// 上传队列任务（持久化到应用数据目录 upload_queue.json）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadJob {
+    pub id: String,
+    pub options: UploadOptions,
+    pub status: UploadJobStatus,
+    pub attempts: u32,
+    pub next_attempt_at: Option<i64>, // 毫秒时间戳
+    pub url: Option<String>,
+    pub error: Option<String>,
+    pub created_at: i64,
+}
+
+// 磁盘持久化的上传队列，由后台 worker 依次处理
+#[derive(Default)]
+pub struct UploadQueue {
+    jobs: Mutex<Vec<UploadJob>>,
+    store_path: Mutex<Option<PathBuf>>,
+    current: Mutex<Option<(String, CancellationToken)>>,
+    wakeup: tokio::sync::Notify,
+    next_id: std::sync::atomic::AtomicU64,
+}
+
+impl UploadQueue {
+    fn jobs(&self) -> std::sync::MutexGuard<'_, Vec<UploadJob>> {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // 将队列写入磁盘，失败只记录日志
+    fn persist(&self) {
+        let Some(path) = self.store_path.lock().ok().and_then(|p| p.clone()) else {
+            return;
+        };
+        let content = match serde_json::to_string_pretty(&*self.jobs()) {
+            Ok(content) => content,
+            Err(e) => {
+                warn!("[UploadQueue] 序列化队列失败: {}", e);
+                return;
+            }
+        };
+        if let Some(parent) = path.parent() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+        if let Err(e) = std::fs::write(&path, content) {
+            warn!("[UploadQueue] 保存队列失败: {}", e);
+        }
+    }
+
+    // 修改指定任务并持久化，返回修改后的任务
+    fn update_job(&self, id: &str, update: impl FnOnce(&mut UploadJob)) -> Option<UploadJob> {
+        let job = {
+            let mut jobs = self.jobs();
+            let job = jobs.iter_mut().find(|job| job.id == id)?;
+            update(job);
+            job.clone()
+        };
+        self.persist();
+        Some(job)
+    }
+
+    // 取出下一个到期的任务；没有到期任务时返回最近的等待时长（None 表示队列空闲）
+    fn next_due(&self) -> Result<UploadJob, Option<std::time::Duration>> {
+        let now = Utc::now().timestamp_millis();
+        let jobs = self.jobs();
+        let pending = jobs
+            .iter()
+            .filter(|job| job.status == UploadJobStatus::Pending);
+
+        let mut earliest: Option<i64> = None;
+        for job in pending {
+            let due_at = job.next_attempt_at.unwrap_or(0);
+            if due_at <= now {
+                return Ok(job.clone());
+            }
+            earliest = Some(earliest.map_or(due_at, |e| e.min(due_at)));
+        }
+        Err(earliest.map(|due_at| std::time::Duration::from_millis((due_at - now) as u64)))
+    }
+}
+
+fn emit_upload_job(app: &tauri::AppHandle, job: &UploadJob) {
+    if let Err(e) = app.emit("upload-queue-status", job) {
+        debug!("[UploadQueue] 发送状态事件失败: {}", e);
+    }
+}
+
+// 启动上传队列：从磁盘恢复未完成的任务并启动后台 worker（在 setup 中调用）
+pub fn start_upload_queue(app: &tauri::AppHandle) -> Result<(), String> {
+    let queue = app.state::<UploadQueue>();
+    let store_path = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("无法解析应用数据目录: {}", e))?
+        .join("upload_queue.json");
+
+    let mut restored: Vec<UploadJob> = match std::fs::read_to_string(&store_path) {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+            warn!("[UploadQueue] 队列文件格式错误，已忽略: {}", e);
+            Vec::new()
+        }),
+        Err(_) => Vec::new(),
+    };
+    // 已结束的任务不再保留；中断时正在上传的任务重新排队
+    restored.retain(|job| {
+        !matches!(
+            job.status,
+            UploadJobStatus::Completed | UploadJobStatus::Cancelled
+        )
+    });
+    for job in restored
+        .iter_mut()
+        .filter(|job| job.status == UploadJobStatus::Uploading)
+    {
+        job.status = UploadJobStatus::Pending;
+    }
+
+    let pending = restored
+        .iter()
+        .filter(|job| job.status == UploadJobStatus::Pending)
+        .count();
+    info!("[UploadQueue] 已恢复 {} 个待上传任务", pending);
+
+    *queue.jobs() = restored;
+    if let Ok(mut path) = queue.store_path.lock() {
+        *path = Some(store_path);
+    }
+    queue.persist();
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        run_upload_queue_worker(app).await;
+    });
+    Ok(())
+}
+
+async fn run_upload_queue_worker(app: tauri::AppHandle) {
+    let queue = app.state::<UploadQueue>();
+    loop {
+        let job = match queue.next_due() {
+            Ok(job) => job,
+            Err(Some(wait)) => {
+                tokio::select! {
+                    _ = queue.wakeup.notified() => {}
+                    _ = tokio::time::sleep(wait) => {}
+                }
+                continue;
+            }
+            Err(None) => {
+                queue.wakeup.notified().await;
+                continue;
+            }
+        };
+
+        let token = CancellationToken::new();
+        if let Ok(mut current) = queue.current.lock() {
+            *current = Some((job.id.clone(), token.clone()));
+        }
+        // 取出任务后可能已被取消，只有仍在排队的任务才开始上传
+        let started = queue.update_job(&job.id, |job| {
+            if job.status == UploadJobStatus::Pending {
+                job.status = UploadJobStatus::Uploading;
+                job.attempts += 1;
+            }
+        });
+        match started {
+            Some(job) if job.status == UploadJobStatus::Uploading => {
+                info!(
+                    "[UploadQueue] 开始上传 {} (第 {} 次): {}",
+                    job.id, job.attempts, job.options.file_path
+                );
+                emit_upload_job(&app, &job);
+            }
+            _ => {
+                if let Ok(mut current) = queue.current.lock() {
+                    *current = None;
+                }
+                continue;
+            }
+        }
+
+        let mut attempt_options = job.options.clone();
+        attempt_options.max_retries = Some(1);
+        let result = tokio::select! {
+            result = upload_file_impl(attempt_options, Some(app.clone())) => result,
+            _ = token.cancelled() => Err(CANCELLED_ERROR.to_string()),
+        };
+        if let Ok(mut current) = queue.current.lock() {
+            *current = None;
+        }
+
+        let updated = queue.update_job(&job.id, |job| {
+            // 上传期间被取消的任务保持取消状态
+            if job.status == UploadJobStatus::Cancelled {
+                return;
+            }
+            let error = match result {
+                Ok(UploadResponse {
+                    success: true, url, ..
+                }) => {
+                    job.status = UploadJobStatus::Completed;
+                    job.url = url;
+                    job.error = None;
+                    job.next_attempt_at = None;
+                    return;
+                }
+                Ok(response) => response.error.unwrap_or_else(|| "上传失败".to_string()),
+                Err(e) => e,
+            };
+            job.error = Some(error);
+            if job.attempts >= upload_queue_max_attempts(&job.options) {
+                job.status = UploadJobStatus::Failed;
+                job.next_attempt_at = None;
+            } else {
+                // 指数退避：30s, 60s, 120s ... 最长 30 分钟
+                let backoff = (30u64 << (job.attempts - 1)).min(UPLOAD_QUEUE_MAX_BACKOFF_SECS);
+                job.status = UploadJobStatus::Pending;
+                job.next_attempt_at = Some(Utc::now().timestamp_millis() + backoff as i64 * 1000);
+            }
+        });
+
+        if let Some(job) = updated {
+            match job.status {
+                UploadJobStatus::Completed => info!("[UploadQueue] 上传完成 {}", job.id),
+                UploadJobStatus::Failed => warn!(
+                    "[UploadQueue] 上传失败 {}，已放弃: {}",
+                    job.id,
+                    job.error.as_deref().unwrap_or("")
+                ),
+                _ => debug!(
+                    "[UploadQueue] 上传未成功 {}，稍后重试: {}",
+                    job.id,
+                    job.error.as_deref().unwrap_or("")
+                ),
+            }
+            emit_upload_job(&app, &job);
+        }
+    }
+}
+
+// 将上传任务加入持久化队列，返回任务 ID
+#[command]
+pub fn enqueue_upload(
+    app: tauri::AppHandle,
+    queue: State<'_, UploadQueue>,
+    options: UploadOptions,
+) -> Result<String, String> {
+    if !std::path::Path::new(&options.file_path).is_file() {
+        return Err(format!("文件不存在: {}", options.file_path));
+    }
+
+    let now = Utc::now().timestamp_millis();
+    let id = format!(
+        "upload_{}_{}",
+        now,
+        queue
+            .next_id
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    );
+    let job = UploadJob {
+        id: id.clone(),
+        options,
+        status: UploadJobStatus::Pending,
+        attempts: 0,
+        next_attempt_at: None,
+        url: None,
+        error: None,
+        created_at: now,
+    };
+
+    queue.jobs().push(job.clone());
+    queue.persist();
+    queue.wakeup.notify_one();
+
+    info!("[UploadQueue] 已加入队列 {}: {}", id, job.options.file_path);
+    emit_upload_job(&app, &job);
+    Ok(id)
+}
+
+// 获取上传队列中的所有任务
+#[command]
+pub fn get_upload_queue(queue: State<'_, UploadQueue>) -> Vec<UploadJob> {
+    queue.jobs().clone()
+}
+
+// 取消上传任务（包括正在上传的任务），返回是否取消成功
+#[command]
+pub fn cancel_upload(
+    app: tauri::AppHandle,
+    queue: State<'_, UploadQueue>,
+    id: String,
+) -> Result<bool, String> {
+    let updated = queue.update_job(&id, |job| {
+        if matches!(
+            job.status,
+            UploadJobStatus::Pending | UploadJobStatus::Uploading
+        ) {
+            job.status = UploadJobStatus::Cancelled;
+            job.next_attempt_at = None;
+        }
+    });
+    let job = updated.ok_or_else(|| format!("上传任务不存在: {}", id))?;
+    if job.status != UploadJobStatus::Cancelled {
+        return Ok(false);
+    }
+
+    if let Ok(current) = queue.current.lock() {
+        if let Some((current_id, token)) = current.as_ref() {
+            if *current_id == id {
+                token.cancel();
+            }
+        }
+    }
+
+    info!("[UploadQueue] 已取消上传任务 {}", id);
+    emit_upload_job(&app, &job);
+    Ok(true)
+}
+
+// 输出配置（按项目区分输出目录与文件名模板）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputProfile {
+    pub name: String,
+    pub directory: String,
+    pub filename_template: Option<String>,
+}
+
+// 持久化到配置目录 output_profiles.json 的内容
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct OutputProfilesSettings {
+    pub active: Option<String>,
+    pub profiles: Vec<OutputProfile>,
+}
+
+impl OutputProfilesSettings {
+    fn active_profile(&self) -> Option<&OutputProfile> {
+        let active = self.active.as_deref()?;
+        self.profiles.iter().find(|p| p.name == active)
+    }
+}
+
+fn output_profiles_path(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_config_dir()
+        .map(|dir| dir.join("output_profiles.json"))
+        .map_err(|e| format!("无法解析配置目录: {}", e))
+}
+
+// 读取输出配置，文件不存在时返回空配置
+fn load_output_profiles(app: &tauri::AppHandle) -> Result<OutputProfilesSettings, String> {
+    let path = output_profiles_path(app)?;
+    match std::fs::read_to_string(&path) {
+        Ok(content) => {
+            serde_json::from_str(&content).map_err(|e| format!("输出配置文件格式错误: {}", e))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(OutputProfilesSettings::default()),
+        Err(e) => Err(format!("无法读取输出配置: {}", e)),
+    }
+}
+
+fn save_output_profiles(
+    app: &tauri::AppHandle,
+    settings: &OutputProfilesSettings,
+) -> Result<(), String> {
+    let path = output_profiles_path(app)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("无法创建配置目录: {}", e))?;
+    }
+    let content =
+        serde_json::to_string_pretty(settings).map_err(|e| format!("无法序列化输出配置: {}", e))?;
+    std::fs::write(&path, content).map_err(|e| format!("无法保存输出配置: {}", e))
+}
+
+// 按模板生成文件名，支持 {name} {ext} {media_type} {date} {time} {timestamp}
+// 模板结果没有扩展名时自动补上原扩展名
+fn apply_filename_template(template: &str, file_name: &str, media_type: &str) -> String {
+    let path = std::path::Path::new(file_name);
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(file_name);
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let now = chrono::Local::now();
+
+    let rendered = template
+        .replace("{name}", stem)
+        .replace("{ext}", ext)
+        .replace("{media_type}", media_type)
+        .replace("{date}", &now.format("%Y%m%d").to_string())
+        .replace("{time}", &now.format("%H%M%S").to_string())
+        .replace("{timestamp}", &now.timestamp_millis().to_string());
+
+    if ext.is_empty() || std::path::Path::new(&rendered).extension().is_some() {
+        rendered
+    } else {
+        format!("{}.{}", rendered, ext)
+    }
+}
+
+// 创建或更新输出配置（同名配置会被覆盖）
+#[command]
+pub fn create_profile(app: tauri::AppHandle, profile: OutputProfile) -> Result<(), String> {
+    let name = profile.name.trim().to_string();
+    if name.is_empty() {
+        return Err("配置名称不能为空".to_string());
+    }
+    let directory = PathBuf::from(profile.directory.trim());
+    if !directory.is_absolute() {
+        return Err(format!("输出目录必须是绝对路径: {}", profile.directory));
+    }
+    ensure_writable_dir(&directory).map_err(|e| format!("输出目录不可用: {}", e))?;
+
+    let profile = OutputProfile {
+        name: name.clone(),
+        directory: directory.to_string_lossy().to_string(),
+        filename_template: profile
+            .filename_template
+            .filter(|template| !template.trim().is_empty()),
+    };
+
+    let mut settings = load_output_profiles(&app)?;
+    match settings.profiles.iter_mut().find(|p| p.name == name) {
+        Some(existing) => *existing = profile,
+        None => settings.profiles.push(profile),
+    }
+    save_output_profiles(&app, &settings)?;
+
+    info!("[OutputProfile] 已保存输出配置: {}", name);
+    Ok(())
+}
+
+// 切换当前输出配置，传入 None 恢复默认输出目录
+#[command]
+pub fn set_active_profile(app: tauri::AppHandle, name: Option<String>) -> Result<(), String> {
+    let mut settings = load_output_profiles(&app)?;
+    if let Some(name) = &name {
+        if !settings.profiles.iter().any(|p| &p.name == name) {
+            return Err(format!("输出配置不存在: {}", name));
+        }
+    }
+    settings.active = name;
+    save_output_profiles(&app, &settings)?;
+
+    info!(
+        "[OutputProfile] 当前输出配置: {}",
+        settings.active.as_deref().unwrap_or("默认")
+    );
+    Ok(())
+}
+
+// 列出所有输出配置及当前配置
+#[command]
+pub fn list_output_profiles(app: tauri::AppHandle) -> Result<OutputProfilesSettings, String> {
+    load_output_profiles(&app)
+}
+
+// 解析输出目录：优先使用当前输出配置的目录，否则为系统视频目录下的 MatrixGen_Output，失败时回退到临时目录
+fn resolve_output_dir(app: &tauri::AppHandle, tag: &str) -> PathBuf {
+    match load_output_profiles(app) {
+        Ok(settings) => {
+            if let Some(profile) = settings.active_profile() {
+                debug!(
+                    "[{}] 使用输出配置 {}: {}",
+                    tag, profile.name, profile.directory
+                );
+                return PathBuf::from(&profile.directory);
+            }
+        }
+        Err(e) => warn!("[{}] 读取输出配置失败: {}, 使用默认目录", tag, e),
+    }
+
+    match app.path().video_dir() {
+        Ok(video_dir) => {
+            debug!("[{}] 使用系统视频目录: {:?}", tag, video_dir);
+            video_dir.join("MatrixGen_Output")
+        }
+        Err(e) => {
+            warn!(
+                "[{}] 获取系统视频目录失败: {}, 使用临时目录作为fallback",
+                tag, e
+            );
+            std::env::temp_dir().join("MatrixGen_Output")
+        }
+    }
+}
+
+// 按路径加锁：同一文件的写入串行执行，不同文件可并行写入
+#[derive(Debug, Default)]
+pub struct FileWriteLocks(Mutex<HashMap<PathBuf, std::sync::Arc<tokio::sync::Mutex<()>>>>);
+
+impl FileWriteLocks {
+    async fn lock(&self, path: &std::path::Path) -> tokio::sync::OwnedMutexGuard<()> {
+        let path_lock = {
+            let mut locks = self.0.lock().unwrap_or_else(|e| e.into_inner());
+            // 清理没有任何持有者或等待者的锁
+            locks.retain(|_, lock| std::sync::Arc::strong_count(lock) > 1);
+            locks.entry(path.to_path_buf()).or_default().clone()
+        };
+        path_lock.lock_owned().await
+    }
+}
+
+// 文件名最大长度（字节），为完整路径留出余量
+const MAX_FILE_NAME_BYTES: usize = 200;
+
+// Windows 保留设备名（不区分大小写，带扩展名同样保留）
+const RESERVED_FILE_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+// 规范化文件名：替换非法字符、合并空白、去除结尾的点和空格、避开保留名并限制长度
+fn normalize_file_name(name: &str, replacement: &str) -> Result<String, String> {
+    let is_illegal = |c: char| {
+        c.is_control() || matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*')
+    };
+    if replacement.chars().any(is_illegal) {
+        return Err(format!("替换字符串包含非法字符: {}", replacement));
+    }
+
+    let replaced: String = name
+        .chars()
+        .map(|c| {
+            if is_illegal(c) {
+                replacement.to_string()
+            } else {
+                c.to_string()
+            }
+        })
+        .collect();
+    let mut cleaned = replaced.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    // 超长时截断主文件名，尽量保留扩展名
+    if cleaned.len() > MAX_FILE_NAME_BYTES {
+        let (stem, ext) = match cleaned.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() && ext.len() < 16 => (stem, format!(".{}", ext)),
+            _ => (cleaned.as_str(), String::new()),
+        };
+        let mut cut = MAX_FILE_NAME_BYTES.saturating_sub(ext.len());
+        while !stem.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        cleaned = format!("{}{}", &stem[..cut], ext);
+    }
+
+    let mut cleaned = cleaned
+        .trim_end_matches(['.', ' '])
+        .trim_start()
+        .to_string();
+    if cleaned.is_empty() {
+        return Err(format!("文件名无效: {:?}", name));
+    }
+
+    let base = cleaned.split('.').next().unwrap_or("");
+    if RESERVED_FILE_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(base.trim_end()))
+    {
+        cleaned.insert(base.len(), '_');
+    }
+
+    Ok(cleaned)
+}
+
+// 校验并规范化文件名，返回清理后的名称（replacement 默认为 "_"）
+#[command]
+pub fn sanitize_filename(name: String, replacement: Option<String>) -> Result<String, String> {
+    normalize_file_name(&name, replacement.as_deref().unwrap_or("_"))
+}
+
+// 写入输出文件（用于视频和图像生成结果）
+// 注意：由于配置为 currentUser 安装模式，应用安装在 C:\Users\Name\AppData\Local\Programs\MatrixGenPro\
+// std::env::current_exe() 将指向该路径，您可以安全地写入相对于此路径的文件
+#[derive(Debug, Deserialize)]
+pub struct WriteOutputFileOptions {
+    pub file_name: String,
+    pub data: String,
+    pub media_type: String,        // "video" 或 "image"
+    pub subfolder: Option<String>, // 写入的子文件夹，"date" 表示按日期（YYYY-MM-DD）分类
+    pub metadata: Option<Value>,   // 生成参数（提示词、模型、种子等），写入同名 .json 附属文件
+}
+
+// 输出文件写入结果
+#[derive(Debug, Serialize)]
+pub struct WriteOutputFileResult {
+    pub path: String,
+    pub metadata_path: Option<String>,
+}
+
+// 确保输出文件所在目录存在
+fn create_output_parent_dir(file_path: &std::path::Path, tag: &str) -> Result<(), String> {
+    if let Some(output_dir) = file_path.parent() {
+        if let Err(e) = std::fs::create_dir_all(output_dir) {
+            error!("[{}] 创建目录失败: {}", tag, e);
+            return Err(format!("无法创建输出目录: {}", e));
+        }
+    }
+    Ok(())
+}
+
+// 计算输出文件的完整路径，不创建任何目录
+fn output_file_path(
+    app: &tauri::AppHandle,
+    file_name: &str,
+    media_type: &str,
+    tag: &str,
+) -> Result<PathBuf, String> {
+    // 使用系统标准目录，避免触发Tauri热重载
+    let output_dir = resolve_output_dir(app, tag);
+
+    // 只保留最后一级文件名，防止通过目录部分写到输出目录之外
+    let file_name = file_name.rsplit(['/', '\\']).next().unwrap_or(file_name);
+
+    // 当前输出配置设置了文件名模板时按模板重命名
+    let file_name = match load_output_profiles(app)
+        .ok()
+        .and_then(|settings| settings.active_profile()?.filename_template.clone())
+    {
+        Some(template) => apply_filename_template(&template, file_name, media_type),
+        None => file_name.to_string(),
+    };
+    let file_name = normalize_file_name(&file_name, "_")?;
+    Ok(output_dir.join(file_name))
+}
+
+// 将输出文件放入子文件夹："date" 按当天日期分类，其他值作为文件夹名（规范化后），为空时不变
+fn apply_output_subfolder(file_path: PathBuf, subfolder: Option<&str>) -> Result<PathBuf, String> {
+    let subfolder = match subfolder.map(str::trim).filter(|s| !s.is_empty()) {
+        Some("date") => chrono::Local::now().format("%Y-%m-%d").to_string(),
+        Some(name) => normalize_file_name(name, "_")?,
+        None => return Ok(file_path),
+    };
+    match (file_path.parent(), file_path.file_name()) {
+        (Some(dir), Some(name)) => Ok(dir.join(subfolder).join(name)),
+        _ => Ok(file_path),
+    }
+}
+
+// 同名文件已存在时在扩展名前追加序号，如 "a.png" -> "a (1).png"
+fn numbered_file_path(path: &std::path::Path, index: u32) -> PathBuf {
+    if index == 0 {
+        return path.to_path_buf();
+    }
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let file_name = match path.extension() {
+        Some(ext) => format!("{} ({}).{}", stem, index, ext.to_string_lossy()),
+        None => format!("{} ({})", stem, index),
+    };
+    path.with_file_name(file_name)
+}
+
+// 以独占方式创建文件，同名文件已存在时依次尝试 " (1)"、" (2)" 等，返回文件与实际路径
+fn create_unique_file(path: &std::path::Path) -> std::io::Result<(std::fs::File, PathBuf)> {
+    for index in 0..10_000 {
+        let candidate = numbered_file_path(path, index);
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+        {
+            Ok(file) => return Ok((file, candidate)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::AlreadyExists,
+        "同名文件过多",
+    ))
+}
+
+// 输出写入确认设置（持久化到配置目录 output_write_settings.json）
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct OutputWriteSettings {
+    pub confirm_output_writes: bool,
+    #[serde(default)]
+    pub mirror_directory: Option<String>, // 写入成功后在后台复制一份到该目录
+}
+
+// 等待前端确认的写入超时时间，超时视为拒绝
+const WRITE_CONFIRM_TIMEOUT_SECS: u64 = 60;
+
+// 等待前端确认的写入请求
+#[derive(Default)]
+pub struct WriteConfirmations {
+    next_id: std::sync::atomic::AtomicU64,
+    pending: Mutex<HashMap<String, tokio::sync::oneshot::Sender<bool>>>,
+}
+
+impl WriteConfirmations {
+    fn pending(
+        &self,
+    ) -> std::sync::MutexGuard<'_, HashMap<String, tokio::sync::oneshot::Sender<bool>>> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// 写入确认事件
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfirmWriteEvent {
+    pub token: String,
+    pub path: String,
+    pub size: u64,
+}
+
+fn output_write_settings_path(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_config_dir()
+        .map(|dir| dir.join("output_write_settings.json"))
+        .map_err(|e| format!("无法解析配置目录: {}", e))
+}
+
+// 读取输出写入设置，文件不存在时返回默认值（不需要确认）
+fn load_output_write_settings(app: &tauri::AppHandle) -> Result<OutputWriteSettings, String> {
+    let path = output_write_settings_path(app)?;
+    match std::fs::read_to_string(&path) {
+        Ok(content) => {
+            serde_json::from_str(&content).map_err(|e| format!("输出写入设置格式错误: {}", e))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(OutputWriteSettings::default()),
+        Err(e) => Err(format!("无法读取输出写入设置: {}", e)),
+    }
+}
+
+fn save_output_write_settings(
+    app: &tauri::AppHandle,
+    settings: &OutputWriteSettings,
+) -> Result<(), String> {
+    let path = output_write_settings_path(app)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("无法创建配置目录: {}", e))?;
+    }
+    let content = serde_json::to_string_pretty(settings)
+        .map_err(|e| format!("无法序列化输出写入设置: {}", e))?;
+    std::fs::write(&path, content).map_err(|e| format!("无法保存输出写入设置: {}", e))
+}
+
+// 开启或关闭输出写入确认
+#[command]
+pub fn set_confirm_output_writes(app: tauri::AppHandle, enabled: bool) -> Result<(), String> {
+    let mut settings = load_output_write_settings(&app)?;
+    settings.confirm_output_writes = enabled;
+    save_output_write_settings(&app, &settings)?;
+
+    info!(
+        "[OutputFile] 输出写入确认: {}",
+        if enabled { "开启" } else { "关闭" }
+    );
+    Ok(())
+}
+
+// 获取输出写入设置
+#[command]
+pub fn get_output_write_settings(app: tauri::AppHandle) -> Result<OutputWriteSettings, String> {
+    load_output_write_settings(&app)
+}
+
+// 设置镜像目录（如云同步文件夹），传入 None 关闭镜像
+#[command]
+pub fn set_mirror_directory(app: tauri::AppHandle, path: Option<String>) -> Result<(), String> {
+    let path = path.filter(|p| !p.trim().is_empty());
+    if let Some(dir) = path.as_deref() {
+        let dir = PathBuf::from(dir.trim());
+        if !dir.is_absolute() {
+            return Err(format!("镜像目录必须是绝对路径: {}", dir.display()));
+        }
+        ensure_writable_dir(&dir).map_err(|e| format!("镜像目录不可用: {}", e))?;
+    }
+
+    let mut settings = load_output_write_settings(&app)?;
+    settings.mirror_directory = path.map(|p| p.trim().to_string());
+    save_output_write_settings(&app, &settings)?;
+
+    match settings.mirror_directory.as_deref() {
+        Some(dir) => info!("[Mirror] 镜像目录: {}", dir),
+        None => info!("[Mirror] 已关闭镜像"),
+    }
+    Ok(())
+}
+
+// 镜像复制结果事件（mirror-complete / mirror-failed）
+#[derive(Debug, Clone, Serialize)]
+pub struct MirrorEvent {
+    pub source: String,
+    pub mirror_path: Option<String>,
+    pub error: Option<String>,
+}
+
+// 配置了镜像目录时，在后台把输出文件复制过去，不阻塞调用方
+fn spawn_output_mirror(app: &tauri::AppHandle, source: PathBuf) {
+    let mirror_dir = match load_output_write_settings(app) {
+        Ok(settings) => match settings.mirror_directory {
+            Some(dir) => PathBuf::from(dir),
+            None => return,
+        },
+        Err(e) => {
+            warn!("[Mirror] 读取镜像设置失败: {}", e);
+            return;
+        }
+    };
+    // 保留相对输出目录的子路径，避免不同子目录下的同名文件互相覆盖
+    let output_dir = resolve_output_dir(app, "Mirror");
+    let relative = match source.strip_prefix(&output_dir) {
+        Ok(relative) => relative.to_path_buf(),
+        Err(_) => match source.file_name() {
+            Some(file_name) => PathBuf::from(file_name),
+            None => return,
+        },
+    };
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let requested = mirror_dir.join(&relative);
+        let mut target = requested.clone();
+        let mut part_path = None;
+
+        // 先用占位文件预留不重名的目标，复制为 .part 再重命名，避免同步客户端上传不完整的文件
+        let result = async {
+            if let Some(parent) = requested.parent() {
+                tokio::fs::create_dir_all(parent)
+                    .await
+                    .map_err(|e| format!("无法创建镜像目录: {}", e))?;
+            }
+            let (_, reserved) =
+                create_unique_file(&requested).map_err(|e| format!("无法创建镜像文件: {}", e))?;
+            target = reserved;
+            let mut part_name = target.clone().into_os_string();
+            part_name.push(".part");
+            let part = part_path.insert(PathBuf::from(part_name));
+            tokio::fs::copy(&source, &*part)
+                .await
+                .map_err(|e| format!("复制到镜像目录失败: {}", e))?;
+            tokio::fs::rename(&*part, &target)
+                .await
+                .map_err(|e| format!("复制到镜像目录失败: {}", e))
+        }
+        .await;
+
+        let source = source.to_string_lossy().to_string();
+        let (event_name, event) = match result {
+            Ok(()) => {
+                debug!("[Mirror] 已镜像: {} -> {}", source, target.display());
+                (
+                    "mirror-complete",
+                    MirrorEvent {
+                        source,
+                        mirror_path: Some(target.to_string_lossy().to_string()),
+                        error: None,
+                    },
+                )
+            }
+            Err(e) => {
+                warn!("[Mirror] 镜像失败 {}: {}", source, e);
+                // 已预留占位文件时一并清理
+                if let Some(part_path) = &part_path {
+                    let _ = tokio::fs::remove_file(part_path).await;
+                    let _ = tokio::fs::remove_file(&target).await;
+                }
+                (
+                    "mirror-failed",
+                    MirrorEvent {
+                        source,
+                        mirror_path: None,
+                        error: Some(e),
+                    },
+                )
+            }
+        };
+        if let Err(e) = app.emit(event_name, event) {
+            warn!("[Mirror] 发送 {} 事件失败: {}", event_name, e);
+        }
+    });
+}
+
+// 前端对 confirm-write 事件的答复
+#[command]
+pub fn confirm_write(
+    confirmations: State<'_, WriteConfirmations>,
+    token: String,
+    allow: bool,
+) -> Result<(), String> {
+    let sender = confirmations
+        .pending()
+        .remove(&token)
+        .ok_or_else(|| format!("写入确认已过期或不存在: {}", token))?;
+    // 接收端已超时退出时忽略
+    let _ = sender.send(allow);
+    Ok(())
+}
+
+// 发送 confirm-write 事件并等待答复，超时或窗口未响应均视为拒绝
+async fn request_write_confirmation(
+    app: &tauri::AppHandle,
+    confirmations: &WriteConfirmations,
+    path: &std::path::Path,
+    size: u64,
+) -> bool {
+    let token = format!(
+        "write-{}",
+        confirmations
+            .next_id
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    );
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    confirmations.pending().insert(token.clone(), sender);
+
+    let event = ConfirmWriteEvent {
+        token: token.clone(),
+        path: path.to_string_lossy().to_string(),
+        size,
+    };
+    if let Err(e) = app.emit("confirm-write", event) {
+        warn!("[OutputFile] 发送 confirm-write 事件失败: {}", e);
+        confirmations.pending().remove(&token);
+        return false;
+    }
+
+    let timeout = std::time::Duration::from_secs(WRITE_CONFIRM_TIMEOUT_SECS);
+    let allowed = matches!(tokio::time::timeout(timeout, receiver).await, Ok(Ok(true)));
+    confirmations.pending().remove(&token);
+    allowed
+}
+
+#[command]
+pub async fn write_output_file(
+    app: tauri::AppHandle,
+    write_locks: State<'_, FileWriteLocks>,
+    confirmations: State<'_, WriteConfirmations>,
+    shutdown: State<'_, ShutdownCoordinator>,
+    options: WriteOutputFileOptions,
+) -> Result<WriteOutputFileResult, String> {
+    let _pending_write = shutdown.track_write()?;
+    let WriteOutputFileOptions {
+        file_name,
+        data,
+        media_type,
+        subfolder,
+        metadata,
+    } = options;
+
+    // 解码 base64
+    let decoded_data =
+        match base64::Engine::decode(&base64::engine::general_purpose::STANDARD, &data) {
+            Ok(d) => d,
+            Err(e) => {
+                error!("[OutputFile] base64 解码失败: {}", e);
+                return Err(format!("base64 解码失败: {}", e));
+            }
+        };
+
+    debug!("[OutputFile] 解码后数据长度: {}", decoded_data.len());
+
+    let path = write_output_bytes(
+        &app,
+        &write_locks,
+        &confirmations,
+        &file_name,
+        &media_type,
+        subfolder.as_deref(),
+        &decoded_data,
+    )
+    .await?;
+    let metadata_path = metadata.and_then(|metadata| {
+        write_output_metadata(std::path::Path::new(&path), metadata)
+            .map_err(|e| warn!("[OutputFile] 写入元数据失败: {}", e))
+            .ok()
+    });
+    Ok(WriteOutputFileResult {
+        path,
+        metadata_path,
+    })
+}
+
+// 输出文件的元数据文件路径（<文件名>.json）
+fn output_metadata_path(media_path: &std::path::Path) -> Option<PathBuf> {
+    let mut sidecar_name = media_path.file_name()?.to_os_string();
+    sidecar_name.push(".json");
+    Some(media_path.with_file_name(sidecar_name))
+}
+
+// 在输出文件旁写入 <文件名>.json 元数据：生成参数、创建时间与文件大小
+fn write_output_metadata(media_path: &std::path::Path, metadata: Value) -> Result<String, String> {
+    let sidecar_path = output_metadata_path(media_path).ok_or("输出文件路径无效")?;
+
+    let file_size = std::fs::metadata(media_path).map(|m| m.len()).unwrap_or(0);
+    let content = serde_json::json!({
+        "file_name": media_path.file_name().map(|n| n.to_string_lossy().to_string()),
+        "created_at": Utc::now().to_rfc3339(),
+        "file_size": file_size,
+        "metadata": metadata,
+    });
+    let content =
+        serde_json::to_string_pretty(&content).map_err(|e| format!("无法序列化元数据: {}", e))?;
+    std::fs::write(&sidecar_path, content).map_err(|e| format!("无法写入元数据文件: {}", e))?;
+
+    debug!("[OutputFile] 已写入元数据: {}", sidecar_path.display());
+    Ok(sidecar_path.to_string_lossy().to_string())
+}
+
+// 以二进制请求体写入输出文件，免去 base64 编解码（适合大视频）
+// 文件信息通过请求头传递（值需经过 encodeURIComponent 编码）：
+// x-file-name（必填）、x-media-type（默认 image）、x-subfolder（可选，同 write_output_file）
+#[command]
+pub async fn write_output_file_binary(
+    app: tauri::AppHandle,
+    write_locks: State<'_, FileWriteLocks>,
+    confirmations: State<'_, WriteConfirmations>,
+    shutdown: State<'_, ShutdownCoordinator>,
+    request: tauri::ipc::Request<'_>,
+) -> Result<String, String> {
+    let _pending_write = shutdown.track_write()?;
+    let tauri::ipc::InvokeBody::Raw(data) = request.body() else {
+        return Err("请求体必须是二进制数据".to_string());
+    };
+    let header = |name: &str| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(percent_decode)
+            .filter(|v| !v.is_empty())
+    };
+    let file_name = header("x-file-name").ok_or("缺少 x-file-name 请求头")?;
+    let media_type = header("x-media-type").unwrap_or_else(|| "image".to_string());
+    let subfolder = header("x-subfolder");
+
+    write_output_bytes(
+        &app,
+        &write_locks,
+        &confirmations,
+        &file_name,
+        &media_type,
+        subfolder.as_deref(),
+        data,
+    )
+    .await
+}
+
+// 计算输出路径（含子文件夹），开启写入确认时等待用户允许，然后创建所在目录
+async fn prepare_output_path(
+    app: &tauri::AppHandle,
+    confirmations: &WriteConfirmations,
+    file_name: &str,
+    media_type: &str,
+    subfolder: Option<&str>,
+    size: u64,
+) -> Result<PathBuf, String> {
+    // 开启写入确认时，在创建目录或写入之前等待用户允许
+    let confirm = load_output_write_settings(app)
+        .map(|settings| settings.confirm_output_writes)
+        .unwrap_or_else(|e| {
+            warn!("[OutputFile] {}, 按需要确认处理", e);
+            true
+        });
+    let file_path = output_file_path(app, file_name, media_type, "OutputFile")?;
+    let file_path = apply_output_subfolder(file_path, subfolder)?;
+    if confirm && !request_write_confirmation(app, confirmations, &file_path, size).await {
+        info!("[OutputFile] 写入被拒绝: {}", file_path.display());
+        return Err(format!("写入未被允许: {}", file_path.display()));
+    }
+    create_output_parent_dir(&file_path, "OutputFile")?;
+    Ok(file_path)
+}
+
+// 将数据写入输出目录（write_output_file 与 write_output_file_binary 共用），返回实际写入的绝对路径
+async fn write_output_bytes(
+    app: &tauri::AppHandle,
+    write_locks: &FileWriteLocks,
+    confirmations: &WriteConfirmations,
+    file_name: &str,
+    media_type: &str,
+    subfolder: Option<&str>,
+    data: &[u8],
+) -> Result<String, String> {
+    let file_path = prepare_output_path(
+        app,
+        confirmations,
+        file_name,
+        media_type,
+        subfolder,
+        data.len() as u64,
+    )
+    .await?;
+
+    debug!(
+        "[OutputFile] 准备写入文件: {}, 数据长度: {}",
+        file_path.display(),
+        data.len()
+    );
+
+    // 同一路径的写入串行执行，防止并发写入交错损坏文件
+    let _write_guard = write_locks.lock(&file_path).await;
+
+    // 不覆盖已有文件：同名时自动追加序号
+    let (mut file, file_path) = create_unique_file(&file_path).map_err(|e| {
+        error!("[OutputFile] 创建文件失败: {}", e);
+        format!("无法写入文件: {}", e)
+    })?;
+    let file_path_str = file_path.to_string_lossy().to_string();
+
+    // 写入文件
+    if let Err(e) = std::io::Write::write_all(&mut file, data) {
+        error!("[OutputFile] 写入文件失败: {}", e);
+        drop(file);
+        let _ = std::fs::remove_file(&file_path);
+        return Err(format!("无法写入文件: {}", e));
+    }
+    drop(file);
+
+    // 返回绝对路径供前端使用convertFileSrc转换
+    let absolute_path = file_path
+        .canonicalize()
+        .unwrap_or(file_path.clone())
+        .to_string_lossy()
+        .to_string();
+
+    // 验证文件是否存在
+    if !file_path.exists() {
+        error!("[OutputFile] 文件写入后不存在: {}", file_path_str);
+        return Err("文件写入后不存在".to_string());
+    }
+
+    // 获取文件大小
+    let file_size = std::fs::metadata(&file_path).map(|m| m.len()).unwrap_or(0);
+
+    info!(
+        "[OutputFile] 输出文件创建成功: {}, 大小: {} bytes",
+        absolute_path, file_size
+    );
+
+    spawn_output_mirror(app, file_path);
+    Ok(absolute_path)
+}
+
+// 分块写入中的输出文件：先写入 .part 临时文件，完成后重命名为最终文件
+struct ChunkedOutputFile {
+    file: std::fs::File,
+    part_path: PathBuf,
+    final_path: PathBuf,
+    // 上一块中不足 4 个字符的 base64 余量，与下一块拼接后再解码
+    pending: String,
+    written: u64,
+    // 最近一次追加数据的时间，用于过期清理
+    last_activity: std::time::Instant,
+    // 写入期间持有该路径的写入锁
+    _write_guard: tokio::sync::OwnedMutexGuard<()>,
+}
+
+impl ChunkedOutputFile {
+    // 关闭文件并删除 .part 临时文件与占位的最终文件
+    fn remove_files(self) {
+        let ChunkedOutputFile {
+            file,
+            part_path,
+            final_path,
+            ..
+        } = self;
+        // 先关闭文件句柄，Windows 上才能删除
+        drop(file);
+        for path in [&part_path, &final_path] {
+            if let Err(e) = std::fs::remove_file(path) {
+                warn!(
+                    "[OutputFile] 删除未完成的输出文件失败 {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+// 分块写入句柄空闲超时：超时未追加数据的句柄会被丢弃，释放写入锁并删除 .part 文件
+const CHUNKED_OUTPUT_IDLE_TIMEOUT_SECS: u64 = 300;
+
+// 分块写入的输出文件句柄
+#[derive(Default)]
+pub struct ChunkedOutputFiles {
+    next_id: std::sync::atomic::AtomicU64,
+    files: Mutex<HashMap<String, ChunkedOutputFile>>,
+}
+
+impl ChunkedOutputFiles {
+    fn files(&self) -> std::sync::MutexGuard<'_, HashMap<String, ChunkedOutputFile>> {
+        self.files.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // 移除句柄（释放写入锁）并删除其 .part 文件，句柄不存在时返回 false
+    fn discard(&self, handle: &str) -> bool {
+        let Some(output) = self.files().remove(handle) else {
+            return false;
+        };
+        output.remove_files();
+        true
+    }
+}
+
+// 后台检查句柄是否空闲超时，超时则丢弃；句柄完成或被中止后任务自动结束
+fn spawn_chunked_output_expiry(app: tauri::AppHandle, handle: String) {
+    tauri::async_runtime::spawn(async move {
+        let timeout = std::time::Duration::from_secs(CHUNKED_OUTPUT_IDLE_TIMEOUT_SECS);
+        let mut wait = timeout;
+        loop {
+            tokio::time::sleep(wait).await;
+            let chunked_files = app.state::<ChunkedOutputFiles>();
+            let idle = match chunked_files.files().get(&handle) {
+                Some(output) => output.last_activity.elapsed(),
+                None => return,
+            };
+            if idle >= timeout {
+                warn!("[OutputFile] 分块写入句柄空闲超时，已丢弃: {}", handle);
+                chunked_files.discard(&handle);
+                return;
+            }
+            wait = timeout - idle;
+        }
+    });
+}
+
+// 分块写入输出文件选项
+#[derive(Debug, Deserialize)]
+pub struct BeginOutputFileOptions {
+    pub file_name: String,
+    pub media_type: String,
+    pub subfolder: Option<String>, // 同 write_output_file
+    pub total_size: Option<u64>,   // 预计大小，用于写入确认提示
+}
+
+// 开始分块写入输出文件（用于超出 IPC 消息大小限制的大文件），返回句柄
+#[command]
+pub async fn begin_output_file(
+    app: tauri::AppHandle,
+    write_locks: State<'_, FileWriteLocks>,
+    confirmations: State<'_, WriteConfirmations>,
+    chunked_files: State<'_, ChunkedOutputFiles>,
+    options: BeginOutputFileOptions,
+) -> Result<String, String> {
+    let final_path = prepare_output_path(
+        &app,
+        &confirmations,
+        &options.file_name,
+        &options.media_type,
+        options.subfolder.as_deref(),
+        options.total_size.unwrap_or(0),
+    )
+    .await?;
+    let write_guard = write_locks.lock(&final_path).await;
+
+    // 不覆盖已有文件：先以空文件占用最终文件名（同名时自动追加序号），完成时再用 .part 替换
+    let (_, final_path) =
+        create_unique_file(&final_path).map_err(|e| format!("无法创建输出文件: {}", e))?;
+    let mut part_name = final_path.file_name().unwrap_or_default().to_os_string();
+    part_name.push(".part");
+    let part_path = final_path.with_file_name(part_name);
+    let file = match std::fs::File::create(&part_path) {
+        Ok(file) => file,
+        Err(e) => {
+            let _ = std::fs::remove_file(&final_path);
+            return Err(format!("无法创建输出文件: {}", e));
+        }
+    };
+
+    let handle = format!(
+        "output_{}",
+        chunked_files
+            .next_id
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    );
+    info!(
+        "[OutputFile] 开始分块写入 {}: {}",
+        handle,
+        final_path.display()
+    );
+
+    chunked_files.files().insert(
+        handle.clone(),
+        ChunkedOutputFile {
+            file,
+            part_path,
+            final_path,
+            pending: String::new(),
+            written: 0,
+            last_activity: std::time::Instant::now(),
+            _write_guard: write_guard,
+        },
+    );
+    spawn_chunked_output_expiry(app, handle.clone());
+    Ok(handle)
+}
+
+// 解码一块 base64 数据并追加到输出文件，返回已写入的总字节数
+#[command]
+pub async fn append_output_chunk(
+    chunked_files: State<'_, ChunkedOutputFiles>,
+    shutdown: State<'_, ShutdownCoordinator>,
+    handle: String,
+    base64_chunk: String,
+) -> Result<u64, String> {
+    use std::io::Write;
+
+    let _pending_write = shutdown.track_write()?;
+
+    let result = {
+        let mut files = chunked_files.files();
+        let output = files
+            .get_mut(&handle)
+            .ok_or_else(|| format!("无效的输出文件句柄: {}", handle))?;
+        output.last_activity = std::time::Instant::now();
+
+        output
+            .pending
+            .extend(base64_chunk.chars().filter(|c| !c.is_ascii_whitespace()));
+        let aligned_len = output.pending.len() / 4 * 4;
+        base64::Engine::decode(
+            &base64::engine::general_purpose::STANDARD,
+            &output.pending[..aligned_len],
+        )
+        .map_err(|e| format!("base64 解码失败: {}", e))
+        .and_then(|decoded| {
+            output.pending.drain(..aligned_len);
+            output
+                .file
+                .write_all(&decoded)
+                .map_err(|e| format!("无法写入文件: {}", e))?;
+            output.written += decoded.len() as u64;
+            Ok(output.written)
+        })
+    };
+
+    // 出错后文件已不完整，丢弃句柄以释放写入锁
+    if let Err(e) = &result {
+        error!("[OutputFile] 分块写入失败 {}: {}", handle, e);
+        chunked_files.discard(&handle);
+    }
+    result
+}
+
+// 中止分块写入：释放句柄并删除 .part 临时文件
+#[command]
+pub fn abort_output_file(
+    chunked_files: State<'_, ChunkedOutputFiles>,
+    handle: String,
+) -> Result<(), String> {
+    if !chunked_files.discard(&handle) {
+        return Err(format!("无效的输出文件句柄: {}", handle));
+    }
+    info!("[OutputFile] 已中止分块写入: {}", handle);
+    Ok(())
+}
+
+// 完成分块写入：刷新并替换占位的最终文件，返回实际写入的绝对路径
+#[command]
+pub async fn finish_output_file(
+    app: tauri::AppHandle,
+    chunked_files: State<'_, ChunkedOutputFiles>,
+    shutdown: State<'_, ShutdownCoordinator>,
+    handle: String,
+) -> Result<String, String> {
+    use std::io::Write;
+
+    let _pending_write = shutdown.track_write()?;
+
+    let mut output = chunked_files
+        .files()
+        .remove(&handle)
+        .ok_or_else(|| format!("无效的输出文件句柄: {}", handle))?;
+
+    let result = (|| {
+        if !output.pending.is_empty() {
+            return Err(format!(
+                "base64 数据不完整，剩余 {} 个字符",
+                output.pending.len()
+            ));
+        }
+        output
+            .file
+            .flush()
+            .and_then(|_| output.file.sync_all())
+            .map_err(|e| format!("无法写入文件: {}", e))?;
+        std::fs::rename(&output.part_path, &output.final_path)
+            .map_err(|e| format!("无法保存输出文件: {}", e))
+    })();
+
+    if let Err(e) = result {
+        error!("[OutputFile] 分块写入失败 {}: {}", handle, e);
+        output.remove_files();
+        return Err(e);
+    }
+
+    let absolute_path = output
+        .final_path
+        .canonicalize()
+        .unwrap_or(output.final_path.clone())
+        .to_string_lossy()
+        .to_string();
+    info!(
+        "[OutputFile] 分块写入完成: {}, 大小: {} bytes",
+        absolute_path, output.written
+    );
+    spawn_output_mirror(&app, output.final_path.clone());
+    Ok(absolute_path)
+}
+
+// 临时文件唯一后缀计数器（进程内单调递增）
+static TEMP_NAME_COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+// 在目录中为文件名追加 "_<时间戳><计数>" 后缀，并以 create_new 占位，保证不会与已有文件重名
+fn reserve_unique_temp_path(dir: &std::path::Path, file_name: &str) -> Result<PathBuf, String> {
+    let name = std::path::Path::new(file_name);
+    let stem = name
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(file_name);
+    let ext = name.extension().and_then(|e| e.to_str());
+    let millis = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+
+    loop {
+        let counter = TEMP_NAME_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let unique_name = match ext {
+            Some(ext) => format!("{}_{}{}.{}", stem, millis, counter, ext),
+            None => format!("{}_{}{}", stem, millis, counter),
+        };
+        let path = dir.join(unique_name);
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(_) => return Ok(path),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(format!("无法创建临时文件: {}", e)),
+        }
+    }
+}
+
+// 保留旧的临时文件函数以保持兼容性
+// unique 为 true 时自动追加唯一后缀，避免批量任务中同名文件互相覆盖；返回实际写入的路径
+#[command]
+pub async fn write_temp_file_binary(
+    write_locks: State<'_, FileWriteLocks>,
+    shutdown: State<'_, ShutdownCoordinator>,
+    file_name: String,
+    data: String,
+    unique: Option<bool>,
+) -> Result<String, String> {
+    let _pending_write = shutdown.track_write()?;
+    let cache_dir = std::env::temp_dir().join("matrix-gen").join("temp");
+
+    // 确保目录存在
+    if let Err(e) = std::fs::create_dir_all(&cache_dir) {
+        error!("[TempFile] 创建目录失败: {}", e);
+        return Err(format!("无法创建缓存目录: {}", e));
+    }
+
+    let file_path = if unique.unwrap_or(false) {
+        reserve_unique_temp_path(&cache_dir, &file_name)?
+    } else {
+        cache_dir.join(&file_name)
+    };
+    let file_path_str = file_path.to_string_lossy().to_string();
+
+    debug!(
+        "[TempFile] 准备写入文件: {}, 数据长度: {}",
+        file_path_str,
+        data.len()
+    );
+
+    // 解码 base64
+    let decoded_data =
+        match base64::Engine::decode(&base64::engine::general_purpose::STANDARD, &data) {
+            Ok(d) => d,
+            Err(e) => {
+                error!("[TempFile] base64 解码失败: {}", e);
+                return Err(format!("base64 解码失败: {}", e));
+            }
+        };
+
+    debug!("[TempFile] 解码后数据长度: {}", decoded_data.len());
+
+    // 同一路径的写入串行执行，防止并发写入交错损坏文件
+    let _write_guard = write_locks.lock(&file_path).await;
+
+    // 写入文件
+    if let Err(e) = std::fs::write(&file_path, &decoded_data) {
+        error!("[TempFile] 写入文件失败: {}", e);
+        return Err(format!("无法写入文件: {}", e));
+    }
+
+    // 验证文件是否存在
+    if !file_path.exists() {
+        error!("[TempFile] 文件写入后不存在: {}", file_path_str);
+        return Err("文件写入后不存在".to_string());
+    }
+
+    // 获取文件大小
+    let file_size = std::fs::metadata(&file_path).map(|m| m.len()).unwrap_or(0);
+
+    info!(
+        "[TempFile] 临时文件创建成功: {}, 大小: {} bytes",
+        file_path_str, file_size
+    );
+
+    Ok(file_path_str)
+}
+
+// 角色图片保存选项
+#[derive(Debug, Deserialize)]
+pub struct SaveCharacterImageOptions {
+    pub source_path: String,
+    pub character_id: String,
+}
+
+// 角色图片保存响应
+#[derive(Debug, Serialize)]
+pub struct SaveCharacterImageResponse {
+    pub success: bool,
+    pub path: Option<String>,
+    pub error: Option<String>,
+}
+
+// 保存角色图片到应用数据目录
+#[command]
+pub async fn save_character_image(
+    options: SaveCharacterImageOptions,
+) -> Result<SaveCharacterImageResponse, String> {
+    let source_path = options.source_path;
+    let character_id = options.character_id;
+
+    info!(
+        "[CharacterImage] 保存角色图片: {} -> {}",
+        source_path, character_id
+    );
+
+    // 获取应用数据目录
+    let data_dir = match std::env::current_dir() {
+        Ok(dir) => {
+            // 如果在开发环境，使用项目目录下的 data 目录
+            let data_dir = dir.join("data").join("characters").join(&character_id);
+            data_dir
+        }
+        Err(e) => {
+            error!("[CharacterImage] 获取目录失败: {}", e);
+            return Err(format!("获取目录失败: {}", e));
+        }
+    };
+
+    // 创建目录
+    if let Err(e) = std::fs::create_dir_all(&data_dir) {
+        error!("[CharacterImage] 创建目录失败: {}", e);
+        return Err(format!("创建目录失败: {}", e));
+    }
+
+    // 获取源文件名
+    let file_name = std::path::Path::new(&source_path)
+        .file_name()
+        .and_then(|n| n.to_str().map(|s| s.to_string()))
+        .unwrap_or_else(|| format!("{}.jpg", character_id));
+
+    // 目标路径
+    let target_path = data_dir.join(&file_name);
+    let relative_path = format!("data/characters/{}/{}", character_id, file_name);
+
+    // 复制文件
+    match std::fs::copy(&source_path, &target_path) {
+        Ok(bytes) => {
+            info!(
+                "[CharacterImage] 图片保存成功: {} ({} bytes)",
+                relative_path, bytes
+            );
+            Ok(SaveCharacterImageResponse {
+                success: true,
+                path: Some(relative_path),
+                error: None,
+            })
+        }
+        Err(e) => {
+            warn!("[CharacterImage] 复制文件失败: {}", e);
+            Ok(SaveCharacterImageResponse {
+                success: false,
+                path: None,
+                error: Some(format!("复制文件失败: {}", e)),
+            })
+        }
+    }
+}
+
+// 保存 base64 数据为角色图片
+#[derive(Debug, Deserialize)]
+pub struct SaveCharacterImageFromBase64Options {
+    pub base64_data: String,
+    pub character_id: String,
+    pub mime_type: String,
+}
+
+#[command]
+pub async fn save_character_image_from_base64(
+    options: SaveCharacterImageFromBase64Options,
+) -> Result<SaveCharacterImageResponse, String> {
+    let base64_data = options.base64_data;
+    let character_id = options.character_id;
+    let mime_type = options.mime_type;
+
+    info!(
+        "[CharacterImage] 保存 base64 图片: {} ({} bytes)",
+        character_id,
+        base64_data.len()
+    );
+
+    // 保存到项目根目录的 characters 文件夹
+    let data_dir = match std::env::current_dir() {
+        Ok(dir) => {
+            let data_dir = dir.join("characters").join(&character_id);
+            data_dir
+        }
+        Err(e) => {
+            error!("[CharacterImage] 获取目录失败: {}", e);
+            return Err(format!("获取目录失败: {}", e));
+        }
+    };
+
+    // 创建目录
+    if let Err(e) = std::fs::create_dir_all(&data_dir) {
+        error!("[CharacterImage] 创建目录失败: {}", e);
+        return Err(format!("创建目录失败: {}", e));
+    }
+
+    // 解码 base64
+    let image_data =
+        match base64::Engine::decode(&base64::engine::general_purpose::STANDARD, &base64_data) {
+            Ok(data) => data,
+            Err(e) => {
+                error!("[CharacterImage] base64 解码失败: {}", e);
+                return Err(format!("base64 解码失败: {}", e));
+            }
+        };
+
+    // 获取文件扩展名
+    let extension = match mime_type.as_str() {
+        "image/png" => "png",
+        "image/jpeg" => "jpg",
+        "image/jpg" => "jpg",
+        "image/webp" => "webp",
+        _ => "png",
+    };
+
+    let file_name = format!("character_preview_{}.{}", character_id, extension);
+    let target_path = data_dir.join(&file_name);
+    let relative_path = format!("characters/{}/{}", character_id, file_name);
+
+    // 写入文件
+    match std::fs::write(&target_path, &image_data) {
+        Ok(_) => {
+            info!(
+                "[CharacterImage] 图片保存成功: {} ({} bytes)",
+                relative_path,
+                image_data.len()
+            );
+            Ok(SaveCharacterImageResponse {
+                success: true,
+                path: Some(relative_path),
+                error: None,
+            })
+        }
+        Err(e) => {
+            warn!("[CharacterImage] 写入文件失败: {}", e);
+            Ok(SaveCharacterImageResponse {
+                success: false,
+                path: None,
+                error: Some(format!("写入文件失败: {}", e)),
+            })
+        }
+    }
+}
+
+// 开发模式下的项目根目录：可执行文件位于 <项目根>/src-tauri/target/<profile>/ 时返回项目根
+pub(crate) fn dev_project_root() -> Option<PathBuf> {
+    let exe_path = std::env::current_exe().ok()?;
+    let root = exe_path.parent()?.parent()?.parent()?.parent()?;
+    (root.join("src-tauri").exists() && root.join("src").exists()).then(|| root.to_path_buf())
+}
+
+// 插件目录候选（按优先级排列）：开发模式的源码目录 > 打包资源目录 > 可执行文件同目录
+fn plugin_dir_candidates(app: &tauri::AppHandle) -> Vec<(&'static str, PathBuf)> {
+    let mut candidates = Vec::new();
+
+    // 开发模式：Resource 常指向 target/debug/plugins，该目录可能缺 zhichuang 等，直接使用源码目录
+    if let Some(root) = dev_project_root() {
+        candidates.push(("dev_source", root.join("src-tauri").join("plugins")));
+    }
+    match app
+        .path()
+        .resolve("plugins/", tauri::path::BaseDirectory::Resource)
+    {
+        Ok(resource_path) => candidates.push(("resource", resource_path)),
+        Err(e) => warn!("[PluginLoader] Resource 解析失败: {}", e),
+    }
+    if let Some(exe_dir) = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.to_path_buf()))
+    {
+        candidates.push(("exe_dir", exe_dir.join("plugins")));
+    }
+    candidates
+}
+
+// 解析插件目录（插件加载与官方插件提取共用），不存在时创建
+pub(crate) fn resolve_plugins_dir(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    let (label, plugins_dir) = plugin_dir_candidates(app)
+        .into_iter()
+        .next()
+        .ok_or("无法确定插件目录")?;
+
+    info!(
+        "[PluginLoader] 插件目录路径 ({}): {}",
+        label,
+        plugins_dir.display()
+    );
+
+    // 创建插件目录（如果不存在）
+    if !plugins_dir.exists() {
+        std::fs::create_dir_all(&plugins_dir).map_err(|e| format!("无法创建插件目录: {}", e))?;
+        info!("[PluginLoader] 创建了插件目录: {}", plugins_dir.display());
+    }
+
+    Ok(plugins_dir)
+}
+
+// 官方插件源文件：开发模式为源码 resources 目录，发布模式为打包资源
+pub(crate) fn default_plugin_source(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    match dev_project_root() {
+        Some(root) => Ok(root
+            .join("src-tauri")
+            .join("resources")
+            .join("default-provider.js")),
+        None => app
+            .path()
+            .resolve(
+                "resources/default-provider.js",
+                tauri::path::BaseDirectory::Resource,
+            )
+            .map_err(|e| format!("Failed to resolve resource path: {}", e)),
+    }
+}
+
+// 插件目录候选项的诊断信息
+#[derive(Debug, Serialize)]
+pub struct PluginDirCandidate {
+    pub label: String,
+    pub path: String,
+    pub exists: bool,
+    pub selected: bool,
+    pub files: Vec<String>,
+}
+
+// 插件目录诊断结果
+#[derive(Debug, Serialize)]
+pub struct PluginDiagnostics {
+    pub resolved_dir: Option<String>,
+    pub is_dev_mode: bool,
+    pub default_plugin_source: Option<String>,
+    pub default_plugin_source_exists: bool,
+    pub candidates: Vec<PluginDirCandidate>,
+}
+
+// 诊断插件目录：列出实际使用的目录、考虑过的候选目录以及每个目录中的 .js 文件
+#[command]
+pub async fn diagnose_plugins(app: tauri::AppHandle) -> Result<PluginDiagnostics, String> {
+    let candidates = plugin_dir_candidates(&app);
+    let resolved = candidates.first().map(|(_, path)| path.clone());
+    let source = default_plugin_source(&app).ok();
+
+    let candidates = candidates
+        .into_iter()
+        .map(|(label, path)| {
+            let mut files: Vec<String> = std::fs::read_dir(&path)
+                .map(|entries| {
+                    entries
+                        .flatten()
+                        .map(|entry| entry.path())
+                        .filter(|p| p.extension().and_then(|e| e.to_str()) == Some("js"))
+                        .filter_map(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
+                        .collect()
+                })
+                .unwrap_or_default();
+            files.sort();
+            PluginDirCandidate {
+                label: label.to_string(),
+                exists: path.is_dir(),
+                selected: resolved.as_ref() == Some(&path),
+                path: path.to_string_lossy().to_string(),
+                files,
+            }
+        })
+        .collect();
+
+    Ok(PluginDiagnostics {
+        resolved_dir: resolved.map(|p| p.to_string_lossy().to_string()),
+        is_dev_mode: dev_project_root().is_some(),
+        default_plugin_source_exists: source.as_ref().is_some_and(|p| p.exists()),
+        default_plugin_source: source.map(|p| p.to_string_lossy().to_string()),
+        candidates,
+    })
+}
+
+// 插件存储文件路径：应用数据目录 plugin_storage/<plugin_id>.json，每个插件一个文件互相隔离
+fn plugin_storage_path(app: &tauri::AppHandle, plugin_id: &str) -> Result<PathBuf, String> {
+    // 只允许安全字符，防止通过 ../ 等访问其他插件或任意文件
+    let valid = !plugin_id.is_empty()
+        && plugin_id.len() <= 128
+        && !plugin_id.starts_with('.')
+        && plugin_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        && !RESERVED_FILE_NAMES.iter().any(|reserved| {
+            let stem = plugin_id.split('.').next().unwrap_or_default();
+            stem.eq_ignore_ascii_case(reserved)
+        });
+    if !valid {
+        return Err(format!("无效的插件 ID: {}", plugin_id));
+    }
+
+    app.path()
+        .app_data_dir()
+        .map(|dir| {
+            dir.join("plugin_storage")
+                .join(format!("{}.json", plugin_id))
+        })
+        .map_err(|e| format!("无法解析应用数据目录: {}", e))
+}
+
+// 读取插件存储，文件不存在时返回空对象
+fn load_plugin_storage(path: &std::path::Path) -> Result<serde_json::Map<String, Value>, String> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => {
+            serde_json::from_str(&content).map_err(|e| format!("插件存储文件格式错误: {}", e))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(serde_json::Map::new()),
+        Err(e) => Err(format!("无法读取插件存储: {}", e)),
+    }
+}
+
+// 读取插件存储中的值，不存在时返回 null
+#[command]
+pub async fn plugin_storage_get(
+    app: tauri::AppHandle,
+    write_locks: State<'_, FileWriteLocks>,
+    plugin_id: String,
+    key: String,
+) -> Result<Value, String> {
+    let path = plugin_storage_path(&app, &plugin_id)?;
+    let _guard = write_locks.lock(&path).await;
+    let mut storage = load_plugin_storage(&path)?;
+    Ok(storage.remove(&key).unwrap_or(Value::Null))
+}
+
+// 写入插件存储，value 为 null 时删除该键
+#[command]
+pub async fn plugin_storage_set(
+    app: tauri::AppHandle,
+    write_locks: State<'_, FileWriteLocks>,
+    plugin_id: String,
+    key: String,
+    value: Value,
+) -> Result<(), String> {
+    let path = plugin_storage_path(&app, &plugin_id)?;
+    // 同一插件的读改写串行执行，避免并发写入丢失数据
+    let _guard = write_locks.lock(&path).await;
+
+    let mut storage = load_plugin_storage(&path)?;
+    if value.is_null() {
+        storage.remove(&key);
+    } else {
+        storage.insert(key.clone(), value);
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("无法创建插件存储目录: {}", e))?;
+    }
+    let content =
+        serde_json::to_string_pretty(&storage).map_err(|e| format!("无法序列化插件存储: {}", e))?;
+    // 先写临时文件再替换，避免写入中途退出导致存储损坏
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, content).map_err(|e| format!("无法保存插件存储: {}", e))?;
+    std::fs::rename(&tmp_path, &path).map_err(|e| format!("无法保存插件存储: {}", e))?;
+
+    debug!("[PluginStorage] {} 写入键: {}", plugin_id, key);
+    Ok(())
+}
+
+// 单个目录的检查结果
+#[derive(Debug, Serialize)]
+pub struct DirectoryCheck {
+    pub name: String,
+    pub path: Option<String>,
+    pub ok: bool,
+    pub error: Option<String>,
+}
+
+// 应用目录检查报告
+#[derive(Debug, Serialize)]
+pub struct DirectoryReport {
+    pub all_ok: bool,
+    pub directories: Vec<DirectoryCheck>,
+}
+
+// 将文件系统错误转换为可操作的提示
+fn describe_write_error(action: &str, e: std::io::Error) -> String {
+    match e.kind() {
+        std::io::ErrorKind::PermissionDenied => format!(
+            "{}失败：没有写入权限，请检查该文件夹的权限或将应用安装到可写位置 ({})",
+            action, e
+        ),
+        std::io::ErrorKind::ReadOnlyFilesystem => {
+            format!("{}失败：所在磁盘为只读 ({})", action, e)
+        }
+        std::io::ErrorKind::StorageFull => {
+            format!("{}失败：磁盘空间不足 ({})", action, e)
+        }
+        _ => format!("{}失败: {}", action, e),
+    }
+}
+
+// 在已存在的目录中写入并删除一个测试文件，确认目录可写
+fn probe_writable_dir(dir: &std::path::Path) -> Result<(), String> {
+    if !dir.is_dir() {
+        return Err(format!("目录不存在: {}", dir.display()));
+    }
+    let probe = dir.join(format!(".write-test-{}", std::process::id()));
+    std::fs::write(&probe, b"ok").map_err(|e| describe_write_error("写入测试文件", e))?;
+    std::fs::remove_file(&probe).map_err(|e| describe_write_error("删除测试文件", e))
+}
+
+// 创建目录并确认其可写
+fn ensure_writable_dir(dir: &std::path::Path) -> Result<(), String> {
+    std::fs::create_dir_all(dir).map_err(|e| describe_write_error("创建目录", e))?;
+    probe_writable_dir(dir)
+}
+
+// 目录可写性检查结果
+#[derive(Debug, Serialize)]
+pub struct WritableCheck {
+    pub writable: bool,
+    pub error: Option<String>,
+}
+
+// 检查目录是否可写（创建并删除一个临时文件），用于在选定输出目录前提前发现问题
+#[command]
+pub async fn test_writable(path: String) -> Result<WritableCheck, String> {
+    let result = probe_writable_dir(std::path::Path::new(&path));
+    if let Err(e) = &result {
+        warn!("[TestWritable] 目录不可写 {}: {}", path, e);
+    }
+    Ok(WritableCheck {
+        writable: result.is_ok(),
+        error: result.err(),
+    })
+}
+
+// 创建并检查应用使用的所有目录（插件、输出、临时、图片缓存、角色图片）
+pub fn check_app_directories(app: &tauri::AppHandle) -> DirectoryReport {
+    let temp_root = std::env::temp_dir().join("matrix-gen");
+    let current_dir = std::env::current_dir().map_err(|e| format!("无法获取当前目录: {}", e));
+
+    let targets: Vec<(&str, Result<PathBuf, String>)> = vec![
+        ("plugins", resolve_plugins_dir(app)),
+        ("output", Ok(resolve_output_dir(app, "EnsureDirs"))),
+        ("temp", Ok(temp_root.join("temp"))),
+        ("images", Ok(temp_root.join("images"))),
+        (
+            "characters",
+            current_dir.clone().map(|dir| dir.join("characters")),
+        ),
+        (
+            "character_data",
+            current_dir.map(|dir| dir.join("data").join("characters")),
+        ),
+    ];
+
+    let directories: Vec<DirectoryCheck> = targets
+        .into_iter()
+        .map(|(name, dir)| {
+            let result = dir.and_then(|dir| ensure_writable_dir(&dir).map(|_| dir));
+            let (path, error) = match result {
+                Ok(dir) => (Some(dir.to_string_lossy().to_string()), None),
+                Err(e) => {
+                    warn!("[EnsureDirs] {} 目录不可用: {}", name, e);
+                    (None, Some(e))
+                }
+            };
+            DirectoryCheck {
+                name: name.to_string(),
+                ok: error.is_none(),
+                path,
+                error,
+            }
+        })
+        .collect();
+
+    let all_ok = directories.iter().all(|d| d.ok);
+    if all_ok {
+        info!("[EnsureDirs] 所有应用目录均可写");
+    }
+    DirectoryReport {
+        all_ok,
+        directories,
+    }
+}
+
+// 确保应用目录存在且可写，返回每个目录的检查结果（启动时也会执行一次）
+#[command]
+pub async fn ensure_app_directories(app: tauri::AppHandle) -> Result<DirectoryReport, String> {
+    Ok(check_app_directories(&app))
+}
+
+// 目录树节点（size_bytes 为包含所有子项的总大小）
+#[derive(Debug, Serialize)]
+pub struct DirectoryTreeNode {
+    pub name: String,
+    pub path: String,
+    pub size_bytes: u64,
+    pub is_dir: bool,
+    pub children: Vec<DirectoryTreeNode>,
+}
+
+// 应用管理的目录（目录树浏览只允许访问这些目录）
+fn app_managed_roots(app: &tauri::AppHandle) -> Vec<PathBuf> {
+    let mut roots = vec![
+        resolve_output_dir(app, "DirectoryTree"),
+        std::env::temp_dir().join("matrix-gen"),
+    ];
+    roots.extend(resolve_plugins_dir(app).ok());
+    roots.extend(app.path().app_config_dir().ok());
+    roots.extend(app.path().app_data_dir().ok());
+    roots.extend(app.path().app_log_dir().ok());
+    if let Ok(current_dir) = std::env::current_dir() {
+        roots.push(current_dir.join("characters"));
+        roots.push(current_dir.join("data").join("characters"));
+    }
+    roots
+}
+
+// 递归统计目录大小，depth 小于 max_depth 时保留子节点（不跟随符号链接）
+fn build_directory_tree(
+    path: &std::path::Path,
+    depth: usize,
+    max_depth: usize,
+) -> DirectoryTreeNode {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string_lossy().to_string());
+    let metadata = std::fs::symlink_metadata(path).ok();
+    let is_dir = metadata.as_ref().is_some_and(|m| m.is_dir());
+
+    let mut node = DirectoryTreeNode {
+        name,
+        path: path.to_string_lossy().to_string(),
+        size_bytes: if is_dir {
+            0
+        } else {
+            metadata.map(|m| m.len()).unwrap_or(0)
+        },
+        is_dir,
+        children: Vec::new(),
+    };
+    if !is_dir {
+        return node;
+    }
+
+    if let Ok(entries) = std::fs::read_dir(path) {
+        for entry in entries.flatten() {
+            let child = build_directory_tree(&entry.path(), depth + 1, max_depth);
+            node.size_bytes += child.size_bytes;
+            if depth < max_depth {
+                node.children.push(child);
+            }
+        }
+    }
+    node.children.sort_by(|a, b| {
+        b.size_bytes
+            .cmp(&a.size_bytes)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    node
+}
+
+// 获取应用管理目录下的目录树及各节点大小，max_depth 为返回子节点的层数
+#[command]
+pub async fn get_directory_tree(
+    app: tauri::AppHandle,
+    root: String,
+    max_depth: usize,
+) -> Result<DirectoryTreeNode, String> {
+    let root = PathBuf::from(&root)
+        .canonicalize()
+        .map_err(|e| format!("目录不存在: {} ({})", root, e))?;
+    let allowed = app_managed_roots(&app)
+        .into_iter()
+        .filter_map(|dir| dir.canonicalize().ok())
+        .any(|dir| root.starts_with(dir));
+    if !allowed {
+        return Err(format!("只能浏览应用管理的目录: {}", root.display()));
+    }
+
+    let max_depth = max_depth.min(16);
+    tokio::task::spawn_blocking(move || build_directory_tree(&root, 0, max_depth))
+        .await
+        .map_err(|e| format!("统计目录大小失败: {}", e))
+}
+
+// 官方插件修复结果
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PluginRepairStatus {
+    Restored,      // 插件文件缺失，已恢复
+    Updated,       // 插件文件内容不一致，已覆盖
+    AlreadyOk,     // 插件文件完好
+    Kept,          // 插件文件被用户修改且内置版本不更新，已保留
+    SourceMissing, // 开发模式下内置插件源文件不存在
+}
+
+// 立即重新提取官方插件（default-provider.js），无需重启应用（会覆盖用户的修改）
+#[command]
+pub async fn repair_default_plugin(app: tauri::AppHandle) -> Result<PluginRepairStatus, String> {
+    let status = crate::extract_default_plugin(&app, true)?;
+    info!("[PluginExtract] 官方插件修复结果: {:?}", status);
+    Ok(status)
+}
+
+// 迁移包中的各部分：(压缩包内前缀, 本机目录)
+fn profile_archive_sections(
+    app: &tauri::AppHandle,
+) -> Result<Vec<(&'static str, PathBuf)>, String> {
+    let config_dir = app
+        .path()
+        .app_config_dir()
+        .map_err(|e| format!("无法解析配置目录: {}", e))?;
+    let current_dir = std::env::current_dir().map_err(|e| format!("无法获取当前目录: {}", e))?;
+    Ok(vec![
+        ("config", config_dir),
+        ("plugins", resolve_plugins_dir(app)?),
+        ("characters", current_dir.join("characters")),
+        (
+            "data/characters",
+            current_dir.join("data").join("characters"),
+        ),
+    ])
+}
+
+// 是否应打包该文件：配置目录只打包顶层 json 设置文件，插件目录排除官方插件
+fn include_in_profile(section: &str, relative: &std::path::Path) -> bool {
+    match section {
+        "config" => {
+            relative.components().count() == 1
+                && relative.extension().and_then(|e| e.to_str()) == Some("json")
+        }
+        "plugins" => relative != std::path::Path::new("default-provider.js"),
+        _ => true,
+    }
+}
+
+// 导出结果
+#[derive(Debug, Serialize)]
+pub struct ProfileExportReport {
+    pub path: String,
+    pub file_count: usize,
+    pub total_bytes: u64,
+}
+
+// 将设置、插件（不含官方插件）和角色图片打包为一个 zip，用于迁移到其他电脑
+#[command]
+pub async fn export_profile(
+    app: tauri::AppHandle,
+    dest_zip: String,
+) -> Result<ProfileExportReport, String> {
+    let sections = profile_archive_sections(&app)?;
+
+    tokio::task::spawn_blocking(move || {
+        use std::io::Write;
+
+        let file =
+            std::fs::File::create(&dest_zip).map_err(|e| format!("无法创建压缩包: {}", e))?;
+        let mut zip = zip::ZipWriter::new(std::io::BufWriter::new(file));
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+
+        let mut report = ProfileExportReport {
+            path: dest_zip.clone(),
+            file_count: 0,
+            total_bytes: 0,
+        };
+        for (section, dir) in &sections {
+            let mut files = Vec::new();
+            collect_files_recursive(dir, &mut files);
+            for (path, size, _) in files {
+                let Ok(relative) = path.strip_prefix(dir) else {
+                    continue;
+                };
+                if !include_in_profile(section, relative) {
+                    continue;
+                }
+                let name = format!(
+                    "{}/{}",
+                    section,
+                    relative.to_string_lossy().replace('\\', "/")
+                );
+                let content = std::fs::read(&path)
+                    .map_err(|e| format!("无法读取 {}: {}", path.display(), e))?;
+                zip.start_file(name, options)
+                    .and_then(|_| zip.write_all(&content).map_err(Into::into))
+                    .map_err(|e| format!("写入压缩包失败: {}", e))?;
+                report.file_count += 1;
+                report.total_bytes += size;
+            }
+        }
+        zip.finish()
+            .map_err(|e| format!("写入压缩包失败: {}", e))?
+            .flush()
+            .map_err(|e| format!("写入压缩包失败: {}", e))?;
+
+        info!(
+            "[Profile] 已导出 {} 个文件 ({} bytes) 到 {}",
+            report.file_count, report.total_bytes, dest_zip
+        );
+        Ok(report)
+    })
+    .await
+    .map_err(|e| format!("导出线程异常: {}", e))?
+}
+
+// 导入结果：conflicts 为本机已存在且内容不同的文件（未覆盖时保留本机版本）
+#[derive(Debug, Default, Serialize)]
+pub struct ProfileImportReport {
+    pub imported: Vec<String>,
+    pub unchanged: Vec<String>,
+    pub conflicts: Vec<String>,
+    pub skipped: Vec<String>,
+}
+
+// 从 export_profile 生成的压缩包恢复设置、插件和角色图片；overwrite 为 true 时覆盖冲突文件
+#[command]
+pub async fn import_profile(
+    app: tauri::AppHandle,
+    zip_path: String,
+    overwrite: Option<bool>,
+) -> Result<ProfileImportReport, String> {
+    let sections = profile_archive_sections(&app)?;
+    let overwrite = overwrite.unwrap_or(false);
+
+    tokio::task::spawn_blocking(move || {
+        use std::io::Read;
+
+        let file = std::fs::File::open(&zip_path).map_err(|e| format!("无法打开压缩包: {}", e))?;
+        let mut archive = zip::ZipArchive::new(file).map_err(|e| format!("无效的压缩包: {}", e))?;
+        let mut report = ProfileImportReport::default();
+
+        for index in 0..archive.len() {
+            let mut entry = archive
+                .by_index(index)
+                .map_err(|e| format!("读取压缩包失败: {}", e))?;
+            if entry.is_dir() {
+                continue;
+            }
+            let name = entry.name().to_string();
+
+            // enclosed_name 拒绝包含 .. 或绝对路径的条目
+            let target = entry.enclosed_name().and_then(|relative_name| {
+                sections.iter().find_map(|(section, dir)| {
+                    let relative = relative_name.strip_prefix(section).ok()?;
+                    include_in_profile(section, relative).then(|| dir.join(relative))
+                })
+            });
+            let Some(target) = target else {
+                warn!("[Profile] 跳过无法识别的条目: {}", name);
+                report.skipped.push(name);
+                continue;
+            };
+
+            let mut content = Vec::new();
+            entry
+                .read_to_end(&mut content)
+                .map_err(|e| format!("读取压缩包条目 {} 失败: {}", name, e))?;
+
+            match std::fs::read(&target) {
+                Ok(existing) if existing == content => {
+                    report.unchanged.push(name);
+                    continue;
+                }
+                Ok(_) => {
+                    report.conflicts.push(name.clone());
+                    if !overwrite {
+                        continue;
+                    }
+                }
+                Err(_) => {}
+            }
+
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent).map_err(|e| format!("无法创建目录: {}", e))?;
+            }
+            std::fs::write(&target, &content)
+                .map_err(|e| format!("无法写入 {}: {}", target.display(), e))?;
+            report.imported.push(name);
+        }
+
+        info!(
+            "[Profile] 导入完成: {} 个文件已导入, {} 个冲突{}, {} 个跳过",
+            report.imported.len(),
+            report.conflicts.len(),
+            if overwrite {
+                "（已覆盖）"
+            } else {
+                "（保留本机版本）"
+            },
+            report.skipped.len()
+        );
+        Ok(report)
+    })
+    .await
+    .map_err(|e| format!("导入线程异常: {}", e))?
+}
+
+// 插件签名公钥（Ed25519，32 字节的 base64），发布构建时通过环境变量 MATRIX_PLUGIN_PUBLIC_KEY 嵌入
+const PLUGIN_SIGNING_PUBLIC_KEY: Option<&str> = option_env!("MATRIX_PLUGIN_PUBLIC_KEY");
+
+// 校验插件签名：签名位于同目录的 <文件名>.sig，内容为 64 字节原始签名或其 base64 文本
+fn verify_plugin_signature(path: &std::path::Path, content: &[u8]) -> Result<(), String> {
+    use base64::Engine;
+    use ed25519_dalek::Verifier;
+
+    let public_key = PLUGIN_SIGNING_PUBLIC_KEY.ok_or("未配置插件签名公钥")?;
+    let key_bytes: [u8; 32] = base64::engine::general_purpose::STANDARD
+        .decode(public_key.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or("插件签名公钥格式错误")?;
+    let public_key = ed25519_dalek::VerifyingKey::from_bytes(&key_bytes)
+        .map_err(|e| format!("插件签名公钥无效: {}", e))?;
+
+    let mut sig_path = path.as_os_str().to_owned();
+    sig_path.push(".sig");
+    let raw = std::fs::read(&sig_path).map_err(|_| "缺少签名文件".to_string())?;
+    let sig_bytes: [u8; 64] = match <[u8; 64]>::try_from(raw.as_slice()) {
+        Ok(bytes) => bytes,
+        Err(_) => base64::engine::general_purpose::STANDARD
+            .decode(String::from_utf8_lossy(&raw).trim())
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or("签名文件格式错误")?,
+    };
+
+    public_key
+        .verify(content, &ed25519_dalek::Signature::from_bytes(&sig_bytes))
+        .map_err(|_| "签名无效".to_string())
+}
+
+// 加载外部插件文件，strict 为 true 时跳过未签名或签名无效的插件
+#[command]
+pub async fn load_plugins_raw(
+    app: tauri::AppHandle,
+    strict: Option<bool>,
+) -> Result<Vec<String>, String> {
+    let strict = strict.unwrap_or(false);
+    let plugins_dir = resolve_plugins_dir(&app)?;
+    let disabled = load_disabled_plugins(&plugins_dir);
+
+    // 读取插件目录中的所有 .js 文件
+    let mut plugin_contents = Vec::new();
+    let entries =
+        std::fs::read_dir(&plugins_dir).map_err(|e| format!("无法读取插件目录: {}", e))?;
+
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("读取目录条目失败: {}", e))?;
+        let path = entry.path();
+
+        // 检查是否为 .js 文件
+        if path.is_file() && path.extension().and_then(|ext| ext.to_str()) == Some("js") {
+            debug!("[PluginLoader] 发现插件文件: {}", path.display());
+            if is_plugin_disabled(&disabled, &path) {
+                debug!("[PluginLoader] 跳过已禁用的插件: {}", path.display());
+                continue;
+            }
+            let content = std::fs::read_to_string(&path)
+                .map_err(|e| format!("读取插件文件 {} 失败: {}", path.display(), e))?;
+            if strict {
+                if let Err(e) = verify_plugin_signature(&path, content.as_bytes()) {
+                    warn!("[PluginLoader] 跳过插件 {}: {}", path.display(), e);
+                    continue;
+                }
+            }
+            plugin_contents.push(content);
+        }
+    }
+
+    info!(
+        "[PluginLoader] 共加载了 {} 个插件文件",
+        plugin_contents.len()
+    );
+    Ok(plugin_contents)
+}
+
+// 插件文件及其元数据、兼容性信息（id 为文件名去掉扩展名，modified 为毫秒时间戳）
+#[derive(Debug, Serialize)]
+pub struct PluginFile {
+    pub id: String,
+    pub file_name: String,
+    pub content: String,
+    pub size_bytes: u64,
+    pub modified: Option<i64>,
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub compatible: bool,
+    pub required_version: Option<String>,
+    pub current_version: String,
+}
+
+// 读取插件头部注释中的标记值（标记后的整行内容），如 "// @name 示例插件"
+fn plugin_header_tag(content: &str, tag: &str) -> Option<String> {
+    content
+        .lines()
+        .take(50)
+        .map(str::trim)
+        .filter(|line| line.starts_with("//") || line.starts_with('*') || line.starts_with("/*"))
+        .find_map(|line| {
+            let (_, rest) = line.split_once(tag)?;
+            // 避免 @name 匹配到 @name-xxx 之类的其他标记
+            if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+                return None;
+            }
+            let value = rest.trim().trim_end_matches("*/").trim();
+            (!value.is_empty()).then(|| value.to_string())
+        })
+}
+
+// 读取插件头部注释中声明的最低应用版本，如 "// @min-app-version 1.2.0"
+fn plugin_min_app_version(content: &str) -> Option<String> {
+    plugin_header_tag(content, "@min-app-version")
+        .and_then(|value| value.split_whitespace().next().map(str::to_string))
+}
+
+// 读取插件名称与版本：优先使用开头注释块中的 JSON（如 /* {"name": "...", "version": "..."} */），
+// 否则使用 // @name 与 // @version 标记
+fn plugin_metadata(content: &str) -> (Option<String>, Option<String>) {
+    let header: Option<Value> = content
+        .trim_start()
+        .strip_prefix("/*")
+        .and_then(|rest| rest.split_once("*/"))
+        .and_then(|(block, _)| serde_json::from_str(block.trim_start_matches('*').trim()).ok());
+    let json_field = |key: &str| {
+        header
+            .as_ref()
+            .and_then(|json| json.get(key))
+            .and_then(|v| v.as_str())
+            .map(str::to_string)
+    };
+
+    let name = json_field("name").or_else(|| plugin_header_tag(content, "@name"));
+    let version = json_field("version").or_else(|| {
+        plugin_header_tag(content, "@version")
+            .and_then(|value| value.split_whitespace().next().map(str::to_string))
+    });
+    (name, version)
+}
+
+// 读取插件声明的版本：优先使用头部元数据，否则读取清单对象中的 version: "x.y.z"
+pub(crate) fn plugin_declared_version(content: &str) -> Option<String> {
+    plugin_metadata(content).1.or_else(|| {
+        content.lines().take(100).find_map(|line| {
+            let value = line.trim().strip_prefix("version:")?;
+            let value = value.trim().trim_end_matches(',').trim_matches(['"', '\'']);
+            (!value.is_empty()).then(|| value.to_string())
+        })
+    })
+}
+
+// 解析 x.y.z 版本号（忽略 v 前缀及预发布/构建后缀，缺少的部分按 0 处理）
+pub(crate) fn parse_app_version(version: &str) -> Option<(u64, u64, u64)> {
+    let core = version
+        .trim()
+        .trim_start_matches('v')
+        .split(['-', '+'])
+        .next()?;
+    let mut parts = core.split('.').map(|p| p.parse::<u64>());
+    let major = parts.next()?.ok()?;
+    let minor = parts.next().unwrap_or(Ok(0)).ok()?;
+    let patch = parts.next().unwrap_or(Ok(0)).ok()?;
+    Some((major, minor, patch))
+}
+
+// 加载插件文件并检查 @min-app-version，skip_incompatible 为 true 时跳过不兼容的插件
+// strict 为 true 时跳过未签名或签名无效的插件
+#[command]
+pub async fn load_plugins(
+    app: tauri::AppHandle,
+    skip_incompatible: Option<bool>,
+    strict: Option<bool>,
+) -> Result<Vec<PluginFile>, String> {
+    let plugins_dir = resolve_plugins_dir(&app)?;
+    let current_version = app.package_info().version.to_string();
+
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(&plugins_dir)
+        .map_err(|e| format!("无法读取插件目录: {}", e))?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file() && path.extension().and_then(|ext| ext.to_str()) == Some("js")
+        })
+        .collect();
+    paths.sort();
+
+    let disabled = load_disabled_plugins(&plugins_dir);
+    let mut plugins = Vec::new();
+    for path in paths {
+        if is_plugin_disabled(&disabled, &path) {
+            debug!("[PluginLoader] 跳过已禁用的插件: {}", path.display());
+            continue;
+        }
+        let plugin = read_plugin_file(&path, &current_version)?;
+        if strict.unwrap_or(false) {
+            if let Err(e) = verify_plugin_signature(&path, plugin.content.as_bytes()) {
+                warn!("[PluginLoader] 跳过插件 {}: {}", path.display(), e);
+                continue;
+            }
+        }
+        if !plugin.compatible {
+            warn!(
+                "[PluginLoader] 插件 {} 需要应用版本 {}，当前为 {}",
+                plugin.file_name,
+                plugin.required_version.as_deref().unwrap_or_default(),
+                current_version
+            );
+            if skip_incompatible.unwrap_or(false) {
+                continue;
+            }
+        }
+        plugins.push(plugin);
+    }
+
+    info!("[PluginLoader] 共加载了 {} 个插件文件", plugins.len());
+    Ok(plugins)
+}
+
+// 按 ID（文件名去掉 .js）加载单个插件，用于编辑后重新加载
+// strict 为 true 时插件未签名或签名无效则返回错误
+#[command]
+pub async fn load_plugin(
+    app: tauri::AppHandle,
+    id: String,
+    strict: Option<bool>,
+) -> Result<PluginFile, String> {
+    validate_plugin_file_id(&id)?;
+
+    let plugins_dir = resolve_plugins_dir(&app)?;
+    let path = plugins_dir.join(format!("{}.js", id));
+    if !path.is_file() {
+        return Err(format!("插件不存在: {}", id));
+    }
+    // 已禁用的插件不参与热重载
+    if is_plugin_disabled(&load_disabled_plugins(&plugins_dir), &path) {
+        return Err(format!("插件已禁用: {}", id));
+    }
+
+    let current_version = app.package_info().version.to_string();
+    let plugin = read_plugin_file(&path, &current_version)?;
+    if strict.unwrap_or(false) {
+        verify_plugin_signature(&path, plugin.content.as_bytes())
+            .map_err(|e| format!("插件 {} 签名校验失败: {}", id, e))?;
+    }
+    info!("[PluginLoader] 已加载插件: {}", plugin.file_name);
+    Ok(plugin)
+}
+
+// 校验插件文件 ID：禁止路径分隔符与 ..，防止访问插件目录之外的文件
+fn validate_plugin_file_id(id: &str) -> Result<(), String> {
+    if id.is_empty() || id.contains(['/', '\\', '\0']) || id.contains("..") {
+        return Err(format!("无效的插件 ID: {}", id));
+    }
+    Ok(())
+}
+
+// 读取插件文件并解析元数据与兼容性
+fn read_plugin_file(path: &std::path::Path, current_version: &str) -> Result<PluginFile, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("读取插件文件 {} 失败: {}", path.display(), e))?;
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let id = path
+        .file_stem()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let metadata = std::fs::metadata(path).ok();
+    let modified = metadata
+        .as_ref()
+        .and_then(|m| m.modified().ok())
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as i64);
+    let (name, version) = plugin_metadata(&content);
+    let required_version = plugin_min_app_version(&content);
+
+    // 无法解析的版本声明视为不兼容，避免加载后出现难以理解的错误
+    let compatible = match required_version.as_deref() {
+        None => true,
+        Some(required) => match (
+            parse_app_version(required),
+            parse_app_version(current_version),
+        ) {
+            (Some(required), Some(current)) => current >= required,
+            _ => false,
+        },
+    };
+
+    Ok(PluginFile {
+        id,
+        file_name,
+        size_bytes: metadata.map(|m| m.len()).unwrap_or(content.len() as u64),
+        content,
+        modified,
+        name,
+        version,
+        compatible,
+        required_version,
+        current_version: current_version.to_string(),
+    })
+}
+
+// 插件目录监听器（用于插件热重载）
+#[derive(Default)]
+pub struct PluginWatcher(pub Mutex<Option<notify::RecommendedWatcher>>);
+
+// 插件文件变化事件（kind: created / modified / removed）
+#[derive(Debug, Clone, Serialize)]
+pub struct PluginChangedEvent {
+    pub id: String,
+    pub kind: &'static str,
+}
+
+// 插件变化事件合并窗口（编辑器保存时常连续触发多次写入）
+const PLUGIN_WATCH_DEBOUNCE_MS: u64 = 300;
+
+// 开始监听插件目录，.js 文件变化时发送 plugin-changed 事件
+#[command]
+pub fn watch_plugins(
+    app: tauri::AppHandle,
+    state: State<'_, PluginWatcher>,
+) -> Result<String, String> {
+    use notify::Watcher;
+    use std::sync::mpsc::RecvTimeoutError;
+
+    let mut guard = state.0.lock().map_err(|e| e.to_string())?;
+    let plugins_dir = resolve_plugins_dir(&app)?;
+    let plugins_path = plugins_dir.to_string_lossy().to_string();
+
+    if guard.is_some() {
+        info!("[PluginWatch] 监听已在运行: {}", plugins_path);
+        return Ok(plugins_path);
+    }
+
+    let (tx, rx) = std::sync::mpsc::channel::<notify::Result<notify::Event>>();
+    let mut watcher =
+        notify::recommended_watcher(tx).map_err(|e| format!("无法创建目录监听器: {}", e))?;
+    watcher
+        .watch(&plugins_dir, notify::RecursiveMode::NonRecursive)
+        .map_err(|e| format!("无法监听插件目录: {}", e))?;
+
+    // 后台线程：合并编辑器保存时的突发事件后再发送，监听器被释放后 channel 关闭，线程自动退出
+    std::thread::spawn(move || {
+        let debounce = std::time::Duration::from_millis(PLUGIN_WATCH_DEBOUNCE_MS);
+        // 路径 -> 合并窗口内是否出现过创建事件
+        let mut pending: HashMap<PathBuf, bool> = HashMap::new();
+
+        loop {
+            let received = if pending.is_empty() {
+                rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            } else {
+                rx.recv_timeout(debounce)
+            };
+
+            match received {
+                Ok(Ok(event)) => {
+                    use notify::EventKind;
+                    let created = match event.kind {
+                        EventKind::Create(_)
+                        | EventKind::Modify(notify::event::ModifyKind::Name(_)) => true,
+                        EventKind::Modify(_) | EventKind::Remove(_) => false,
+                        _ => continue,
+                    };
+                    for path in event.paths {
+                        if path.extension().and_then(|ext| ext.to_str()) == Some("js") {
+                            *pending.entry(path).or_default() |= created;
+                        }
+                    }
+                }
+                Ok(Err(e)) => warn!("[PluginWatch] 监听错误: {}", e),
+                Err(RecvTimeoutError::Timeout) => {
+                    // 以合并窗口结束时的实际状态为准
+                    for (path, created) in pending.drain() {
+                        let kind = if !path.exists() {
+                            "removed"
+                        } else if created {
+                            "created"
+                        } else {
+                            "modified"
+                        };
+                        let id = path
+                            .file_stem()
+                            .map(|n| n.to_string_lossy().to_string())
+                            .unwrap_or_default();
+                        debug!("[PluginWatch] 插件 {} {}", id, kind);
+                        if let Err(e) = app.emit("plugin-changed", PluginChangedEvent { id, kind })
+                        {
+                            warn!("[PluginWatch] 发送事件失败: {}", e);
+                        }
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        info!("[PluginWatch] 监听线程已退出");
+    });
+
+    *guard = Some(watcher);
+    info!("[PluginWatch] 开始监听插件目录: {}", plugins_path);
+    Ok(plugins_path)
+}
+
+// 停止监听插件目录
+#[command]
+pub fn stop_watching_plugins(state: State<'_, PluginWatcher>) -> Result<(), String> {
+    let mut guard = state.0.lock().map_err(|e| e.to_string())?;
+    if guard.take().is_some() {
+        info!("[PluginWatch] 已停止监听插件目录");
+    }
+    Ok(())
+}
+
+// 应用退出时释放插件目录监听器
+pub fn stop_plugin_watcher(app: &tauri::AppHandle) {
+    if let Ok(mut guard) = app.state::<PluginWatcher>().0.lock() {
+        guard.take();
+    }
+}
+
+// 读取已禁用的插件 ID（插件目录下的 disabled.json），文件缺失或格式错误时视为全部启用
+fn load_disabled_plugins(plugins_dir: &std::path::Path) -> std::collections::HashSet<String> {
+    let path = plugins_dir.join("disabled.json");
+    match std::fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+            warn!("[PluginLoader] disabled.json 格式错误，视为全部启用: {}", e);
+            Default::default()
+        }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Default::default(),
+        Err(e) => {
+            warn!("[PluginLoader] 无法读取 disabled.json，视为全部启用: {}", e);
+            Default::default()
+        }
+    }
+}
+
+fn is_plugin_disabled(
+    disabled: &std::collections::HashSet<String>,
+    path: &std::path::Path,
+) -> bool {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .is_some_and(|id| disabled.contains(id))
+}
+
+// 启用或禁用插件（不删除插件文件）
+#[command]
+pub async fn set_plugin_enabled(
+    app: tauri::AppHandle,
+    write_locks: State<'_, FileWriteLocks>,
+    id: String,
+    enabled: bool,
+) -> Result<(), String> {
+    validate_plugin_file_id(&id)?;
+    let plugins_dir = resolve_plugins_dir(&app)?;
+    let path = plugins_dir.join("disabled.json");
+    // 读改写串行执行，避免并发修改丢失数据
+    let _guard = write_locks.lock(&path).await;
+
+    let mut disabled = load_disabled_plugins(&plugins_dir);
+    let changed = if enabled {
+        disabled.remove(&id)
+    } else {
+        disabled.insert(id.clone())
+    };
+    if !changed {
+        return Ok(());
+    }
+
+    let mut ids: Vec<&String> = disabled.iter().collect();
+    ids.sort();
+    let content =
+        serde_json::to_string_pretty(&ids).map_err(|e| format!("无法序列化插件状态: {}", e))?;
+    // 先写临时文件再替换，避免写入中途退出导致文件损坏
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, content).map_err(|e| format!("无法保存插件状态: {}", e))?;
+    std::fs::rename(&tmp_path, &path).map_err(|e| format!("无法保存插件状态: {}", e))?;
+
+    info!(
+        "[PluginLoader] 插件 {} 已{}",
+        id,
+        if enabled { "启用" } else { "禁用" }
+    );
+    Ok(())
+}
+
+// 插件列表项（不含文件内容）
+#[derive(Debug, Serialize)]
+pub struct PluginSummary {
+    pub id: String,
+    pub file_name: String,
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub enabled: bool,
+}
+
+// 列出插件目录中的所有插件及其启用状态
+#[command]
+pub async fn list_plugins(app: tauri::AppHandle) -> Result<Vec<PluginSummary>, String> {
+    let plugins_dir = resolve_plugins_dir(&app)?;
+    let disabled = load_disabled_plugins(&plugins_dir);
+
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(&plugins_dir)
+        .map_err(|e| format!("无法读取插件目录: {}", e))?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.is_file() && path.extension().and_then(|ext| ext.to_str()) == Some("js")
+        })
+        .collect();
+    paths.sort();
+
+    let plugins = paths
+        .into_iter()
+        .map(|path| {
+            let content = std::fs::read_to_string(&path).unwrap_or_default();
+            PluginSummary {
+                id: path
+                    .file_stem()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                file_name: path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                name: plugin_metadata(&content).0,
+                version: plugin_declared_version(&content),
+                enabled: !is_plugin_disabled(&disabled, &path),
+            }
+        })
+        .collect();
+    Ok(plugins)
+}
+
+// 插件试运行选项
+#[derive(Debug, Deserialize)]
+pub struct TestPluginRequestOptions {
+    pub plugin_file: String, // 插件文件名，如 "test-provider.js"
+    pub sample_input: Value, // 传给 createRequest 的参数
+}
+
+// 插件试运行的循环次数与递归深度上限，防止插件死循环卡住后台线程
+const PLUGIN_TEST_LOOP_LIMIT: u64 = 1_000_000;
+const PLUGIN_TEST_RECURSION_LIMIT: usize = 512;
+
+// 在内嵌 JS 引擎中执行插件的 createRequest，返回生成的请求（不会真正发送）
+#[command]
+pub async fn test_plugin_request(
+    app: tauri::AppHandle,
+    options: TestPluginRequestOptions,
+) -> Result<RequestOptions, String> {
+    let TestPluginRequestOptions {
+        plugin_file,
+        sample_input,
+    } = options;
+
+    if plugin_file.is_empty() || plugin_file.contains(['/', '\\']) || plugin_file.contains("..") {
+        return Err(format!("无效的插件文件名: {}", plugin_file));
+    }
+
+    let plugin_path = resolve_plugins_dir(&app)?.join(&plugin_file);
+    let source = std::fs::read_to_string(&plugin_path)
+        .map_err(|e| format!("读取插件文件 {} 失败: {}", plugin_path.display(), e))?;
+
+    info!("[PluginTest] 试运行插件: {}", plugin_file);
+
+    // boa 的 Context 不是 Send，放到阻塞线程中执行
+    let output =
+        tokio::task::spawn_blocking(move || run_plugin_create_request(&source, &sample_input))
+            .await
+            .map_err(|e| format!("插件执行线程异常: {}", e))??;
+
+    debug!("[PluginTest] createRequest 返回: {}", output);
+
+    serde_json::from_str::<RequestOptions>(&output)
+        .map_err(|e| format!("createRequest 返回的请求结构无效: {} ({})", e, output))
+}
+
+// 执行插件脚本并调用 plugin.createRequest(input)，返回 JSON 字符串
+fn run_plugin_create_request(source: &str, input: &Value) -> Result<String, String> {
+    use boa_engine::{Context, Source};
+
+    // 输入经两次序列化后作为 JS 字符串字面量嵌入，避免注入
+    let input_literal = serde_json::to_string(&input.to_string()).map_err(|e| e.to_string())?;
+    let script = format!(
+        "var console = {{ log: function() {{}}, info: function() {{}}, warn: function() {{}}, error: function() {{}} }};\n\
+         {}\n\
+         ;(function() {{\n\
+             if (typeof plugin === 'undefined' || typeof plugin.createRequest !== 'function') {{\n\
+                 throw new Error('插件未定义 plugin.createRequest');\n\
+             }}\n\
+             return JSON.stringify(plugin.createRequest(JSON.parse({})));\n\
+         }})()",
+        source, input_literal
+    );
+
+    let mut context = Context::default();
+    context
+        .runtime_limits_mut()
+        .set_loop_iteration_limit(PLUGIN_TEST_LOOP_LIMIT);
+    context
+        .runtime_limits_mut()
+        .set_recursion_limit(PLUGIN_TEST_RECURSION_LIMIT);
+
+    let result = context
+        .eval(Source::from_bytes(script.as_bytes()))
+        .map_err(|e| format!("插件执行失败: {}", e))?;
+
+    result
+        .as_string()
+        .map(|s| s.to_std_string_escaped())
+        .ok_or_else(|| "createRequest 未返回对象".to_string())
+}
+
+// 获取输出目录路径
+#[command]
+pub fn get_output_path() -> Result<String, String> {
+    let current_dir = std::env::current_dir().map_err(|e| format!("获取当前目录失败: {}", e))?;
+
+    // Normalize path separators for cross-platform compatibility
+    let output_path = current_dir.to_string_lossy().to_string().replace('\\', "/");
+    Ok(output_path)
+}
+
+// 创建日志监视器窗口
+#[command]
+pub async fn create_log_monitor_window(app: tauri::AppHandle) -> Result<(), String> {
+    use tauri::webview::WebviewWindowBuilder;
+
+    // 检查窗口是否已经存在
+    if let Some(window) = app.get_webview_window("log-monitor") {
+        // 窗口已存在，确保显示并聚焦到前台
+        window
+            .unminimize()
+            .map_err(|e| format!("Failed to unminimize log monitor window: {}", e))?;
+        window
+            .show()
+            .map_err(|e| format!("Failed to show log monitor window: {}", e))?;
+        window
+            .set_focus()
+            .map_err(|e| format!("Failed to focus log monitor window: {}", e))?;
+        info!("[LogMonitor] 窗口已存在，已显示到前台");
+        return Ok(());
+    }
+
+    // 窗口不存在，创建新窗口
+    let window = WebviewWindowBuilder::new(
+        &app,
+        "log-monitor",
+        tauri::WebviewUrl::App("/log-monitor".into()),
+    )
+    .title("MatrixGen Pro - Console")
+    .inner_size(800.0, 600.0)
+    .resizable(true)
+    .always_on_top(false)
+    .position(100.0, 100.0)
+    .decorations(true)
+    .transparent(false)
+    .build()
+    .map_err(|e| format!("Failed to create log monitor window: {}", e))?;
+
+    info!("[LogMonitor] 创建了新的日志监视器窗口");
+    Ok(())
+}
+
+// 阿里云 OSS 上传功能已迁移到 Supabase Storage，前端直接使用 Supabase SDK
+
+// 清理报告（dry_run 时只统计将被删除的内容，不实际删除）
+#[derive(Debug, Default, Serialize)]
+pub struct CleanupReport {
+    pub dry_run: bool,
+    pub deleted_count: u64,
+    pub freed_bytes: u64,
+    pub skipped_count: u64, // 因过新或属于其他实例而保留的文件数
+    pub paths: Vec<String>,
+}
+
+impl CleanupReport {
+    fn record(&mut self, path: &std::path::Path, size: u64) {
+        self.deleted_count += 1;
+        self.freed_bytes += size;
+        self.paths.push(path.to_string_lossy().to_string());
+    }
+}
+
+// 实例锁目录：每个运行中的实例写入 instance-<pid>.lock，清理时据此避让其他实例的文件
+fn instance_lock_dir() -> PathBuf {
+    std::env::temp_dir().join("matrix-gen").join("instances")
+}
+
+fn instance_lock_path() -> PathBuf {
+    instance_lock_dir().join(format!("instance-{}.lock", std::process::id()))
+}
+
+// 创建当前实例的锁文件（内容为启动时间的 Unix 秒数）
+pub fn acquire_instance_lock() -> Result<(), String> {
+    let lock_dir = instance_lock_dir();
+    std::fs::create_dir_all(&lock_dir).map_err(|e| format!("无法创建实例锁目录: {}", e))?;
+
+    let started_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let lock_path = instance_lock_path();
+    std::fs::write(&lock_path, started_at.to_string())
+        .map_err(|e| format!("无法写入实例锁文件: {}", e))?;
+
+    info!("[InstanceLock] 已创建实例锁: {}", lock_path.display());
+    Ok(())
+}
+
+// 删除当前实例的锁文件（应用退出时调用）
+pub fn release_instance_lock() {
+    let lock_path = instance_lock_path();
+    if lock_path.exists() {
+        if let Err(e) = std::fs::remove_file(&lock_path) {
+            warn!("[InstanceLock] 删除实例锁失败: {}", e);
+        }
+    }
+}
+
+// 检查进程是否仍在运行
+fn is_process_alive(pid: u32) -> bool {
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+        std::process::Command::new("tasklist")
+            .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+            .creation_flags(CREATE_NO_WINDOW)
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).contains(&pid.to_string()))
+            .unwrap_or(false)
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    {
+        std::process::Command::new("kill")
+            .args(["-0", &pid.to_string()])
+            .stderr(std::process::Stdio::null())
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false)
+    }
+}
+
+// 返回其他存活实例中最早的启动时间，顺便删除已退出实例遗留的锁文件
+fn oldest_live_instance_start() -> Option<std::time::SystemTime> {
+    let own_pid = std::process::id();
+    let mut oldest: Option<std::time::SystemTime> = None;
+
+    let entries = std::fs::read_dir(instance_lock_dir()).ok()?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let pid = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.strip_prefix("instance-"))
+            .and_then(|s| s.parse::<u32>().ok());
+
+        let Some(pid) = pid else { continue };
+        if pid == own_pid {
+            continue;
+        }
+
+        if !is_process_alive(pid) {
+            info!("[InstanceLock] 删除失效的实例锁: {}", path.display());
+            let _ = std::fs::remove_file(&path);
+            continue;
+        }
+
+        let started_at = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|s| s.trim().parse::<u64>().ok())
+            .map(|secs| std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs))
+            .or_else(|| entry.metadata().and_then(|m| m.modified()).ok());
+
+        if let Some(started_at) = started_at {
+            oldest = Some(oldest.map_or(started_at, |o| o.min(started_at)));
+        }
+    }
+
+    oldest
+}
+
+// 清理目录的辅助函数
+// skip_newer_than: 修改时间不早于该时间的文件会被保留
+// keep_dirs: 整个跳过的子目录（由调用方单独清理）
+// 返回目录内容是否已全部清空（或 dry_run 时将被全部清空）
+fn cleanup_directory(
+    dir: &std::path::Path,
+    dry_run: bool,
+    skip_newer_than: Option<std::time::SystemTime>,
+    keep_dirs: &[PathBuf],
+    report: &mut CleanupReport,
+) -> Result<bool, String> {
+    if !dir.exists() {
+        return Ok(true);
+    }
+
+    let lock_dir = instance_lock_dir();
+    let mut cleared = true;
+
+    for entry in std::fs::read_dir(dir).map_err(|e| format!("Unable to read directory: {}", e))? {
+        let entry = entry.map_err(|e| format!("Failed to read directory entry: {}", e))?;
+        let path = entry.path();
+
+        // 实例锁由 oldest_live_instance_start 单独维护
+        if path == lock_dir || keep_dirs.contains(&path) {
+            cleared = false;
+            continue;
+        }
+
+        if path.is_dir() {
+            // 递归清理子目录，仅在子目录已清空时删除
+            if !cleanup_directory(&path, dry_run, skip_newer_than, keep_dirs, report)? {
+                cleared = false;
+                continue;
+            }
+            if dry_run {
+                report.record(&path, 0);
+            } else if let Err(e) = std::fs::remove_dir(&path) {
+                cleared = false;
+                warn!(
+                    "[Cleanup] Failed to remove directory {}: {}",
+                    path.display(),
+                    e
+                );
+            } else {
+                report.record(&path, 0);
+            }
+        } else {
+            let metadata = std::fs::metadata(&path).ok();
+
+            // 跳过可能属于其他运行中实例的文件
+            if let Some(cutoff) = skip_newer_than {
+                let modified = metadata.as_ref().and_then(|m| m.modified().ok());
+                if !modified.is_some_and(|m| m < cutoff) {
+                    cleared = false;
+                    report.skipped_count += 1;
+                    continue;
+                }
+            }
+
+            // 删除文件
+            let size = metadata.map(|m| m.len()).unwrap_or(0);
+            if dry_run {
+                report.record(&path, size);
+            } else if let Err(e) = std::fs::remove_file(&path) {
+                cleared = false;
+                warn!("[Cleanup] Failed to remove file {}: {}", path.display(), e);
+            } else {
+                report.record(&path, size);
+            }
+        }
+    }
+
+    Ok(cleared)
+}
+
+// 崩溃恢复窗口：启动清理时保留 temp 目录中该时间内写入的文件，供 list_recoverable_temp_files 找回
+const TEMP_RECOVERY_WINDOW_SECS: u64 = 24 * 60 * 60;
+
+// 启动清理默认只删除超过该时长未修改的文件，保留上一实例或可续传下载仍可能需要的文件
+const DEFAULT_TEMP_CLEANUP_MAX_AGE_SECS: u64 = 6 * 60 * 60;
+
+// write_temp_file_binary / download_file 写入的临时文件目录
+fn recoverable_temp_dir() -> PathBuf {
+    std::env::temp_dir().join("matrix-gen").join("temp")
+}
+
+// temp 子目录的清理截止时间：保留恢复窗口内（含进行中或可续传的下载）以及其他实例启动后写入的文件
+fn recovery_cutoff(skip_newer_than: Option<std::time::SystemTime>) -> std::time::SystemTime {
+    let recovery_cutoff =
+        std::time::SystemTime::now() - std::time::Duration::from_secs(TEMP_RECOVERY_WINDOW_SECS);
+    skip_newer_than.map_or(recovery_cutoff, |c| c.min(recovery_cutoff))
+}
+
+// 执行临时文件清理并返回报告
+// spare_recent: 保留恢复窗口内的临时文件（启动时使用，避免删除崩溃前未保存的生成结果）
+// max_age_secs: 只删除修改时间早于该时长的文件，None 时不按年龄过滤
+fn run_temp_cleanup(
+    dry_run: bool,
+    spare_recent: bool,
+    max_age_secs: Option<u64>,
+) -> Result<CleanupReport, String> {
+    info!(
+        "[Cleanup] Starting temp file cleanup{}...",
+        if dry_run { " (dry run)" } else { "" }
+    );
+
+    let mut report = CleanupReport {
+        dry_run,
+        ..Default::default()
+    };
+
+    // 获取临时目录
+    let temp_dir = std::env::temp_dir().join("matrix-gen");
+
+    // 如果临时目录不存在，直接返回
+    if !temp_dir.exists() {
+        info!("[Cleanup] Temp directory doesn't exist, skipping cleanup");
+        return Ok(report);
+    }
+
+    // 若有其他实例正在运行，只删除早于其启动时间的文件
+    let mut skip_newer_than = oldest_live_instance_start();
+    if skip_newer_than.is_some() {
+        info!("[Cleanup] Another running instance detected, keeping files newer than its start");
+    }
+
+    // 按年龄过滤时取两个截止时间中较早者
+    if let Some(max_age_secs) = max_age_secs {
+        let age_cutoff = std::time::SystemTime::now()
+            .checked_sub(std::time::Duration::from_secs(max_age_secs))
+            .unwrap_or(std::time::UNIX_EPOCH);
+        skip_newer_than = Some(skip_newer_than.map_or(age_cutoff, |c| c.min(age_cutoff)));
+        info!(
+            "[Cleanup] Keeping files modified within the last {} seconds",
+            max_age_secs
+        );
+    }
+
+    // 先按恢复窗口单独清理 temp 子目录
+    let mut keep_dirs = Vec::new();
+    if spare_recent {
+        let recovery_dir = recoverable_temp_dir();
+        cleanup_directory(
+            &recovery_dir,
+            dry_run,
+            Some(recovery_cutoff(skip_newer_than)),
+            &[],
+            &mut report,
+        )?;
+        keep_dirs.push(recovery_dir);
+    }
+
+    // 清理主临时目录（包含 temp、images 等子目录）
+    cleanup_directory(&temp_dir, dry_run, skip_newer_than, &keep_dirs, &mut report)?;
+
+    info!(
+        "[Cleanup] Cleanup completed: {} {} items, total size {} bytes",
+        if dry_run { "would remove" } else { "removed" },
+        report.deleted_count,
+        report.freed_bytes
+    );
+    Ok(report)
+}
+
+// 清理临时文件的函数，只删除修改时间早于 max_age_secs 的文件
+pub fn cleanup_temp_files(max_age_secs: u64, dry_run: bool) -> Result<CleanupReport, String> {
+    run_temp_cleanup(dry_run, true, Some(max_age_secs))
+}
+
+// 启动时清理临时文件（使用保守的默认年龄阈值）
+pub fn cleanup_temp_files_on_startup() -> Result<CleanupReport, String> {
+    cleanup_temp_files(DEFAULT_TEMP_CLEANUP_MAX_AGE_SECS, false)
+}
+
+// 按需清理超过 max_age_secs 未修改的临时文件并返回报告，dry_run 为 true 时仅统计将释放的空间
+#[command]
+pub async fn cleanup_temp_files_cmd(
+    max_age_secs: u64,
+    dry_run: bool,
+) -> Result<CleanupReport, String> {
+    run_temp_cleanup(dry_run, false, Some(max_age_secs))
+}
+
+// 清理临时文件并返回报告，dry_run 为 true 时仅预览将被删除的内容
+#[command]
+pub async fn cleanup_temp_files_report(dry_run: bool) -> Result<CleanupReport, String> {
+    run_temp_cleanup(dry_run, false, None)
+}
+
+// 仅清理图片缓存目录（cache_image 写入的 images），不影响 temp 中进行中的下载
+#[command]
+pub async fn clear_image_cache() -> Result<CleanupReport, String> {
+    let images_dir = std::env::temp_dir().join("matrix-gen").join("images");
+    let mut report = CleanupReport::default();
+
+    // 与全量清理一致，避让其他运行中实例刚写入的文件
+    let skip_newer_than = oldest_live_instance_start();
+    cleanup_directory(&images_dir, false, skip_newer_than, &[], &mut report)?;
+
+    info!(
+        "[Cleanup] Image cache cleared: removed {} items, total size {} bytes",
+        report.deleted_count, report.freed_bytes
+    );
+    Ok(report)
+}
+
+// 可恢复的临时文件
+#[derive(Debug, Serialize)]
+pub struct RecoverableTempFile {
+    pub path: String,
+    pub name: String,
+    pub size: u64,
+    pub modified_unix: Option<i64>,
+    pub media_type: String,
+}
+
+// 列出恢复窗口内写入 temp 目录的图片/视频文件（最新的在前）
+#[command]
+pub async fn list_recoverable_temp_files() -> Result<Vec<RecoverableTempFile>, String> {
+    tokio::task::spawn_blocking(|| {
+        let cutoff = std::time::SystemTime::now()
+            - std::time::Duration::from_secs(TEMP_RECOVERY_WINDOW_SECS);
+        let mut files = Vec::new();
+        collect_files_recursive(&recoverable_temp_dir(), &mut files);
+
+        let mut recoverable: Vec<(std::time::SystemTime, RecoverableTempFile)> = files
+            .into_iter()
+            .filter(|(_, size, modified)| *size > 0 && *modified >= cutoff)
+            .filter_map(|(path, size, modified)| {
+                let media_type = infer_media_type(&path);
+                if media_type == "other" {
+                    return None;
+                }
+                let file = RecoverableTempFile {
+                    name: path.file_name()?.to_string_lossy().to_string(),
+                    path: path.to_string_lossy().to_string(),
+                    size,
+                    modified_unix: modified
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .ok()
+                        .map(|d| d.as_secs() as i64),
+                    media_type: media_type.to_string(),
+                };
+                Some((modified, file))
+            })
+            .collect();
+        recoverable.sort_by(|a, b| b.0.cmp(&a.0));
+        recoverable.into_iter().map(|(_, file)| file).collect()
+    })
+    .await
+    .map_err(|e| format!("扫描临时目录失败: {}", e))
+}
+
+// 将临时文件移动到输出目录，返回新路径
+#[command]
+pub async fn recover_temp_file(
+    app: tauri::AppHandle,
+    write_locks: State<'_, FileWriteLocks>,
+    confirmations: State<'_, WriteConfirmations>,
+    temp_path: String,
+    output_name: String,
+) -> Result<String, String> {
+    let source = PathBuf::from(&temp_path)
+        .canonicalize()
+        .map_err(|e| format!("临时文件不存在: {}", e))?;
+    // 只允许恢复 temp 目录中的文件
+    let temp_root = recoverable_temp_dir()
+        .canonicalize()
+        .map_err(|e| format!("无法访问临时目录: {}", e))?;
+    if !source.starts_with(&temp_root) || !source.is_file() {
+        return Err(format!("不是可恢复的临时文件: {}", temp_path));
+    }
+
+    // 与 write_output_bytes 相同：写入确认、创建目录，同名时自动追加序号
+    let media_type = infer_media_type(&source);
+    let size = std::fs::metadata(&source).map(|m| m.len()).unwrap_or(0);
+    let requested =
+        prepare_output_path(&app, &confirmations, &output_name, media_type, None, size).await?;
+    let _write_guard = write_locks.lock(&requested).await;
+    let (_, target) = create_unique_file(&requested).map_err(|e| format!("无法恢复文件: {}", e))?;
+
+    // 覆盖预留的占位文件；跨磁盘时 rename 会失败，改为复制后删除
+    if std::fs::rename(&source, &target).is_err() {
+        if let Err(e) = std::fs::copy(&source, &target) {
+            let _ = std::fs::remove_file(&target);
+            return Err(format!("无法恢复文件: {}", e));
+        }
+        if let Err(e) = std::fs::remove_file(&source) {
+            warn!("[Recover] 删除临时文件失败 {}: {}", source.display(), e);
+        }
+    }
+
+    let target_str = target.to_string_lossy().to_string();
+    info!("[Recover] 已恢复临时文件: {} -> {}", temp_path, target_str);
+    spawn_output_mirror(&app, target);
+    Ok(target_str)
+}
+
+// 定时缓存维护设置（持久化到配置目录 cache_maintenance.json，启动时自动恢复）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheMaintenanceOptions {
+    pub interval_minutes: u64,
+    pub max_age_hours: Option<u64>,
+    pub max_total_bytes: Option<u64>,
+}
+
+// 当前运行的缓存维护任务
+#[derive(Default)]
+pub struct CacheMaintenance(Mutex<Option<CancellationToken>>);
+
+// 递归收集目录下的所有文件 (路径, 大小, 修改时间)，跳过实例锁目录
+fn collect_files_recursive(
+    dir: &std::path::Path,
+    files: &mut Vec<(PathBuf, u64, std::time::SystemTime)>,
+) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let lock_dir = instance_lock_dir();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path == lock_dir {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if metadata.is_dir() {
+            collect_files_recursive(&path, files);
+        } else {
+            let modified = metadata.modified().unwrap_or(std::time::UNIX_EPOCH);
+            files.push((path, metadata.len(), modified));
+        }
+    }
+}
+
+// 按年龄与总大小清理缓存：先删除超龄文件，总大小仍超限时从最旧的文件开始删除
+// 与启动清理使用相同的保护规则：跳过其他运行中实例的文件与恢复窗口内的临时文件
+fn run_cache_maintenance(options: &CacheMaintenanceOptions) -> CleanupReport {
+    let mut report = CleanupReport::default();
+    let mut files = Vec::new();
+    collect_files_recursive(&std::env::temp_dir().join("matrix-gen"), &mut files);
+    files.sort_by_key(|(_, _, modified)| *modified);
+
+    let age_cutoff = options.max_age_hours.and_then(|hours| {
+        std::time::SystemTime::now()
+            .checked_sub(std::time::Duration::from_secs(hours.saturating_mul(3600)))
+    });
+    // 受保护的文件计入总大小，但不会被删除
+    let mut total_bytes: u64 = files.iter().map(|(_, size, _)| size).sum();
+    let skip_newer_than = oldest_live_instance_start();
+    let recovery_dir = recoverable_temp_dir();
+    let recovery_dir_cutoff = recovery_cutoff(skip_newer_than);
+    files.retain(|(path, _, modified)| {
+        let cutoff = if path.starts_with(&recovery_dir) {
+            Some(recovery_dir_cutoff)
+        } else {
+            skip_newer_than
+        };
+        let protected = cutoff.is_some_and(|cutoff| *modified >= cutoff);
+        if protected {
+            report.skipped_count += 1;
+        }
+        !protected
+    });
+
+    for (path, size, modified) in &files {
+        let expired = age_cutoff.is_some_and(|cutoff| *modified < cutoff);
+        let over_budget = options.max_total_bytes.is_some_and(|max| total_bytes > max);
+        // 文件按修改时间从旧到新排序，之后的文件都未超龄且总大小已达标
+        if !expired && !over_budget {
+            break;
+        }
+
+        match std::fs::remove_file(path) {
+            Ok(()) => {
+                total_bytes -= size;
+                report.record(path, *size);
+            }
+            Err(e) => warn!(
+                "[CacheMaintenance] Failed to remove file {}: {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+
+    report
+}
+
+// 维护间隔与最长保留时间的上限（一年），避免换算为秒时溢出
+const CACHE_MAINTENANCE_MAX_HOURS: u64 = 365 * 24;
+
+fn validate_cache_maintenance_options(options: &CacheMaintenanceOptions) -> Result<(), String> {
+    if options.interval_minutes == 0 {
+        return Err("维护间隔必须大于 0 分钟".to_string());
+    }
+    if options.interval_minutes > CACHE_MAINTENANCE_MAX_HOURS * 60 {
+        return Err(format!(
+            "维护间隔不能超过 {} 分钟",
+            CACHE_MAINTENANCE_MAX_HOURS * 60
+        ));
+    }
+    if options
+        .max_age_hours
+        .is_some_and(|hours| hours > CACHE_MAINTENANCE_MAX_HOURS)
+    {
+        return Err(format!(
+            "max_age_hours 不能超过 {} 小时",
+            CACHE_MAINTENANCE_MAX_HOURS
+        ));
+    }
+    Ok(())
+}
+
+fn cache_maintenance_settings_path(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_config_dir()
+        .map(|dir| dir.join("cache_maintenance.json"))
+        .map_err(|e| format!("无法解析配置目录: {}", e))
+}
+
+// 启动后台维护任务（替换已有任务）
+fn spawn_cache_maintenance(state: &CacheMaintenance, options: CacheMaintenanceOptions) {
+    let token = CancellationToken::new();
+    if let Ok(mut current) = state.0.lock() {
+        if let Some(previous) = current.replace(token.clone()) {
+            previous.cancel();
+        }
+    }
+
+    tauri::async_runtime::spawn(async move {
+        let interval = std::time::Duration::from_secs(options.interval_minutes.saturating_mul(60));
+        loop {
+            let pass_options = options.clone();
+            match tokio::task::spawn_blocking(move || run_cache_maintenance(&pass_options)).await {
+                Ok(report) if report.deleted_count > 0 => info!(
+                    "[CacheMaintenance] Removed {} files, {} bytes",
+                    report.deleted_count, report.freed_bytes
+                ),
+                Ok(_) => debug!("[CacheMaintenance] Nothing to clean"),
+                Err(e) => warn!("[CacheMaintenance] Maintenance pass failed: {}", e),
+            }
+
+            tokio::select! {
+                _ = token.cancelled() => break,
+                _ = tokio::time::sleep(interval) => {}
+            }
+        }
+        debug!("[CacheMaintenance] Maintenance task stopped");
+    });
+}
+
+// 启动定时缓存维护，并保存设置以便下次启动时恢复
+#[command]
+pub fn start_cache_maintenance(
+    app: tauri::AppHandle,
+    state: State<'_, CacheMaintenance>,
+    options: CacheMaintenanceOptions,
+) -> Result<(), String> {
+    validate_cache_maintenance_options(&options)?;
+    if options.max_age_hours.is_none() && options.max_total_bytes.is_none() {
+        return Err("至少需要设置 max_age_hours 或 max_total_bytes".to_string());
+    }
+
+    let path = cache_maintenance_settings_path(&app)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("无法创建配置目录: {}", e))?;
+    }
+    let content =
+        serde_json::to_string_pretty(&options).map_err(|e| format!("无法序列化设置: {}", e))?;
+    std::fs::write(&path, content).map_err(|e| format!("无法保存维护设置: {}", e))?;
+
+    info!(
+        "[CacheMaintenance] Scheduled every {} min (max age: {:?} h, max size: {:?} bytes)",
+        options.interval_minutes, options.max_age_hours, options.max_total_bytes
+    );
+    spawn_cache_maintenance(&state, options);
+    Ok(())
+}
+
+// 停止定时缓存维护并删除保存的设置
+#[command]
+pub fn stop_cache_maintenance(
+    app: tauri::AppHandle,
+    state: State<'_, CacheMaintenance>,
+) -> Result<bool, String> {
+    let stopped = match state.0.lock() {
+        Ok(mut current) => current.take().map(|token| token.cancel()).is_some(),
+        Err(_) => false,
+    };
+
+    let path = cache_maintenance_settings_path(&app)?;
+    if path.exists() {
+        std::fs::remove_file(&path).map_err(|e| format!("无法删除维护设置: {}", e))?;
+    }
+
+    info!("[CacheMaintenance] Maintenance stopped");
+    Ok(stopped)
+}
+
+// 启动时恢复已保存的缓存维护计划（在 setup 中调用）
+pub fn resume_cache_maintenance(app: &tauri::AppHandle) -> Result<(), String> {
+    let path = cache_maintenance_settings_path(app)?;
+    let Ok(content) = std::fs::read_to_string(&path) else {
+        return Ok(());
+    };
+    let options: CacheMaintenanceOptions =
+        serde_json::from_str(&content).map_err(|e| format!("维护设置格式错误: {}", e))?;
+    validate_cache_maintenance_options(&options)?;
+
+    info!(
+        "[CacheMaintenance] Resuming schedule: every {} min",
+        options.interval_minutes
+    );
+    spawn_cache_maintenance(&app.state::<CacheMaintenance>(), options);
+    Ok(())
+}
+
+// 在文件管理器中打开文件夹并选中文件
+#[command]
+pub async fn show_in_folder(path: String) -> Result<(), String> {
+    // Log the attempt
+    info!("[Rust] Attempting to open path: {}", path);
+
+    #[cfg(target_os = "windows")]
+    {
+        // Windows trick: "explorer /select, path" opens the folder AND highlights the file
+        // If it's just a folder, just "explorer path"
+        let path_obj = std::path::Path::new(&path);
+        if path_obj.is_file() {
+            std::process::Command::new("explorer")
+                .args(["/select,", &path]) // Comma is important for /select
+                .spawn()
+                .map_err(|e| format!("Failed to open file in explorer: {}", e))?;
+        } else {
+            std::process::Command::new("explorer")
+                .arg(&path)
+                .spawn()
+                .map_err(|e| format!("Failed to open folder in explorer: {}", e))?;
+        }
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    {
+        // Fallback for Mac/Linux (just open it)
+        open::that(&path).map_err(|e| format!("Failed to open path: {}", e))?;
+    }
+
+    Ok(())
+}
+
+// 创建目录（如不存在）并在文件管理器中打开
+async fn reveal_app_dir(dir: Result<PathBuf, tauri::Error>, tag: &str) -> Result<String, String> {
+    let dir = dir.map_err(|e| format!("无法解析目录: {}", e))?;
+    std::fs::create_dir_all(&dir).map_err(|e| format!("无法创建目录: {}", e))?;
+
+    let dir_str = dir.to_string_lossy().to_string();
+    info!("[{}] 打开目录: {}", tag, dir_str);
+    show_in_folder(dir_str.clone()).await?;
+    Ok(dir_str)
+}
+
+// 打开应用数据目录（角色图片、日志等）
+#[command]
+pub async fn open_app_data_folder(app: tauri::AppHandle) -> Result<String, String> {
+    reveal_app_dir(app.path().app_data_dir(), "AppDataFolder").await
+}
+
+// 打开应用配置目录（settings.json 等）
+#[command]
+pub async fn open_config_folder(app: tauri::AppHandle) -> Result<String, String> {
+    reveal_app_dir(app.path().app_config_dir(), "ConfigFolder").await
+}
+
+// 默认允许同时进行的生成任务数
+const DEFAULT_MAX_CONCURRENT_GENERATIONS: usize = 3;
+
+// 生成队列状态
+#[derive(Debug, Clone, Serialize)]
+pub struct GenerationState {
+    pub running: usize,
+    pub queued: usize,
+    pub paused: bool,
+    pub max_concurrent: usize,
+}
+
+// 生成队列：限制并发生成数量，暂停时不再发放新的名额，进行中的任务不受影响
+pub struct GenerationQueue {
+    state: Mutex<GenerationState>,
+    changed: tokio::sync::Notify,
+}
+
+impl Default for GenerationQueue {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(GenerationState {
+                running: 0,
+                queued: 0,
+                paused: false,
+                max_concurrent: DEFAULT_MAX_CONCURRENT_GENERATIONS,
+            }),
+            changed: tokio::sync::Notify::new(),
+        }
+    }
+}
+
+// 排队计数守卫：等待被取消时自动减少排队数
+struct QueuedGeneration<'a>(&'a GenerationQueue);
+
+impl Drop for QueuedGeneration<'_> {
+    fn drop(&mut self) {
+        let mut state = self.0.state();
+        state.queued = state.queued.saturating_sub(1);
+    }
+}
+
+impl GenerationQueue {
+    fn state(&self) -> std::sync::MutexGuard<'_, GenerationState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // 有空闲名额且未暂停时占用一个名额
+    fn try_acquire(&self) -> bool {
+        let mut state = self.state();
+        if state.paused || state.running >= state.max_concurrent {
+            return false;
+        }
+        state.running += 1;
+        true
+    }
+
+    // 等待直到获得名额
+    async fn acquire(&self) {
+        self.state().queued += 1;
+        let _queued = QueuedGeneration(self);
+        loop {
+            // 先登记通知再检查状态，避免错过释放通知
+            let notified = self.changed.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if self.try_acquire() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    fn release(&self) {
+        {
+            let mut state = self.state();
+            state.running = state.running.saturating_sub(1);
+        }
+        self.changed.notify_waiters();
+    }
+
+    fn set_paused(&self, paused: bool) {
+        self.state().paused = paused;
+        self.changed.notify_waiters();
+    }
+}
+
+// 等待并占用一个生成名额（队列暂停或已满时排队），完成后需调用 release_generation_lock
+#[command]
+pub async fn acquire_generation_slot(queue: State<'_, GenerationQueue>) -> Result<(), String> {
+    queue.acquire().await;
+    Ok(())
+}
+
+// 检查生成锁：有空闲名额时立即占用并返回 true，否则返回 false
+#[command]
+pub fn check_generation_lock(queue: State<'_, GenerationQueue>) -> Result<bool, String> {
+    Ok(queue.try_acquire())
+}
+
+// 释放生成名额
+#[command]
+pub fn release_generation_lock(queue: State<'_, GenerationQueue>) -> Result<(), String> {
+    queue.release();
+    Ok(())
+}
+
+// 暂停发放新的生成名额，进行中的任务继续执行
+#[command]
+pub fn pause_generations(queue: State<'_, GenerationQueue>) -> GenerationState {
+    queue.set_paused(true);
+    info!("[GenerationQueue] 已暂停新的生成任务");
+    queue.state().clone()
+}
+
+// 恢复发放生成名额
+#[command]
+pub fn resume_generations(queue: State<'_, GenerationQueue>) -> GenerationState {
+    queue.set_paused(false);
+    info!("[GenerationQueue] 已恢复生成任务");
+    queue.state().clone()
+}
+
+// 设置最大并发生成数
+#[command]
+pub fn set_max_concurrent_generations(
+    queue: State<'_, GenerationQueue>,
+    max_concurrent: usize,
+) -> Result<GenerationState, String> {
+    if max_concurrent == 0 {
+        return Err("最大并发数必须大于 0".to_string());
+    }
+    queue.state().max_concurrent = max_concurrent;
+    queue.changed.notify_waiters();
+    info!("[GenerationQueue] 最大并发数: {}", max_concurrent);
+    Ok(queue.state().clone())
+}
+
+// 获取生成队列状态
+#[command]
+pub fn generation_state(queue: State<'_, GenerationQueue>) -> GenerationState {
+    queue.state().clone()
+}
+
+// 下载进度事件（download_id 用于区分并发下载）
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadProgressEvent {
+    pub download_id: Option<String>,
+    pub file_name: String,
+    pub downloaded: u64,
+    pub total: Option<u64>,
+}
+
+// 下载进度推送目标
+struct DownloadProgress {
+    app: tauri::AppHandle,
+    download_id: Option<String>,
+}
+
+// 两次进度事件之间的最小间隔
+const DOWNLOAD_PROGRESS_INTERVAL_MS: u64 = 100;
+
+// 带 download_id 的进行中下载（用于单独取消）
+#[derive(Default)]
+pub struct ActiveDownloads(Mutex<HashMap<String, CancellationToken>>);
+
+impl ActiveDownloads {
+    fn downloads(&self) -> std::sync::MutexGuard<'_, HashMap<String, CancellationToken>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+// 下载登记守卫：下载结束时自动注销
+struct DownloadRegistration<'a> {
+    downloads: &'a ActiveDownloads,
+    id: String,
+    token: CancellationToken,
+}
+
+impl Drop for DownloadRegistration<'_> {
+    fn drop(&mut self) {
+        // 已取消的下载已被 cancel_download 移除，避免误删复用同一 ID 的新下载
+        if !self.token.is_cancelled() {
+            self.downloads.downloads().remove(&self.id);
+        }
+    }
+}
+
+// 下载文件到本地临时目录，下载过程中发送 download-progress 事件
+// 传入 download_id 时可通过 cancel_download 取消，取消后返回 CANCELLED_ERROR
+// 传入 expected_sha256 时校验下载内容，不一致则删除文件并返回错误
+// 目标文件已存在时尝试断点续传，返回是否续传与最终大小
+#[command]
+pub async fn download_file(
+    app: tauri::AppHandle,
+    operations: State<'_, ActiveOperations>,
+    downloads: State<'_, ActiveDownloads>,
+    url: String,
+    file_name: String,
+    download_id: Option<String>,
+    expected_sha256: Option<String>,
+) -> Result<DownloadResult, String> {
+    let registration = match download_id.clone() {
+        Some(id) => {
+            let token = CancellationToken::new();
+            match downloads.downloads().entry(id.clone()) {
+                std::collections::hash_map::Entry::Occupied(_) => {
+                    return Err(format!("下载 ID 已在使用中: {}", id));
+                }
+                std::collections::hash_map::Entry::Vacant(entry) => {
+                    entry.insert(token.clone());
+                }
+            }
+            Some(DownloadRegistration {
+                downloads: &downloads,
+                id,
+                token,
+            })
+        }
+        None => None,
+    };
+    let cancel = registration.as_ref().map(|r| r.token.clone());
+
+    let write_locks = app.state::<FileWriteLocks>();
+    let progress = DownloadProgress {
+        app: app.clone(),
+        download_id,
+    };
+    operations
+        .run(download_file_impl(
+            &write_locks,
+            url,
+            file_name,
+            Some(progress),
+            cancel,
+            expected_sha256,
+        ))
         .await
-        .map_err(|e| format!("无法启动服务器: {}", e))?;
+}
 
-    println!("[FileServer] 已在 {} 启动文件服务器", addr);
+// 取消指定 download_id 的下载
+#[command]
+pub fn cancel_download(
+    downloads: State<'_, ActiveDownloads>,
+    download_id: String,
+) -> Result<(), String> {
+    let token = downloads
+        .downloads()
+        .remove(&download_id)
+        .ok_or_else(|| format!("没有进行中的下载: {}", download_id))?;
+    token.cancel();
+    info!("[Download] 已取消下载: {}", download_id);
+    Ok(())
+}
 
-    // 在后台任务中处理请求
-    let path_clone = path.clone();
-    tokio::spawn(async move {
+async fn download_file_impl(
+    write_locks: &FileWriteLocks,
+    url: String,
+    file_name: String,
+    progress: Option<DownloadProgress>,
+    cancel: Option<CancellationToken>,
+    expected_sha256: Option<String>,
+) -> Result<DownloadResult, String> {
+    let expected = expected_sha256
+        .map(|hash| hash.trim().to_string())
+        .filter(|hash| !hash.is_empty());
+    let result = stream_download(
+        write_locks,
+        url,
+        file_name,
+        expected.is_some(),
+        true,
+        progress,
+        cancel,
+    )
+    .await?;
+
+    if let (Some(expected), Some(actual)) = (expected, result.sha256.as_deref()) {
+        if !expected.eq_ignore_ascii_case(actual) {
+            error!(
+                "[Download] SHA-256 校验失败: {} (期望 {}, 实际 {})",
+                result.path, expected, actual
+            );
+            let _ = std::fs::remove_file(&result.path);
+            return Err(format!(
+                "SHA-256 校验失败: 期望 {}, 实际 {}",
+                expected, actual
+            ));
+        }
+    }
+
+    Ok(result)
+}
+
+// 下载结果（sha256 为小写十六进制，仅在需要时计算；resumed 表示是否断点续传）
+#[derive(Debug, Serialize)]
+pub struct DownloadResult {
+    pub path: String,
+    pub size: u64,
+    pub sha256: Option<String>,
+    pub resumed: bool,
+}
+
+// 可续传下载的记录（<文件名>.part.json）：只有 URL 一致时才续传，validator 用作 If-Range
+#[derive(Debug, Serialize, Deserialize)]
+struct PartialDownloadInfo {
+    url: String,
+    validator: Option<String>, // 强 ETag 或 Last-Modified
+}
+
+// 从响应中取出可用于 If-Range 的校验值（弱 ETag 不能用于 If-Range）
+fn download_validator(headers: &reqwest::header::HeaderMap) -> Option<String> {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|v: &reqwest::header::HeaderValue| v.to_str().ok())
+            .map(|v| v.trim().to_string())
+    };
+    header(reqwest::header::ETAG)
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| header(reqwest::header::LAST_MODIFIED))
+}
+
+// 读取与当前 URL 对应的续传记录，URL 不一致或记录缺失时返回 None
+fn load_partial_download(info_path: &std::path::Path, url: &str) -> Option<PartialDownloadInfo> {
+    let content = std::fs::read_to_string(info_path).ok()?;
+    serde_json::from_str::<PartialDownloadInfo>(&content)
+        .ok()
+        .filter(|info| info.url == url)
+}
+
+// 边下载边写入临时文件，compute_hash 为 true 时同时计算 SHA-256，避免下载后再完整读取一次
+// 下载过程中写入 <文件名>.part，完成后重命名；resume 时只从本 URL 留下的 .part 续传
+async fn stream_download(
+    write_locks: &FileWriteLocks,
+    url: String,
+    file_name: String,
+    compute_hash: bool,
+    resume: bool,
+    progress: Option<DownloadProgress>,
+    cancel: Option<CancellationToken>,
+) -> Result<DownloadResult, String> {
+    use sha2::Digest;
+
+    let cache_dir = std::env::temp_dir().join("matrix-gen").join("temp");
+
+    // 确保目录存在
+    if let Err(e) = std::fs::create_dir_all(&cache_dir) {
+        error!("[Download] 创建缓存目录失败: {}", e);
+        return Err(format!("无法创建缓存目录: {}", e));
+    }
+
+    let file_path = cache_dir.join(&file_name);
+    let file_path_str = file_path.to_string_lossy().to_string();
+    let part_path = cache_dir.join(format!("{}.part", file_name));
+    let info_path = cache_dir.join(format!("{}.part.json", file_name));
+
+    // 同名下载共用 .part 与续传记录，整个下载期间串行执行，避免读取对方进行中的续传信息并交错写入
+    let _write_guard = write_locks.lock(&part_path).await;
+    let _permit = acquire_network_permit().await?;
+
+    info!("[Download] 开始下载到临时文件: {}", file_path_str);
+
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(480)) // 8 分钟请求超时
+        .connect_timeout(std::time::Duration::from_secs(300)) // 5 分钟连接超时
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+
+    // 已有同一 URL 留下的部分文件时请求剩余字节；资源已变化时服务器按 If-Range 返回完整内容
+    let partial = if resume {
+        load_partial_download(&info_path, &url)
+    } else {
+        None
+    };
+    let existing_len = match &partial {
+        Some(_) => tokio::fs::metadata(&part_path)
+            .await
+            .map(|m| m.len())
+            .unwrap_or(0),
+        None => 0,
+    };
+    let mut request = client.get(&url);
+    if existing_len > 0 {
+        request = request.header(reqwest::header::RANGE, format!("bytes={}-", existing_len));
+        if let Some(validator) = partial.as_ref().and_then(|p| p.validator.as_deref()) {
+            request = request.header(reqwest::header::IF_RANGE, validator);
+        }
+    }
+    let mut response = request
+        .send()
+        .await
+        .map_err(|e| format!("Failed to download file: {}", e))?;
+
+    // 范围无效（通常是本地文件已损坏或比远端大）时重新完整下载
+    if existing_len > 0 && response.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+        warn!("[Download] 服务器拒绝续传范围，重新下载: {}", file_path_str);
+        response = client
+            .get(&url)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to download file: {}", e))?;
+    }
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "Download failed with status: {}",
+            response.status()
+        ));
+    }
+
+    // 仅在服务器返回 206 时追加，否则截断重新下载
+    let resumed = existing_len > 0 && response.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+    if resume && !resumed {
+        // 记录本次下载的 URL 与校验值，供中断后续传时确认 .part 属于同一资源
+        let info = PartialDownloadInfo {
+            url: url.clone(),
+            validator: download_validator(response.headers()),
+        };
+        if let Err(e) = serde_json::to_string(&info)
+            .map_err(|e| e.to_string())
+            .and_then(|content| std::fs::write(&info_path, content).map_err(|e| e.to_string()))
+        {
+            warn!("[Download] 无法写入续传记录: {}", e);
+        }
+    }
+    let mut hasher = compute_hash.then(sha2::Sha256::new);
+    let mut file = if resumed {
+        info!(
+            "[Download] 从 {} bytes 处续传: {}",
+            existing_len, file_path_str
+        );
+        let mut file = tokio::fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&part_path)
+            .await
+            .map_err(|e| format!("Failed to write file: {}", e))?;
+        // 续传时已有部分也要计入哈希
+        if let Some(hasher) = hasher.as_mut() {
+            let mut buf = vec![0u8; 64 * 1024];
+            loop {
+                let n = file
+                    .read(&mut buf)
+                    .await
+                    .map_err(|e| format!("Failed to read file: {}", e))?;
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&buf[..n]);
+            }
+        }
+        file
+    } else {
+        tokio::fs::File::create(&part_path)
+            .await
+            .map_err(|e| format!("Failed to write file: {}", e))?
+    };
+    let mut size = if resumed { existing_len } else { 0 };
+    let total = response.content_length().map(|len| len + size);
+    let mut last_emit: Option<std::time::Instant> = None;
+    let mut emit_progress = |downloaded: u64, force: bool| {
+        let Some(progress) = progress.as_ref() else {
+            return;
+        };
+        let due = last_emit.is_none_or(|t| {
+            t.elapsed() >= std::time::Duration::from_millis(DOWNLOAD_PROGRESS_INTERVAL_MS)
+        });
+        if !force && !due {
+            return;
+        }
+        last_emit = Some(std::time::Instant::now());
+        let event = DownloadProgressEvent {
+            download_id: progress.download_id.clone(),
+            file_name: file_name.clone(),
+            downloaded,
+            total,
+        };
+        if let Err(e) = progress.app.emit("download-progress", event) {
+            warn!("[Download] 发送 download-progress 事件失败: {}", e);
+        }
+    };
+
+    let cancel = cancel.unwrap_or_default();
+    let write_result: Result<(), String> = async {
         loop {
-            if let Ok((mut stream, addr)) = listener.accept().await {
-                println!("[FileServer] 收到来自 {} 的请求", addr);
+            let chunk = tokio::select! {
+                chunk = response.chunk() => chunk,
+                _ = cancel.cancelled() => {
+                    info!("[Download] 下载已取消: {}", file_name);
+                    return Err(CANCELLED_ERROR.to_string());
+                }
+            };
+            let Some(chunk) = chunk.map_err(|e| format!("Failed to read response body: {}", e))?
+            else {
+                break;
+            };
+            if let Some(hasher) = hasher.as_mut() {
+                hasher.update(&chunk);
+            }
+            file.write_all(&chunk)
+                .await
+                .map_err(|e| format!("Failed to write file: {}", e))?;
+            size += chunk.len() as u64;
+            emit_progress(size, false);
+        }
+        emit_progress(size, true);
+        file.flush()
+            .await
+            .map_err(|e| format!("Failed to write file: {}", e))
+    }
+    .await;
+
+    // 下载失败时删除不完整的文件；可续传的下载在中断时保留，供下次续传
+    let write_result = write_result.and_then(|_| {
+        drop(file);
+        std::fs::rename(&part_path, &file_path).map_err(|e| format!("Failed to write file: {}", e))
+    });
+    if let Err(e) = write_result {
+        if resume && e != CANCELLED_ERROR {
+            warn!(
+                "[Download] 下载中断，保留部分文件以便续传: {} ({} bytes)",
+                file_path_str, size
+            );
+        } else {
+            let _ = std::fs::remove_file(&part_path);
+            let _ = std::fs::remove_file(&info_path);
+        }
+        return Err(e);
+    }
+    let _ = std::fs::remove_file(&info_path);
+
+    info!(
+        "[Download] 文件下载成功: {} ({} bytes)",
+        file_path_str, size
+    );
+
+    Ok(DownloadResult {
+        path: file_path_str,
+        size,
+        sha256: hasher.map(|h| hex::encode(h.finalize())),
+        resumed,
+    })
+}
+
+// 下载文件到本地临时目录，并在下载过程中计算 SHA-256（与 hash_file 结果一致）
+#[command]
+pub async fn download_file_hashed(
+    operations: State<'_, ActiveOperations>,
+    write_locks: State<'_, FileWriteLocks>,
+    url: String,
+    file_name: String,
+) -> Result<DownloadResult, String> {
+    operations
+        .run(stream_download(
+            &write_locks,
+            url,
+            file_name,
+            true,
+            false,
+            None,
+            None,
+        ))
+        .await
+}
+
+// 流式计算文件的 SHA-256（小写十六进制）
+fn sha256_file(path: &std::path::Path) -> Result<String, String> {
+    use sha2::Digest;
+
+    let mut file = std::fs::File::open(path).map_err(|e| format!("无法打开文件: {}", e))?;
+    let mut hasher = sha2::Sha256::new();
+    std::io::copy(&mut file, &mut hasher).map_err(|e| format!("无法读取文件: {}", e))?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+// 计算文件的 SHA-256（小写十六进制）
+#[command]
+pub async fn hash_file(path: String) -> Result<String, String> {
+    tokio::task::spawn_blocking(move || sha256_file(std::path::Path::new(&path)))
+        .await
+        .map_err(|e| format!("哈希线程异常: {}", e))?
+}
+
+// 取消所有进行中的网络操作（下载、上传、代理请求、轮询）
+#[command]
+pub fn abort_all_operations(operations: State<'_, ActiveOperations>) -> Result<usize, String> {
+    let cancelled = operations.cancel_all();
+    info!("[Operations] 已取消 {} 个进行中的操作", cancelled);
+    Ok(cancelled)
+}
+
+// 系统提示音
+#[derive(Debug, Clone, Serialize)]
+pub struct NotificationSound {
+    pub name: String,
+    pub path: String,
+}
+
+// 各平台系统提示音所在目录及支持的扩展名
+fn notification_sound_dirs() -> Vec<(PathBuf, &'static [&'static str])> {
+    let mut dirs: Vec<(PathBuf, &'static [&'static str])> = Vec::new();
+
+    #[cfg(target_os = "windows")]
+    {
+        let windir = std::env::var_os("WINDIR").unwrap_or_else(|| "C:\\Windows".into());
+        dirs.push((PathBuf::from(windir).join("Media"), &["wav"]));
+    }
+
+    #[cfg(target_os = "macos")]
+    {
+        dirs.push((PathBuf::from("/System/Library/Sounds"), &["aiff", "aif"]));
+        if let Some(home) = std::env::var_os("HOME") {
+            dirs.push((
+                PathBuf::from(home).join("Library").join("Sounds"),
+                &["aiff", "aif", "wav"],
+            ));
+        }
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    {
+        dirs.push((
+            PathBuf::from("/usr/share/sounds/freedesktop/stereo"),
+            &["oga", "ogg", "wav"],
+        ));
+    }
+
+    dirs
+}
+
+// 列出可用的系统提示音（名称为不含扩展名的文件名，同名时先找到的优先）
+#[command]
+pub fn list_notification_sounds() -> Vec<NotificationSound> {
+    let mut sounds: Vec<NotificationSound> = Vec::new();
+    for (dir, extensions) in notification_sound_dirs() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut found: Vec<NotificationSound> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
+                    .and_then(|e| e.to_str())
+                    .is_some_and(|e| extensions.contains(&e.to_ascii_lowercase().as_str()))
+            })
+            .filter_map(|path| {
+                let name = path.file_stem()?.to_str()?.to_string();
+                Some(NotificationSound {
+                    name,
+                    path: path.to_string_lossy().to_string(),
+                })
+            })
+            .filter(|sound| {
+                !sounds
+                    .iter()
+                    .any(|s| s.name.eq_ignore_ascii_case(&sound.name))
+            })
+            .collect();
+        found.sort_by_key(|sound| sound.name.to_lowercase());
+        sounds.extend(found);
+    }
+    sounds
+}
+
+// 播放指定名称的系统提示音，播放结束后返回
+#[command]
+pub async fn play_sound(name: String) -> Result<(), String> {
+    let sound = list_notification_sounds()
+        .into_iter()
+        .find(|sound| sound.name.eq_ignore_ascii_case(&name))
+        .ok_or_else(|| format!("未找到提示音: {}", name))?;
+
+    play_sound_file(sound.path).await
+}
+
+// 通知提示音类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SoundKind {
+    Success,
+    Error,
+    Done,
+    Warning,
+}
 
-                let file_path = path_clone.clone();
-                tokio::spawn(async move {
-                    let mut buffer = [0u8; 1024];
-                    if let Ok(n) = stream.read(&mut buffer).await {
-                        if n > 0 {
-                            let request = String::from_utf8_lossy(&buffer[..n]);
-                            println!(
-                                "[FileServer] 请求内容: {}",
-                                request.lines().next().unwrap_or("")
-                            );
+impl SoundKind {
+    // 各平台对应的系统提示音名称，按优先级排列
+    fn candidates(self) -> &'static [&'static str] {
+        #[cfg(target_os = "windows")]
+        {
+            match self {
+                SoundKind::Success | SoundKind::Done => {
+                    &["notify", "Windows Notify System Generic"]
+                }
+                SoundKind::Error => &["Windows Error", "Windows Critical Stop"],
+                SoundKind::Warning => &["Windows Exclamation", "Windows Background"],
+            }
+        }
 
-                            // 读取文件并返回
-                            if let Ok(content) = std::fs::read(&file_path) {
-                                let header = format!(
-                                    "HTTP/1.1 200 OK\r\nContent-Type: video/mp4\r\nContent-Length: {}\r\nAccess-Control-Allow-Origin: *\r\n\r\n",
-                                    content.len()
-                                );
+        #[cfg(target_os = "macos")]
+        {
+            match self {
+                SoundKind::Success => &["Glass", "Ping"],
+                SoundKind::Done => &["Hero", "Glass"],
+                SoundKind::Error => &["Basso", "Sosumi"],
+                SoundKind::Warning => &["Funk", "Tink"],
+            }
+        }
 
-                                if let Ok(_) = stream.write_all(header.as_bytes()).await {
-                                    let _ = stream.write_all(&content).await;
-                                }
-                            } else {
-                                let response = "HTTP/1.1 404 Not Found\r\n\r\n";
-                                let _ = stream.write_all(response.as_bytes()).await;
-                            }
-                        }
-                    }
-                });
+        #[cfg(all(unix, not(target_os = "macos")))]
+        {
+            match self {
+                SoundKind::Success => &["complete", "message"],
+                SoundKind::Done => &["complete", "message-new-instant"],
+                SoundKind::Error => &["dialog-error", "bell"],
+                SoundKind::Warning => &["dialog-warning", "bell"],
             }
         }
-    });
+    }
+}
 
-    Ok(format!("http://127.0.0.1:{}", port))
+// 播放通知提示音（成功、失败、完成、警告），取当前平台第一个可用的系统提示音
+#[command]
+pub async fn play_notification_sound(kind: SoundKind) -> Result<(), String> {
+    let sounds = list_notification_sounds();
+    let sound = kind
+        .candidates()
+        .iter()
+        .find_map(|name| sounds.iter().find(|s| s.name.eq_ignore_ascii_case(name)))
+        .ok_or_else(|| format!("未找到可用的提示音: {:?}", kind))?;
+
+    play_sound_file(sound.path.clone()).await
 }
 
-// 文件上传指令 - 支持多种图床和代理
+// 在阻塞线程中播放音频文件，播放结束后返回
+async fn play_sound_file(path: String) -> Result<(), String> {
+    debug!("[Sound] 播放提示音: {}", path);
+    tokio::task::spawn_blocking(move || {
+        let (_stream, handle) = rodio::OutputStream::try_default()
+            .map_err(|e| format!("无法打开音频输出设备: {}", e))?;
+        let sink =
+            rodio::Sink::try_new(&handle).map_err(|e| format!("无法创建音频播放器: {}", e))?;
+        let file = std::fs::File::open(&path).map_err(|e| format!("无法打开提示音文件: {}", e))?;
+        let source = rodio::Decoder::new(std::io::BufReader::new(file))
+            .map_err(|e| format!("无法解码提示音: {}", e))?;
+        sink.append(source);
+        sink.sleep_until_end();
+        Ok(())
+    })
+    .await
+    .map_err(|e| format!("提示音播放线程异常: {}", e))?
+}
+
+// 打开输出文件夹
 #[command]
-pub async fn upload_file(options: UploadOptions) -> Result<UploadResponse, String> {
-    // 检查文件是否存在
-    let file_path = options.file_path.clone();
-    let metadata = std::fs::metadata(&file_path).map_err(|e| format!("无法读取文件: {}", e))?;
+pub fn open_output_folder(app: tauri::AppHandle) -> Result<(), String> {
+    // 获取输出目录路径（与write_output_file使用相同的逻辑）
+    let output_dir = resolve_output_dir(&app, "OpenFolder");
 
-    if !metadata.is_file() {
-        return Ok(UploadResponse {
-            success: false,
-            url: None,
-            error: Some("指定的路径不是文件".to_string()),
-        });
+    // 确保目录存在
+    if let Err(e) = std::fs::create_dir_all(&output_dir) {
+        error!("[OpenFolder] 创建目录失败: {}", e);
+        return Err(format!("无法创建输出目录: {}", e));
     }
 
-    // 读取文件内容
-    let file_content = std::fs::read(&file_path).map_err(|e| format!("无法读取文件: {}", e))?;
+    let output_path = output_dir.to_string_lossy().to_string();
+    info!("[OpenFolder] 打开输出文件夹: {}", output_path);
 
-    // 获取文件名
-    let file_name: String = std::path::Path::new(&file_path)
-        .file_name()
-        .and_then(|n| n.to_str().map(|s| s.to_string()))
-        .unwrap_or_else(|| "file.mp4".to_string());
+    // 在文件管理器中打开文件夹
+    #[cfg(target_os = "windows")]
+    {
+        std::process::Command::new("explorer")
+            .arg(&output_path)
+            .spawn()
+            .map_err(|e| format!("Failed to open folder in explorer: {}", e))?;
+    }
 
-    // 获取文件扩展名来确定 MIME 类型
-    let mime_type = match std::path::Path::new(&file_name)
+    #[cfg(not(target_os = "windows"))]
+    {
+        open::that(&output_path).map_err(|e| format!("Failed to open folder: {}", e))?;
+    }
+
+    Ok(())
+}
+
+// 根据扩展名推断媒体类型："video" / "image" / "other"
+fn infer_media_type(path: &std::path::Path) -> &'static str {
+    match path
         .extension()
         .and_then(|e| e.to_str())
+        .and_then(guess_mime)
     {
-        Some("mp4") => "video/mp4",
-        Some("webm") => "video/webm",
-        Some("mov") => "video/quicktime",
-        Some("avi") => "video/x-msvideo",
-        Some("png") => "image/png",
-        Some("jpg") | Some("jpeg") => "image/jpeg",
-        Some("gif") => "image/gif",
-        Some("webp") => "image/webp",
-        _ => "application/octet-stream",
-    };
+        Some(mime) if mime.starts_with("video/") => "video",
+        Some(mime) if mime.starts_with("image/") => "image",
+        _ => "other",
+    }
+}
 
-    // 创建 HTTP 客户端_builder
-    let mut client_builder = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(480)) // 8 分钟请求超时
-        .connect_timeout(std::time::Duration::from_secs(300)); // 5 分钟连接超时
+// 输出库统计信息
+#[derive(Debug, Default, Serialize)]
+pub struct OutputStats {
+    pub video_count: u64,
+    pub image_count: u64,
+    pub total_bytes: u64,
+    pub oldest_unix: Option<i64>,
+    pub newest_unix: Option<i64>,
+}
 
-    // 配置代理
-    if let Some(proxy_url) = &options.proxy_url {
-        if !proxy_url.is_empty() {
-            println!("[Upload] 使用代理: {}", proxy_url);
-            client_builder = client_builder
-                .proxy(reqwest::Proxy::all(proxy_url).map_err(|e| format!("代理配置失败: {}", e))?);
-        }
-    } else {
-        // 尝试从环境变量读取代理
-        if let Ok(http_proxy) = std::env::var("HTTP_PROXY") {
-            if !http_proxy.is_empty() {
-                println!("[Upload] 使用 HTTP_PROXY: {}", http_proxy);
-                client_builder = client_builder.proxy(
-                    reqwest::Proxy::all(&http_proxy).map_err(|e| format!("代理配置失败: {}", e))?,
-                );
-            }
-        } else if let Ok(https_proxy) = std::env::var("HTTPS_PROXY") {
-            if !https_proxy.is_empty() {
-                println!("[Upload] 使用 HTTPS_PROXY: {}", https_proxy);
-                client_builder = client_builder.proxy(
-                    reqwest::Proxy::all(&https_proxy)
-                        .map_err(|e| format!("代理配置失败: {}", e))?,
-                );
+// 统计输出目录（含子目录）中的视频、图片数量、总大小及最早/最新文件时间
+#[command]
+pub async fn get_output_stats(app: tauri::AppHandle) -> Result<OutputStats, String> {
+    let output_dir = resolve_output_dir(&app, "OutputStats");
+
+    tokio::task::spawn_blocking(move || {
+        let mut files = Vec::new();
+        collect_files_recursive(&output_dir, &mut files);
+
+        let mut stats = OutputStats::default();
+        for (path, size, modified) in files {
+            match infer_media_type(&path) {
+                "video" => stats.video_count += 1,
+                "image" => stats.image_count += 1,
+                _ => continue,
             }
+            stats.total_bytes += size;
+
+            let Ok(unix) = modified.duration_since(std::time::UNIX_EPOCH) else {
+                continue;
+            };
+            let unix = unix.as_secs() as i64;
+            stats.oldest_unix = Some(stats.oldest_unix.map_or(unix, |t| t.min(unix)));
+            stats.newest_unix = Some(stats.newest_unix.map_or(unix, |t| t.max(unix)));
         }
+
+        info!(
+            "[OutputStats] {} 个视频, {} 张图片, 共 {} bytes",
+            stats.video_count, stats.image_count, stats.total_bytes
+        );
+        stats
+    })
+    .await
+    .map_err(|e| format!("统计输出目录失败: {}", e))
+}
+
+// 输出库中的单个文件（modified 为毫秒时间戳，metadata 来自同名 .json 附属文件）
+#[derive(Debug, Serialize)]
+pub struct OutputEntry {
+    pub path: String,
+    pub file_name: String,
+    pub size_bytes: u64,
+    pub modified: Option<i64>,
+    pub media_type: String,
+    pub metadata: Option<Value>,
+}
+
+// 读取输出文件的元数据附属文件（<文件名>.json），不存在或格式错误时返回 None
+fn read_output_metadata(media_path: &std::path::Path) -> Option<Value> {
+    let content = std::fs::read_to_string(output_metadata_path(media_path)?).ok()?;
+    let mut sidecar: Value = serde_json::from_str(&content).ok()?;
+    match sidecar.get_mut("metadata") {
+        Some(metadata) => Some(metadata.take()),
+        None => Some(sidecar),
     }
+}
 
-    let client = client_builder.build().map_err(|e| e.to_string())?;
+// 列出输出目录（含子目录）中的视频与图片，按修改时间从新到旧排列
+// media_type 为 "video" / "image" 时只返回该类型，limit 限制返回数量
+#[command]
+pub async fn list_outputs(
+    app: tauri::AppHandle,
+    limit: Option<usize>,
+    media_type: Option<String>,
+) -> Result<Vec<OutputEntry>, String> {
+    let output_dir = resolve_output_dir(&app, "ListOutputs");
+
+    tokio::task::spawn_blocking(move || {
+        let mut files = Vec::new();
+        collect_files_recursive(&output_dir, &mut files);
+        files.retain(|(path, _, _)| {
+            let kind = infer_media_type(path);
+            kind != "other" && media_type.as_deref().is_none_or(|filter| filter == kind)
+        });
+        files.sort_by_key(|(_, _, modified)| std::cmp::Reverse(*modified));
+        files.truncate(limit.unwrap_or(usize::MAX));
+
+        files
+            .into_iter()
+            .map(|(path, size, modified)| OutputEntry {
+                file_name: path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                size_bytes: size,
+                modified: modified
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .ok()
+                    .map(|d| d.as_millis() as i64),
+                media_type: infer_media_type(&path).to_string(),
+                metadata: read_output_metadata(&path),
+                path: path.to_string_lossy().to_string(),
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| format!("读取输出目录失败: {}", e))
+}
 
-    let max_retries = 3;
-    let mut last_error = String::new();
+// 重复文件组中的单个文件
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateFile {
+    pub path: String,
+    pub modified_unix: Option<i64>,
+}
 
-    // 根据上传 URL 判断图床类型
-    let response_format = options.response_format.as_deref().unwrap_or("url");
+// 内容完全相同的一组文件（按修改时间从旧到新排列）
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateGroup {
+    pub sha256: String,
+    pub size: u64,
+    pub files: Vec<DuplicateFile>,
+}
 
-    for attempt in 1..=max_retries {
-        // 构建 multipart 请求
-        let part = reqwest::multipart::Part::bytes(file_content.clone())
-            .file_name(file_name.clone())
-            .mime_str(mime_type)
-            .map_err(|e| e.to_string())?;
+// 查找目录（含子目录）中内容相同的文件：先按大小分组，只对大小相同的文件计算哈希
+async fn find_duplicate_files(dir: PathBuf) -> Result<Vec<DuplicateGroup>, String> {
+    const CONCURRENCY: usize = 4;
 
-        let multipart_form = reqwest::multipart::Form::new()
-            .text("reqtype", "fileupload")
-            .part(options.field_name.clone(), part);
+    let files = tokio::task::spawn_blocking(move || {
+        let mut files = Vec::new();
+        collect_files_recursive(&dir, &mut files);
+        files
+    })
+    .await
+    .map_err(|e| format!("扫描输出目录失败: {}", e))?;
+
+    let mut by_size: HashMap<u64, Vec<(PathBuf, std::time::SystemTime)>> = HashMap::new();
+    for (path, size, modified) in files {
+        // 空文件不视为重复渲染
+        if size > 0 {
+            by_size.entry(size).or_default().push((path, modified));
+        }
+    }
 
-        match client
-            .post(&options.upload_url)
-            .multipart(multipart_form)
-            .send()
-            .await
-        {
-            Ok(response) => {
-                let status = response.status();
+    let semaphore = std::sync::Arc::new(tokio::sync::Semaphore::new(CONCURRENCY));
+    let mut tasks = tokio::task::JoinSet::new();
+    for (size, candidates) in by_size.into_iter().filter(|(_, c)| c.len() > 1) {
+        for (path, modified) in candidates {
+            let semaphore = semaphore.clone();
+            tasks.spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                let hash = tokio::task::spawn_blocking({
+                    let path = path.clone();
+                    move || sha256_file(&path)
+                })
+                .await
+                .map_err(|e| format!("哈希线程异常: {}", e))
+                .and_then(|result| result);
+                (size, path, modified, hash)
+            });
+        }
+    }
 
-                if status.is_success() {
-                    match response_format {
-                        "json" => {
-                            // JSON 响应格式
-                            let json: Value = response.json().await.map_err(|e| e.to_string())?;
-                            // 尝试从常见的 JSON 字段提取 URL
-                            let url = json
-                                .get("url")
-                                .or(json.get("data"))
-                                .and_then(|v| v.as_str())
-                                .map(|s| s.to_string());
+    let mut by_hash: HashMap<(u64, String), Vec<(PathBuf, std::time::SystemTime)>> = HashMap::new();
+    while let Some(joined) = tasks.join_next().await {
+        let Ok((size, path, modified, hash)) = joined else {
+            continue;
+        };
+        match hash {
+            Ok(hash) => by_hash
+                .entry((size, hash))
+                .or_default()
+                .push((path, modified)),
+            Err(e) => warn!("[Duplicates] 跳过无法读取的文件 {}: {}", path.display(), e),
+        }
+    }
 
-                            if let Some(url) = url {
-                                return Ok(UploadResponse {
-                                    success: true,
-                                    url: Some(url),
-                                    error: None,
-                                });
-                            } else {
-                                return Ok(UploadResponse {
-                                    success: false,
-                                    url: None,
-                                    error: Some(format!("JSON 响应中未找到 URL: {}", json)),
-                                });
-                            }
-                        }
-                        _ => {
-                            // 直接返回 URL 文本
-                            let response_text = response.text().await.map_err(|e| e.to_string())?;
-                            if response_text.starts_with("https://")
-                                || response_text.starts_with("http://")
-                            {
-                                return Ok(UploadResponse {
-                                    success: true,
-                                    url: Some(response_text.trim().to_string()),
-                                    error: None,
-                                });
-                            } else {
-                                return Ok(UploadResponse {
-                                    success: false,
-                                    url: None,
-                                    error: Some(response_text),
-                                });
-                            }
-                        }
-                    }
-                } else {
-                    let response_text = response.text().await.unwrap_or_default();
-                    last_error = format!("上传失败 ({}): {}", status, response_text);
-                }
+    let mut groups: Vec<DuplicateGroup> = by_hash
+        .into_iter()
+        .filter(|(_, files)| files.len() > 1)
+        .map(|((size, sha256), mut files)| {
+            files.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+            DuplicateGroup {
+                sha256,
+                size,
+                files: files
+                    .into_iter()
+                    .map(|(path, modified)| DuplicateFile {
+                        path: path.to_string_lossy().to_string(),
+                        modified_unix: modified
+                            .duration_since(std::time::UNIX_EPOCH)
+                            .ok()
+                            .map(|d| d.as_secs() as i64),
+                    })
+                    .collect(),
             }
-            Err(e) => {
-                last_error = format!("上传请求失败 (尝试 {}/{}): {}", attempt, max_retries, e);
-                if attempt < max_retries {
-                    tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+        })
+        .collect();
+    groups.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.sha256.cmp(&b.sha256)));
+    Ok(groups)
+}
+
+// 查找输出目录中内容完全相同的文件
+#[command]
+pub async fn find_duplicate_outputs(app: tauri::AppHandle) -> Result<Vec<DuplicateGroup>, String> {
+    let output_dir = resolve_output_dir(&app, "Duplicates");
+    let groups = find_duplicate_files(output_dir).await?;
+    info!("[Duplicates] 找到 {} 组重复文件", groups.len());
+    Ok(groups)
+}
+
+// 删除重复文件的结果
+#[derive(Debug, Default, Serialize)]
+pub struct DuplicateRemovalReport {
+    pub removed: Vec<String>,
+    pub freed_bytes: u64,
+    pub errors: Vec<String>,
+}
+
+// 删除输出目录中的重复文件，每组只保留最旧（"oldest"）或最新（"newest"）的一个
+// to_trash 为 true 时移到回收站而不是直接删除
+#[command]
+pub async fn remove_duplicates(
+    app: tauri::AppHandle,
+    keep: String,
+    to_trash: Option<bool>,
+) -> Result<DuplicateRemovalReport, String> {
+    let keep_newest = match keep.as_str() {
+        "oldest" => false,
+        "newest" => true,
+        _ => return Err(format!("keep 只能是 \"oldest\" 或 \"newest\": {}", keep)),
+    };
+    let to_trash = to_trash.unwrap_or(false);
+
+    let output_dir = resolve_output_dir(&app, "Duplicates");
+    let groups = find_duplicate_files(output_dir).await?;
+
+    tokio::task::spawn_blocking(move || {
+        let mut report = DuplicateRemovalReport::default();
+        for group in groups {
+            let mut files = group.files;
+            if keep_newest {
+                files.pop();
+            } else {
+                files.remove(0);
+            }
+
+            for file in files {
+                let result = if to_trash {
+                    trash::delete(&file.path).map_err(|e| e.to_string())
+                } else {
+                    std::fs::remove_file(&file.path).map_err(|e| e.to_string())
+                };
+                match result {
+                    Ok(()) => {
+                        report.freed_bytes += group.size;
+                        report.removed.push(file.path);
+                    }
+                    Err(e) => {
+                        warn!("[Duplicates] 删除失败 {}: {}", file.path, e);
+                        report.errors.push(format!("{}: {}", file.path, e));
+                    }
                 }
             }
         }
-    }
 
-    Ok(UploadResponse {
-        success: false,
-        url: None,
-        error: Some(last_error),
+        info!(
+            "[Duplicates] 已{} {} 个重复文件, 释放 {} bytes",
+            if to_trash {
+                "移到回收站"
+            } else {
+                "删除"
+            },
+            report.removed.len(),
+            report.freed_bytes
+        );
+        report
     })
+    .await
+    .map_err(|e| format!("删除重复文件失败: {}", e))
 }
 
-// 写入输出文件（用于视频和图像生成结果）
-// 注意：由于配置为 currentUser 安装模式，应用安装在 C:\Users\Name\AppData\Local\Programs\MatrixGenPro\
-// std::env::current_exe() 将指向该路径，您可以安全地写入相对于此路径的文件
-#[derive(Debug, Deserialize)]
-pub struct WriteOutputFileOptions {
-    pub file_name: String,
-    pub data: String,
-    pub media_type: String, // "video" 或 "image"
+// 输出目录监听器（None 表示未启动）
+#[derive(Default)]
+pub struct OutputWatcher(pub Mutex<Option<notify::RecommendedWatcher>>);
+
+// 输出目录文件变化事件
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputFileEvent {
+    pub path: String,
+    pub media_type: String,
 }
 
+// 事件合并窗口：在该时间内没有新事件才统一发送
+const OUTPUT_WATCH_DEBOUNCE_MS: u64 = 500;
+
+// 开始监听输出目录，文件新增/删除时发送 output-file-added / output-file-removed 事件
 #[command]
-pub async fn write_output_file(
+pub fn start_output_watch(
     app: tauri::AppHandle,
-    options: WriteOutputFileOptions,
+    state: State<'_, OutputWatcher>,
 ) -> Result<String, String> {
-    let WriteOutputFileOptions {
-        file_name,
-        data,
-        media_type,
-    } = options;
+    use notify::Watcher;
+    use std::sync::mpsc::RecvTimeoutError;
 
-    // 使用系统标准目录，避免触发Tauri热重载
-    let output_dir = match app.path().video_dir() {
-        Ok(video_dir) => {
-            println!("[OutputFile] 使用系统视频目录: {:?}", video_dir);
-            video_dir.join("MatrixGen_Output")
-        }
-        Err(e) => {
-            println!(
-                "[OutputFile] 获取系统视频目录失败: {}, 使用临时目录作为fallback",
-                e
-            );
-            std::env::temp_dir().join("MatrixGen_Output")
-        }
-    };
+    let mut guard = state.0.lock().map_err(|e| e.to_string())?;
+    let output_dir = resolve_output_dir(&app, "OutputWatch");
+    let output_path = output_dir.to_string_lossy().to_string();
 
-    // 确保目录存在
-    if let Err(e) = std::fs::create_dir_all(&output_dir) {
-        println!("[OutputFile] 创建目录失败: {}", e);
-        return Err(format!("无法创建输出目录: {}", e));
+    if guard.is_some() {
+        info!("[OutputWatch] 监听已在运行: {}", output_path);
+        return Ok(output_path);
     }
 
-    let file_path = output_dir.join(&file_name);
-    let file_path_str = file_path.to_string_lossy().to_string();
+    std::fs::create_dir_all(&output_dir).map_err(|e| format!("无法创建输出目录: {}", e))?;
 
-    println!(
-        "[OutputFile] 准备写入文件: {}, 数据长度: {}",
-        file_path_str,
-        data.len()
-    );
+    let (tx, rx) = std::sync::mpsc::channel::<notify::Result<notify::Event>>();
+    let mut watcher =
+        notify::recommended_watcher(tx).map_err(|e| format!("无法创建目录监听器: {}", e))?;
+    watcher
+        .watch(&output_dir, notify::RecursiveMode::Recursive)
+        .map_err(|e| format!("无法监听输出目录: {}", e))?;
 
-    // 返回绝对路径供前端使用convertFileSrc转换
-    let absolute_path = file_path
-        .canonicalize()
-        .unwrap_or(file_path.clone())
-        .to_string_lossy()
-        .to_string();
+    // 后台线程：合并突发事件后再发送，监听器被释放后 channel 关闭，线程自动退出
+    std::thread::spawn(move || {
+        let debounce = std::time::Duration::from_millis(OUTPUT_WATCH_DEBOUNCE_MS);
+        let mut pending: Vec<PathBuf> = Vec::new();
 
-    // 解码 base64
-    let decoded_data =
-        match base64::Engine::decode(&base64::engine::general_purpose::STANDARD, &data) {
-            Ok(d) => d,
-            Err(e) => {
-                println!("[OutputFile] base64 解码失败: {}", e);
-                return Err(format!("base64 解码失败: {}", e));
+        loop {
+            let received = if pending.is_empty() {
+                rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+            } else {
+                rx.recv_timeout(debounce)
+            };
+
+            match received {
+                Ok(Ok(event)) => {
+                    use notify::EventKind;
+                    if matches!(
+                        event.kind,
+                        EventKind::Create(_)
+                            | EventKind::Remove(_)
+                            | EventKind::Modify(notify::event::ModifyKind::Name(_))
+                    ) {
+                        for path in event.paths {
+                            if !pending.contains(&path) {
+                                pending.push(path);
+                            }
+                        }
+                    }
+                }
+                Ok(Err(e)) => warn!("[OutputWatch] 监听错误: {}", e),
+                Err(RecvTimeoutError::Timeout) => {
+                    // 以合并窗口结束时的实际状态为准
+                    for path in pending.drain(..) {
+                        if path.is_dir() {
+                            continue;
+                        }
+                        let event_name = if path.exists() {
+                            "output-file-added"
+                        } else {
+                            "output-file-removed"
+                        };
+                        let payload = OutputFileEvent {
+                            path: path.to_string_lossy().to_string(),
+                            media_type: infer_media_type(&path).to_string(),
+                        };
+                        if let Err(e) = app.emit(event_name, payload) {
+                            warn!("[OutputWatch] 发送事件失败: {}", e);
+                        }
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
             }
-        };
+        }
 
-    println!("[OutputFile] 解码后数据长度: {}", decoded_data.len());
+        info!("[OutputWatch] 监听线程已退出");
+    });
 
-    // 写入文件
-    if let Err(e) = std::fs::write(&file_path, &decoded_data) {
-        println!("[OutputFile] 写入文件失败: {}", e);
-        return Err(format!("无法写入文件: {}", e));
-    }
+    *guard = Some(watcher);
+    info!("[OutputWatch] 开始监听输出目录: {}", output_path);
+    Ok(output_path)
+}
 
-    // 验证文件是否存在
-    if !file_path.exists() {
-        println!("[OutputFile] 文件写入后不存在: {}", file_path_str);
-        return Err("文件写入后不存在".to_string());
+// 停止监听输出目录
+#[command]
+pub fn stop_output_watch(state: State<'_, OutputWatcher>) -> Result<(), String> {
+    let mut guard = state.0.lock().map_err(|e| e.to_string())?;
+    if guard.take().is_some() {
+        info!("[OutputWatch] 已停止监听输出目录");
     }
+    Ok(())
+}
 
-    // 获取文件大小
-    let file_size = std::fs::metadata(&file_path).map(|m| m.len()).unwrap_or(0);
-
-    println!(
-        "[OutputFile] 输出文件创建成功: {}, 大小: {} bytes",
-        absolute_path, file_size
-    );
+// 缓存远程图像到本地（用于绕过WebView跟踪预防）
+#[derive(Debug, Deserialize)]
+pub struct CacheImageOptions {
+    pub url: String,
+    pub file_name: Option<String>, // 省略时按 URL 的 SHA-256 命名，相同 URL 复用同一缓存
+    pub force_refresh: Option<bool>, // 忽略已有缓存重新下载
+}
 
-    Ok(absolute_path)
+#[derive(Debug, Serialize)]
+pub struct CacheImageResponse {
+    pub success: bool,
+    pub local_path: Option<String>,
+    pub error: Option<String>,
 }
 
-// 保留旧的临时文件函数以保持兼容性
 #[command]
-pub async fn write_temp_file_binary(file_name: String, data: String) -> Result<String, String> {
-    let cache_dir = std::env::temp_dir().join("matrix-gen").join("temp");
+pub async fn cache_image(
+    app: tauri::AppHandle,
+    operations: State<'_, ActiveOperations>,
+    options: CacheImageOptions,
+) -> Result<CacheImageResponse, String> {
+    operations.run(cache_image_impl(app, options)).await
+}
+
+async fn cache_image_impl(
+    app: tauri::AppHandle,
+    options: CacheImageOptions,
+) -> Result<CacheImageResponse, String> {
+    let CacheImageOptions {
+        url,
+        file_name,
+        force_refresh,
+    } = options;
+    let file_name = match file_name.filter(|name| !name.is_empty()) {
+        Some(name) => name,
+        None => cache_file_name_for_url(&url),
+    };
+
+    info!("[CacheImage] 开始缓存图像: {} -> {}", url, file_name);
+
+    let cache_dir = image_cache_dir();
 
     // 确保目录存在
     if let Err(e) = std::fs::create_dir_all(&cache_dir) {
-        println!("[TempFile] 创建目录失败: {}", e);
+        error!("[CacheImage] 创建缓存目录失败: {}", e);
         return Err(format!("无法创建缓存目录: {}", e));
     }
 
     let file_path = cache_dir.join(&file_name);
     let file_path_str = file_path.to_string_lossy().to_string();
 
-    println!(
-        "[TempFile] 准备写入文件: {}, 数据长度: {}",
-        file_path_str,
-        data.len()
-    );
+    debug!("[CacheImage] 目标路径: {}", file_path_str);
 
-    // 解码 base64
-    let decoded_data =
-        match base64::Engine::decode(&base64::engine::general_purpose::STANDARD, &data) {
-            Ok(d) => d,
-            Err(e) => {
-                println!("[TempFile] base64 解码失败: {}", e);
-                return Err(format!("base64 解码失败: {}", e));
-            }
-        };
+    // 已有非空缓存时直接复用
+    if !force_refresh.unwrap_or(false)
+        && std::fs::metadata(&file_path).is_ok_and(|m| m.is_file() && m.len() > 0)
+    {
+        debug!("[CacheImage] 命中缓存: {}", file_path_str);
+        // 刷新修改时间，作为 LRU 淘汰的最近访问时间
+        if let Err(e) = std::fs::File::options()
+            .write(true)
+            .open(&file_path)
+            .and_then(|file| file.set_modified(std::time::SystemTime::now()))
+        {
+            debug!("[CacheImage] 无法更新缓存访问时间: {}", e);
+        }
+        return Ok(CacheImageResponse {
+            success: true,
+            local_path: Some(file_path_str),
+            error: None,
+        });
+    }
+
+    let _permit = acquire_network_permit().await?;
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(120)) // 2分钟超时足够下载图像
+        .connect_timeout(std::time::Duration::from_secs(60)) // 1分钟连接超时
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
 
-    println!("[TempFile] 解码后数据长度: {}", decoded_data.len());
+    let response = client
+        .get(&url)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to download image: {}", e))?;
 
-    // 写入文件
-    if let Err(e) = std::fs::write(&file_path, &decoded_data) {
-        println!("[TempFile] 写入文件失败: {}", e);
-        return Err(format!("无法写入文件: {}", e));
+    if !response.status().is_success() {
+        return Ok(CacheImageResponse {
+            success: false,
+            local_path: None,
+            error: Some(format!("Download failed with status: {}", response.status())),
+        });
     }
 
-    // 验证文件是否存在
-    if !file_path.exists() {
-        println!("[TempFile] 文件写入后不存在: {}", file_path_str);
-        return Err("文件写入后不存在".to_string());
-    }
+    let content = response
+        .bytes()
+        .await
+        .map_err(|e| format!("Failed to read response body: {}", e))?;
 
-    // 获取文件大小
-    let file_size = std::fs::metadata(&file_path).map(|m| m.len()).unwrap_or(0);
+    // 先写临时文件再替换，避免中断后留下的不完整文件被当作缓存复用
+    let part_path = cache_dir.join(format!("{}.part", file_name));
+    std::fs::write(&part_path, &content)
+        .and_then(|_| std::fs::rename(&part_path, &file_path))
+        .map_err(|e| {
+            let _ = std::fs::remove_file(&part_path);
+            format!("Failed to write image file: {}", e)
+        })?;
 
-    println!(
-        "[TempFile] 临时文件创建成功: {}, 大小: {} bytes",
-        file_path_str, file_size
+    info!(
+        "[CacheImage] 图像缓存成功: {} ({} bytes)",
+        file_path_str,
+        content.len()
     );
 
-    Ok(file_path_str)
-}
+    // 图像已写入，设置读取失败时按默认上限淘汰，不影响本次缓存结果
+    let max_bytes = load_image_cache_settings(&app)
+        .unwrap_or_else(|e| {
+            warn!("[CacheImage] {}，使用默认缓存上限", e);
+            ImageCacheSettings::default()
+        })
+        .max_bytes;
+    let report = evict_image_cache(max_bytes, Some(&file_path));
+    if report.deleted_count > 0 {
+        info!(
+            "[CacheImage] 缓存超出上限，已淘汰 {} 个文件 ({} bytes)",
+            report.deleted_count, report.freed_bytes
+        );
+    }
 
-// 角色图片保存选项
-#[derive(Debug, Deserialize)]
-pub struct SaveCharacterImageOptions {
-    pub source_path: String,
-    pub character_id: String,
+    Ok(CacheImageResponse {
+        success: true,
+        local_path: Some(file_path_str),
+        error: None,
+    })
 }
 
-// 角色图片保存响应
-#[derive(Debug, Serialize)]
-pub struct SaveCharacterImageResponse {
-    pub success: bool,
-    pub path: Option<String>,
-    pub error: Option<String>,
+// 图像缓存目录
+fn image_cache_dir() -> PathBuf {
+    std::env::temp_dir().join("matrix-gen").join("images")
 }
 
-// 保存角色图片到应用数据目录
-#[command]
-pub async fn save_character_image(
-    options: SaveCharacterImageOptions,
-) -> Result<SaveCharacterImageResponse, String> {
-    let source_path = options.source_path;
-    let character_id = options.character_id;
+// 图像缓存默认上限 512 MB
+const DEFAULT_IMAGE_CACHE_MAX_BYTES: u64 = 512 * 1024 * 1024;
 
-    println!(
-        "[CharacterImage] 保存角色图片: {} -> {}",
-        source_path, character_id
-    );
+// 图像缓存设置（持久化到配置目录 image_cache_settings.json）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageCacheSettings {
+    pub max_bytes: u64,
+}
 
-    // 获取应用数据目录
-    let data_dir = match std::env::current_dir() {
-        Ok(dir) => {
-            // 如果在开发环境，使用项目目录下的 data 目录
-            let data_dir = dir.join("data").join("characters").join(&character_id);
-            data_dir
-        }
-        Err(e) => {
-            println!("[CharacterImage] 获取目录失败: {}", e);
-            return Err(format!("获取目录失败: {}", e));
+impl Default for ImageCacheSettings {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_IMAGE_CACHE_MAX_BYTES,
         }
-    };
-
-    // 创建目录
-    if let Err(e) = std::fs::create_dir_all(&data_dir) {
-        println!("[CharacterImage] 创建目录失败: {}", e);
-        return Err(format!("创建目录失败: {}", e));
     }
+}
 
-    // 获取源文件名
-    let file_name = std::path::Path::new(&source_path)
-        .file_name()
-        .and_then(|n| n.to_str().map(|s| s.to_string()))
-        .unwrap_or_else(|| format!("{}.jpg", character_id));
-
-    // 目标路径
-    let target_path = data_dir.join(&file_name);
-    let relative_path = format!("data/characters/{}/{}", character_id, file_name);
+fn image_cache_settings_path(app: &tauri::AppHandle) -> Result<PathBuf, String> {
+    app.path()
+        .app_config_dir()
+        .map(|dir| dir.join("image_cache_settings.json"))
+        .map_err(|e| format!("无法解析配置目录: {}", e))
+}
 
-    // 复制文件
-    match std::fs::copy(&source_path, &target_path) {
-        Ok(bytes) => {
-            println!(
-                "[CharacterImage] 图片保存成功: {} ({} bytes)",
-                relative_path, bytes
-            );
-            Ok(SaveCharacterImageResponse {
-                success: true,
-                path: Some(relative_path),
-                error: None,
-            })
-        }
-        Err(e) => {
-            println!("[CharacterImage] 复制文件失败: {}", e);
-            Ok(SaveCharacterImageResponse {
-                success: false,
-                path: None,
-                error: Some(format!("复制文件失败: {}", e)),
-            })
+fn load_image_cache_settings(app: &tauri::AppHandle) -> Result<ImageCacheSettings, String> {
+    let path = image_cache_settings_path(app)?;
+    match std::fs::read_to_string(&path) {
+        Ok(content) => {
+            serde_json::from_str(&content).map_err(|e| format!("图像缓存设置格式错误: {}", e))
         }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(ImageCacheSettings::default()),
+        Err(e) => Err(format!("无法读取图像缓存设置: {}", e)),
     }
 }
 
-// 保存 base64 数据为角色图片
-#[derive(Debug, Deserialize)]
-pub struct SaveCharacterImageFromBase64Options {
-    pub base64_data: String,
-    pub character_id: String,
-    pub mime_type: String,
+// 收集图像缓存文件 (路径, 大小, 最近访问时间)，最近访问时间取 atime 与 mtime 中较新者
+fn collect_image_cache_files() -> Vec<(PathBuf, u64, std::time::SystemTime)> {
+    let Ok(entries) = std::fs::read_dir(image_cache_dir()) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok().filter(|m| m.is_file())?;
+            let modified = metadata.modified().unwrap_or(std::time::UNIX_EPOCH);
+            let accessed = metadata.accessed().unwrap_or(modified).max(modified);
+            Some((entry.path(), metadata.len(), accessed))
+        })
+        .collect()
 }
 
-#[command]
-pub async fn save_character_image_from_base64(
-    options: SaveCharacterImageFromBase64Options,
-) -> Result<SaveCharacterImageResponse, String> {
-    let base64_data = options.base64_data;
-    let character_id = options.character_id;
-    let mime_type = options.mime_type;
-
-    println!(
-        "[CharacterImage] 保存 base64 图片: {} ({} bytes)",
-        character_id,
-        base64_data.len()
-    );
+// 按最近访问时间从旧到新淘汰缓存文件直到总大小不超过上限；keep 为刚写入的文件，不参与淘汰
+fn evict_image_cache(max_bytes: u64, keep: Option<&std::path::Path>) -> CleanupReport {
+    let mut report = CleanupReport::default();
+    let mut files = collect_image_cache_files();
+    let mut total_bytes: u64 = files.iter().map(|(_, size, _)| size).sum();
+    if total_bytes <= max_bytes {
+        return report;
+    }
+    files.sort_by_key(|(_, _, accessed)| *accessed);
 
-    // 保存到项目根目录的 characters 文件夹
-    let data_dir = match std::env::current_dir() {
-        Ok(dir) => {
-            let data_dir = dir.join("characters").join(&character_id);
-            data_dir
+    for (path, size, _) in &files {
+        if total_bytes <= max_bytes {
+            break;
         }
-        Err(e) => {
-            println!("[CharacterImage] 获取目录失败: {}", e);
-            return Err(format!("获取目录失败: {}", e));
+        // 跳过正在写入的临时文件
+        if Some(path.as_path()) == keep || path.extension().is_some_and(|ext| ext == "part") {
+            report.skipped_count += 1;
+            continue;
         }
-    };
+        match std::fs::remove_file(path) {
+            Ok(()) => {
+                total_bytes -= size;
+                report.record(path, *size);
+            }
+            Err(e) => warn!(
+                "[CacheImage] Failed to evict cached image {}: {}",
+                path.display(),
+                e
+            ),
+        }
+    }
 
-    // 创建目录
-    if let Err(e) = std::fs::create_dir_all(&data_dir) {
-        println!("[CharacterImage] 创建目录失败: {}", e);
-        return Err(format!("创建目录失败: {}", e));
+    report
+}
+
+// 设置图像缓存大小上限（字节），保存后立即按新上限淘汰
+#[command]
+pub fn set_image_cache_limit(
+    app: tauri::AppHandle,
+    max_bytes: u64,
+) -> Result<CleanupReport, String> {
+    if max_bytes == 0 {
+        return Err("缓存上限必须大于 0".to_string());
     }
 
-    // 解码 base64
-    let image_data =
-        match base64::Engine::decode(&base64::engine::general_purpose::STANDARD, &base64_data) {
-            Ok(data) => data,
-            Err(e) => {
-                println!("[CharacterImage] base64 解码失败: {}", e);
-                return Err(format!("base64 解码失败: {}", e));
-            }
-        };
+    let path = image_cache_settings_path(&app)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("无法创建配置目录: {}", e))?;
+    }
+    let content = serde_json::to_string_pretty(&ImageCacheSettings { max_bytes })
+        .map_err(|e| format!("无法序列化设置: {}", e))?;
+    std::fs::write(&path, content).map_err(|e| format!("无法保存图像缓存设置: {}", e))?;
+
+    let report = evict_image_cache(max_bytes, None);
+    info!(
+        "[CacheImage] 缓存上限设为 {} bytes，淘汰 {} 个文件",
+        max_bytes, report.deleted_count
+    );
+    Ok(report)
+}
 
-    // 获取文件扩展名
-    let extension = match mime_type.as_str() {
-        "image/png" => "png",
-        "image/jpeg" => "jpg",
-        "image/jpg" => "jpg",
-        "image/webp" => "webp",
-        _ => "png",
-    };
+// 图像缓存使用情况
+#[derive(Debug, Serialize)]
+pub struct ImageCacheStats {
+    pub total_bytes: u64,
+    pub file_count: u64,
+    pub max_bytes: u64,
+}
 
-    let file_name = format!("character_preview_{}.{}", character_id, extension);
-    let target_path = data_dir.join(&file_name);
-    let relative_path = format!("characters/{}/{}", character_id, file_name);
+// 获取图像缓存总大小与文件数
+#[command]
+pub fn get_image_cache_stats(app: tauri::AppHandle) -> Result<ImageCacheStats, String> {
+    let files = collect_image_cache_files();
+    Ok(ImageCacheStats {
+        total_bytes: files.iter().map(|(_, size, _)| size).sum(),
+        file_count: files.len() as u64,
+        max_bytes: load_image_cache_settings(&app)?.max_bytes,
+    })
+}
 
-    // 写入文件
-    match std::fs::write(&target_path, &image_data) {
-        Ok(_) => {
-            println!(
-                "[CharacterImage] 图片保存成功: {} ({} bytes)",
-                relative_path,
-                image_data.len()
-            );
-            Ok(SaveCharacterImageResponse {
-                success: true,
-                path: Some(relative_path),
-                error: None,
-            })
-        }
-        Err(e) => {
-            println!("[CharacterImage] 写入文件失败: {}", e);
-            Ok(SaveCharacterImageResponse {
-                success: false,
-                path: None,
-                error: Some(format!("写入文件失败: {}", e)),
-            })
-        }
+// 按 URL 的 SHA-256 生成缓存文件名，尽量保留 URL 路径中的图片扩展名
+fn cache_file_name_for_url(url: &str) -> String {
+    use sha2::Digest;
+
+    let hash = hex::encode(sha2::Sha256::digest(url.as_bytes()));
+    let ext = url
+        .split(['?', '#'])
+        .next()
+        .and_then(|path| path.rsplit('/').next())
+        .and_then(|name| name.rsplit_once('.'))
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .filter(|ext| guess_mime(ext).is_some_and(|mime| mime.starts_with("image/")));
+    match ext {
+        Some(ext) => format!("{}.{}", hash, ext),
+        None => hash,
     }
 }
 
-// 加载外部插件文件
-#[command]
-pub async fn load_plugins_raw(app: tauri::AppHandle) -> Result<Vec<String>, String> {
-    // 获取可执行文件所在目录
-    let exe_path = std::env::current_exe().map_err(|e| format!("无法获取可执行文件路径: {}", e))?;
-    let exe_dir = exe_path.parent().ok_or("无法获取可执行文件所在目录")?;
+// 图像探测结果
+#[derive(Debug, Serialize)]
+pub struct ImageProbeResult {
+    pub width: u32,
+    pub height: u32,
+    pub format: String,
+}
 
-    // 插件目录路径：先按 Resource 或 fallback 得到初始路径
-    // exe_dir 在开发模式下为 …/src-tauri/target/debug，往上一级再上一级再上一级 = 项目根
-    let project_root = exe_dir
-        .parent()
-        .and_then(|p| p.parent())
-        .and_then(|p| p.parent());
+// 探测图像尺寸和格式（只读取文件头，不完整解码）
+#[command]
+pub fn probe_image(path: String) -> Result<ImageProbeResult, String> {
+    let reader = image::ImageReader::open(&path)
+        .map_err(|e| format!("无法打开图像文件: {}", e))?
+        .with_guessed_format()
+        .map_err(|e| format!("无法识别图像格式: {}", e))?;
+
+    let format = reader
+        .format()
+        .ok_or_else(|| format!("不支持的图像格式: {}", path))?;
+
+    let (width, height) = reader
+        .into_dimensions()
+        .map_err(|e| format!("无法读取图像尺寸: {}", e))?;
+
+    debug!("[ProbeImage] {}: {}x{} ({:?})", path, width, height, format);
+
+    Ok(ImageProbeResult {
+        width,
+        height,
+        format: format!("{:?}", format).to_lowercase(),
+    })
+}
 
-    let mut plugins_dir = match app.path().resolve("plugins/", tauri::path::BaseDirectory::Resource) {
-        Ok(resource_path) => {
-            println!("[PluginLoader] Resource 解析到: {:?}", resource_path);
-            resource_path
-        }
-        Err(e) => {
-            println!("[PluginLoader] Resource 解析失败: {}, 使用 fallback", e);
-            let is_dev = project_root.map_or(false, |r| {
-                r.join("src-tauri").exists() && r.join("src").exists()
-            });
-            if is_dev {
-                let root = project_root.unwrap();
-                let st = root.join("src-tauri").join("plugins");
-                if st.exists() {
-                    st
-                } else {
-                    std::env::current_dir()
-                        .map_err(|e| format!("无法获取当前目录: {}", e))?
-                        .join("plugins")
-                }
-            } else {
-                exe_dir.join("plugins")
+// 计算图像感知哈希（dHash）：缩放为 9x8 灰度图，比较相邻像素亮度，得到 64 位十六进制字符串
+#[command]
+pub async fn perceptual_hash(path: String) -> Result<String, String> {
+    // 解码与缩放较耗时，放到阻塞线程中执行
+    tokio::task::spawn_blocking(move || {
+        let img = image::open(&path).map_err(|e| format!("无法解码图像: {}", e))?;
+        let gray = img
+            .resize_exact(9, 8, image::imageops::FilterType::Triangle)
+            .to_luma8();
+
+        let mut hash: u64 = 0;
+        for y in 0..8 {
+            for x in 0..8 {
+                let left = gray.get_pixel(x, y)[0];
+                let right = gray.get_pixel(x + 1, y)[0];
+                hash = (hash << 1) | u64::from(left > right);
             }
         }
+
+        let hex = format!("{:016x}", hash);
+        debug!("[PerceptualHash] {}: {}", path, hex);
+        Ok(hex)
+    })
+    .await
+    .map_err(|e| format!("哈希线程异常: {}", e))?
+}
+
+// 计算两个感知哈希的汉明距离（不同位数越少越相似）
+#[command]
+pub fn hamming_distance(a: String, b: String) -> Result<u32, String> {
+    let parse = |h: &str| {
+        u64::from_str_radix(h.trim(), 16).map_err(|e| format!("无效的哈希值 {}: {}", h, e))
     };
+    Ok((parse(&a)? ^ parse(&b)?).count_ones())
+}
+
+// 媒体文件完整性检查结果
+#[derive(Debug, Serialize)]
+pub struct MediaCompleteness {
+    pub complete: bool,
+    pub reason: Option<String>,
+}
 
-    // 开发模式覆盖：Resource 在 dev 下常指向 target/debug/plugins，该目录可能缺 zhichuang 等
-    // 若存在 项目根/src-tauri/plugins，则直接使用源码目录，确保加载到全部插件
-    if let Some(root) = project_root {
-        let src_tauri_plugins = root.join("src-tauri").join("plugins");
-        if src_tauri_plugins.exists() {
-            println!("[PluginLoader] 开发模式：使用源码 src-tauri/plugins（含 zhichuang 等）");
-            plugins_dir = src_tauri_plugins;
+impl MediaCompleteness {
+    fn complete() -> Self {
+        Self {
+            complete: true,
+            reason: None,
         }
     }
 
-    println!("[PluginLoader] 插件目录路径: {}", plugins_dir.display());
-
-    // 创建插件目录（如果不存在）
-    if !plugins_dir.exists() {
-        std::fs::create_dir_all(&plugins_dir).map_err(|e| format!("无法创建插件目录: {}", e))?;
-        println!("[PluginLoader] 创建了插件目录: {}", plugins_dir.display());
+    fn incomplete(reason: impl Into<String>) -> Self {
+        Self {
+            complete: false,
+            reason: Some(reason.into()),
+        }
     }
+}
 
-    // 读取插件目录中的所有 .js 文件
-    let mut plugin_contents = Vec::new();
-    let entries =
-        std::fs::read_dir(&plugins_dir).map_err(|e| format!("无法读取插件目录: {}", e))?;
-
-    for entry in entries {
-        let entry = entry.map_err(|e| format!("读取目录条目失败: {}", e))?;
-        let path = entry.path();
+// 检查 MP4/MOV：顶层 box 的声明大小之和必须等于文件长度，且必须包含 moov
+fn check_mp4_boxes<R: std::io::Read + std::io::Seek>(
+    reader: &mut R,
+    file_len: u64,
+) -> std::io::Result<MediaCompleteness> {
+    use std::io::SeekFrom;
+
+    let mut offset = 0u64;
+    let mut has_moov = false;
+    let mut has_mdat = false;
+    while offset < file_len {
+        if file_len - offset < 8 {
+            return Ok(MediaCompleteness::incomplete(format!(
+                "文件在偏移 {} 处被截断（box 头不完整）",
+                offset
+            )));
+        }
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut header = [0u8; 8];
+        reader.read_exact(&mut header)?;
+        let box_type = String::from_utf8_lossy(&header[4..8]).to_string();
+        let size = match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+            // size 为 0 表示该 box 延伸到文件末尾
+            0 => file_len - offset,
+            // size 为 1 表示使用 64 位扩展大小
+            1 => {
+                if file_len - offset < 16 {
+                    return Ok(MediaCompleteness::incomplete(format!(
+                        "文件在 {} box 的扩展头处被截断",
+                        box_type
+                    )));
+                }
+                let mut large = [0u8; 8];
+                reader.read_exact(&mut large)?;
+                u64::from_be_bytes(large)
+            }
+            size => size as u64,
+        };
+        if size < 8 {
+            return Ok(MediaCompleteness::incomplete(format!(
+                "{} box 大小无效: {}",
+                box_type, size
+            )));
+        }
+        if size > file_len - offset {
+            return Ok(MediaCompleteness::incomplete(format!(
+                "{} box 声明 {} 字节，但文件只剩 {} 字节（下载可能被截断）",
+                box_type,
+                size,
+                file_len - offset
+            )));
+        }
 
-        // 检查是否为 .js 文件
-        if path.is_file() && path.extension().and_then(|ext| ext.to_str()) == Some("js") {
-            println!("[PluginLoader] 发现插件文件: {}", path.display());
-            let content = std::fs::read_to_string(&path)
-                .map_err(|e| format!("读取插件文件 {} 失败: {}", path.display(), e))?;
-            plugin_contents.push(content);
+        match box_type.as_str() {
+            "moov" => has_moov = true,
+            "mdat" => has_mdat = true,
+            _ => {}
         }
+        offset += size;
     }
 
-    println!(
-        "[PluginLoader] 共加载了 {} 个插件文件",
-        plugin_contents.len()
-    );
-    Ok(plugin_contents)
+    if !has_moov {
+        return Ok(MediaCompleteness::incomplete("缺少 moov box（索引信息）"));
+    }
+    if !has_mdat {
+        return Ok(MediaCompleteness::incomplete("缺少 mdat box（媒体数据）"));
+    }
+    Ok(MediaCompleteness::complete())
 }
 
-// 获取输出目录路径
-#[command]
-pub fn get_output_path() -> Result<String, String> {
-    let current_dir = std::env::current_dir().map_err(|e| format!("获取当前目录失败: {}", e))?;
+// 读取 EBML 变长整数，返回 (值, 占用字节数)；keep_marker 为 true 时保留长度标记位（元素 ID）
+// 大小字段全为 1 时表示未知大小，返回 None
+fn read_ebml_vint<R: std::io::Read>(
+    reader: &mut R,
+    keep_marker: bool,
+) -> std::io::Result<(Option<u64>, u64)> {
+    let mut first = [0u8; 1];
+    reader.read_exact(&mut first)?;
+    let len = first[0].leading_zeros() as usize + 1;
+    if len > 8 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "无效的 EBML 变长整数",
+        ));
+    }
 
-    // Normalize path separators for cross-platform compatibility
-    let output_path = current_dir.to_string_lossy().to_string().replace('\\', "/");
-    Ok(output_path)
+    let mut rest = [0u8; 7];
+    reader.read_exact(&mut rest[..len - 1])?;
+    let marker = 0x80u8 >> (len - 1);
+    let mut value = if keep_marker {
+        first[0] as u64
+    } else {
+        (first[0] & !marker) as u64
+    };
+    let mut all_ones = first[0] & !marker == marker.wrapping_sub(1);
+    for &byte in &rest[..len - 1] {
+        value = (value << 8) | byte as u64;
+        all_ones &= byte == 0xFF;
+    }
+
+    let value = if !keep_marker && all_ones {
+        None
+    } else {
+        Some(value)
+    };
+    Ok((value, len as u64))
 }
 
-// 创建日志监视器窗口
-#[command]
-pub async fn create_log_monitor_window(app: tauri::AppHandle) -> Result<(), String> {
-    use tauri::webview::WebviewWindowBuilder;
+// 检查 WebM/MKV：EBML 头之后的 Segment 及其顶层子元素不能超出文件长度
+fn check_webm_elements<R: std::io::Read + std::io::Seek>(
+    reader: &mut R,
+    file_len: u64,
+) -> std::io::Result<MediaCompleteness> {
+    use std::io::SeekFrom;
+
+    const EBML_ID: u64 = 0x1A45_DFA3;
+    const SEGMENT_ID: u64 = 0x1853_8067;
+    const CLUSTER_ID: u64 = 0x1F43_B675;
+
+    let truncated = |what: &str| {
+        Ok(MediaCompleteness::incomplete(format!(
+            "文件在 {} 处被截断（下载可能不完整）",
+            what
+        )))
+    };
 
-    // 检查窗口是否已经存在
-    if let Some(window) = app.get_webview_window("log-monitor") {
-        // 窗口已存在，确保显示并聚焦到前台
-        window
-            .unminimize()
-            .map_err(|e| format!("Failed to unminimize log monitor window: {}", e))?;
-        window
-            .show()
-            .map_err(|e| format!("Failed to show log monitor window: {}", e))?;
-        window
-            .set_focus()
-            .map_err(|e| format!("Failed to focus log monitor window: {}", e))?;
-        println!("[LogMonitor] 窗口已存在，已显示到前台");
-        return Ok(());
+    reader.seek(SeekFrom::Start(0))?;
+    let (id, id_len) = read_ebml_vint(reader, true)?;
+    if id != Some(EBML_ID) {
+        return Ok(MediaCompleteness::incomplete("缺少 EBML 头"));
+    }
+    let (header_size, size_len) = read_ebml_vint(reader, false)?;
+    let Some(header_size) = header_size else {
+        return Ok(MediaCompleteness::incomplete("EBML 头大小无效"));
+    };
+    let mut offset = id_len + size_len + header_size;
+    if offset >= file_len {
+        return truncated("EBML 头");
     }
 
-    // 窗口不存在，创建新窗口
-    let window = WebviewWindowBuilder::new(
-        &app,
-        "log-monitor",
-        tauri::WebviewUrl::App("/log-monitor".into()),
-    )
-    .title("MatrixGen Pro - Console")
-    .inner_size(800.0, 600.0)
-    .resizable(true)
-    .always_on_top(false)
-    .position(100.0, 100.0)
-    .decorations(true)
-    .transparent(false)
-    .build()
-    .map_err(|e| format!("Failed to create log monitor window: {}", e))?;
+    // 查找 Segment（跳过其前面的其他顶层元素）
+    let (segment_start, segment_end) = loop {
+        if file_len - offset < 2 {
+            return truncated("Segment 头");
+        }
+        reader.seek(SeekFrom::Start(offset))?;
+        let (id, id_len) = read_ebml_vint(reader, true)?;
+        let (size, size_len) = read_ebml_vint(reader, false)?;
+        let data_start = offset + id_len + size_len;
+        if id == Some(SEGMENT_ID) {
+            match size {
+                Some(size) if data_start + size > file_len => {
+                    return Ok(MediaCompleteness::incomplete(format!(
+                        "Segment 声明 {} 字节，但文件只剩 {} 字节（下载可能被截断）",
+                        size,
+                        file_len.saturating_sub(data_start)
+                    )));
+                }
+                Some(size) => break (data_start, data_start + size),
+                // 流式写入的文件 Segment 大小未知，检查到文件末尾
+                None => break (data_start, file_len),
+            }
+        }
+        match size {
+            Some(size) if data_start + size <= file_len => offset = data_start + size,
+            _ => return Ok(MediaCompleteness::incomplete("未找到 Segment 元素")),
+        }
+    };
 
-    println!("[LogMonitor] 创建了新的日志监视器窗口");
-    Ok(())
+    // 逐个检查 Segment 的顶层子元素
+    let mut offset = segment_start;
+    let mut has_cluster = false;
+    while offset < segment_end {
+        if segment_end - offset < 2 {
+            return truncated("Segment 子元素头");
+        }
+        reader.seek(SeekFrom::Start(offset))?;
+        let header = read_ebml_vint(reader, true).and_then(|(id, id_len)| {
+            read_ebml_vint(reader, false).map(|(size, size_len)| (id, size, id_len + size_len))
+        });
+        let (id, size, header_len) = match header {
+            Ok(header) => header,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                return truncated("Segment 子元素头")
+            }
+            Err(e) => return Err(e),
+        };
+        if id == Some(CLUSTER_ID) {
+            has_cluster = true;
+        }
+        let Some(size) = size else {
+            // 大小未知的 Cluster（直播式封装）无法按大小继续校验
+            break;
+        };
+        let end = offset + header_len + size;
+        if end > segment_end {
+            return Ok(MediaCompleteness::incomplete(format!(
+                "元素 0x{:X} 声明 {} 字节，超出文件末尾（下载可能被截断）",
+                id.unwrap_or(0),
+                size
+            )));
+        }
+        offset = end;
+    }
+
+    if !has_cluster {
+        return Ok(MediaCompleteness::incomplete("缺少 Cluster（媒体数据）"));
+    }
+    Ok(MediaCompleteness::complete())
 }
 
-// 阿里云 OSS 上传功能已迁移到 Supabase Storage，前端直接使用 Supabase SDK
+// 检查下载的媒体文件容器结构是否完整（MP4/MOV 与 WebM/MKV），用于发现被截断的下载
+#[command]
+pub fn verify_media_complete(path: String) -> Result<MediaCompleteness, String> {
+    use std::io::Read;
+
+    let mut file = std::fs::File::open(&path).map_err(|e| format!("无法打开文件: {}", e))?;
+    let file_len = file
+        .metadata()
+        .map_err(|e| format!("无法读取文件信息: {}", e))?
+        .len();
+
+    let mut magic = [0u8; 8];
+    if file_len < 8 || file.read_exact(&mut magic).is_err() {
+        return Ok(MediaCompleteness::incomplete(
+            "文件过小，不是有效的媒体文件",
+        ));
+    }
 
-// 清理目录的辅助函数
-fn cleanup_directory(
-    dir: &std::path::Path,
-    deleted_count: &mut u64,
-    total_size: &mut u64,
-) -> Result<(), String> {
-    if !dir.exists() {
-        return Ok(());
+    let result = if &magic[4..8] == b"ftyp" {
+        check_mp4_boxes(&mut file, file_len)
+    } else if magic[..4] == [0x1A, 0x45, 0xDF, 0xA3] {
+        check_webm_elements(&mut file, file_len)
+    } else {
+        return Err(format!(
+            "不支持的媒体格式（仅支持 MP4/MOV/WebM/MKV）: {}",
+            path
+        ));
+    };
+
+    let result = match result {
+        Ok(result) => result,
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            MediaCompleteness::incomplete("文件意外结束（下载可能被截断）")
+        }
+        Err(e) => return Err(format!("无法解析媒体文件: {}", e)),
+    };
+
+    if let Some(reason) = &result.reason {
+        warn!("[VerifyMedia] 文件不完整 {}: {}", path, reason);
+    } else {
+        debug!("[VerifyMedia] 文件完整: {}", path);
     }
+    Ok(result)
+}
 
-    for entry in std::fs::read_dir(dir).map_err(|e| format!("Unable to read directory: {}", e))? {
-        let entry = entry.map_err(|e| format!("Failed to read directory entry: {}", e))?;
-        let path = entry.path();
+// MP4 顶层 box 的位置信息
+struct Mp4Box {
+    box_type: [u8; 4],
+    offset: u64,
+    size: u64,
+}
 
-        if path.is_dir() {
-            // 递归清理子目录
-            cleanup_directory(&path, deleted_count, total_size)?;
-            // 删除空目录
-            if let Err(e) = std::fs::remove_dir(&path) {
-                println!(
-                    "[Cleanup] Failed to remove directory {}: {}",
-                    path.display(),
-                    e
-                );
-            } else {
-                *deleted_count += 1;
-            }
-        } else {
-            // 删除文件
-            if let Ok(metadata) = std::fs::metadata(&path) {
-                *total_size += metadata.len();
-            }
-            if let Err(e) = std::fs::remove_file(&path) {
-                println!("[Cleanup] Failed to remove file {}: {}", path.display(), e);
-            } else {
-                *deleted_count += 1;
+// 列出 MP4 文件的顶层 box，box 超出文件长度时返回错误
+fn read_mp4_top_level_boxes<R: std::io::Read + std::io::Seek>(
+    reader: &mut R,
+    file_len: u64,
+) -> Result<Vec<Mp4Box>, String> {
+    use std::io::SeekFrom;
+
+    let mut boxes = Vec::new();
+    let mut offset = 0u64;
+    while file_len - offset >= 8 {
+        let mut header = [0u8; 8];
+        reader
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| reader.read_exact(&mut header))
+            .map_err(|e| format!("无法读取 MP4 box: {}", e))?;
+        let box_type = [header[4], header[5], header[6], header[7]];
+        let size = match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+            0 => file_len - offset,
+            1 => {
+                if file_len - offset < 16 {
+                    return Err(format!(
+                        "{} box 的扩展头不完整，文件可能已损坏或不完整",
+                        String::from_utf8_lossy(&box_type)
+                    ));
+                }
+                let mut large = [0u8; 8];
+                reader
+                    .read_exact(&mut large)
+                    .map_err(|e| format!("无法读取 MP4 box: {}", e))?;
+                u64::from_be_bytes(large)
             }
+            size => size as u64,
+        };
+        // 与 check_mp4_boxes 一致用减法比较，避免 64 位大小相加溢出
+        if size < 8 || size > file_len - offset {
+            return Err(format!(
+                "{} box 大小无效，文件可能已损坏或不完整",
+                String::from_utf8_lossy(&box_type)
+            ));
         }
+        boxes.push(Mp4Box {
+            box_type,
+            offset,
+            size,
+        });
+        offset += size;
     }
+    Ok(boxes)
+}
 
+// 递归修正 moov 中 stco/co64 的块偏移量；adjust 根据原偏移计算新偏移
+fn patch_chunk_offsets(data: &mut [u8], adjust: &dyn Fn(u64) -> u64) -> Result<(), String> {
+    const CONTAINERS: &[&[u8; 4]] = &[b"moov", b"trak", b"mdia", b"minf", b"stbl"];
+
+    let mut pos = 0usize;
+    while pos + 8 <= data.len() {
+        let size =
+            u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+        let box_type: [u8; 4] = [data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]];
+        if size < 8 || pos + size > data.len() {
+            return Err("moov 结构无效（不支持 64 位大小的子 box）".to_string());
+        }
+        let body = &mut data[pos + 8..pos + size];
+
+        match &box_type {
+            b"cmov" => return Err("不支持压缩的 moov".to_string()),
+            b"stco" | b"co64" => {
+                let entry_size = if &box_type == b"stco" { 4 } else { 8 };
+                if body.len() < 8 {
+                    return Err("块偏移表无效".to_string());
+                }
+                let count = u32::from_be_bytes([body[4], body[5], body[6], body[7]]) as usize;
+                if body.len() < 8 + count * entry_size {
+                    return Err("块偏移表长度不足".to_string());
+                }
+                for entry in body[8..8 + count * entry_size].chunks_exact_mut(entry_size) {
+                    if entry_size == 4 {
+                        let old = u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]);
+                        let new = u32::try_from(adjust(old as u64)).map_err(|_| {
+                            "移动后块偏移超出 32 位范围（需要 co64），暂不支持".to_string()
+                        })?;
+                        entry.copy_from_slice(&new.to_be_bytes());
+                    } else {
+                        let mut old = [0u8; 8];
+                        old.copy_from_slice(entry);
+                        entry.copy_from_slice(&adjust(u64::from_be_bytes(old)).to_be_bytes());
+                    }
+                }
+            }
+            t if CONTAINERS.contains(&t) => patch_chunk_offsets(body, adjust)?,
+            _ => {}
+        }
+        pos += size;
+    }
     Ok(())
 }
 
-// 清理临时文件的函数
-pub fn cleanup_temp_files() -> Result<(), String> {
-    println!("[Cleanup] Starting temp file cleanup...");
-
-    // 获取临时目录
-    let temp_dir = std::env::temp_dir().join("matrix-gen");
+// 将 MP4 的 moov box 移动到媒体数据之前（qt-faststart），使视频可以边下载边播放
+// 未指定 output_path 时原地替换，返回输出文件路径
+#[command]
+pub async fn optimize_mp4_faststart(
+    path: String,
+    output_path: Option<String>,
+) -> Result<String, String> {
+    tokio::task::spawn_blocking(move || {
+        use std::io::{Read, Seek, SeekFrom, Write};
+
+        let mut input = std::fs::File::open(&path).map_err(|e| format!("无法打开文件: {}", e))?;
+        let file_len = input
+            .metadata()
+            .map_err(|e| format!("无法读取文件信息: {}", e))?
+            .len();
+        let boxes = read_mp4_top_level_boxes(&mut input, file_len)?;
+
+        let moov = boxes
+            .iter()
+            .find(|b| &b.box_type == b"moov")
+            .ok_or("文件中没有 moov box")?;
+        let first_mdat = boxes
+            .iter()
+            .find(|b| &b.box_type == b"mdat")
+            .ok_or("文件中没有 mdat box")?;
+
+        if moov.offset < first_mdat.offset {
+            info!("[FastStart] moov 已位于文件开头: {}", path);
+            if let Some(output_path) = output_path.filter(|p| *p != path) {
+                std::fs::copy(&path, &output_path).map_err(|e| format!("无法复制文件: {}", e))?;
+                return Ok(output_path);
+            }
+            return Ok(path);
+        }
 
-    // 如果临时目录不存在，直接返回
-    if !temp_dir.exists() {
-        println!("[Cleanup] Temp directory doesn't exist, skipping cleanup");
-        return Ok(());
-    }
+        // moov 插入到第一个 mdat 之前；位于插入点与原 moov 之间的数据整体后移 moov 大小
+        let insert_at = first_mdat.offset;
+        let (moov_start, moov_size) = (moov.offset, moov.size);
+        let mut moov_data = vec![0u8; moov_size as usize];
+        input
+            .seek(SeekFrom::Start(moov_start))
+            .and_then(|_| input.read_exact(&mut moov_data))
+            .map_err(|e| format!("无法读取 moov: {}", e))?;
+        if u32::from_be_bytes([moov_data[0], moov_data[1], moov_data[2], moov_data[3]]) == 1 {
+            return Err("不支持 64 位大小的 moov".to_string());
+        }
+        patch_chunk_offsets(&mut moov_data[8..], &|offset| {
+            if offset >= insert_at && offset < moov_start {
+                offset + moov_size
+            } else {
+                offset
+            }
+        })?;
+
+        let target = PathBuf::from(output_path.as_deref().unwrap_or(&path));
+        let temp_path = target.with_extension("faststart.tmp");
+        let write_result = (|| -> std::io::Result<()> {
+            let mut output = std::io::BufWriter::new(std::fs::File::create(&temp_path)?);
+            for b in boxes.iter().filter(|b| &b.box_type != b"moov") {
+                if b.offset == insert_at {
+                    output.write_all(&moov_data)?;
+                }
+                input.seek(SeekFrom::Start(b.offset))?;
+                std::io::copy(&mut (&mut input).take(b.size), &mut output)?;
+            }
+            output.flush()?;
+            output.get_ref().sync_all()
+        })();
 
-    let mut deleted_count = 0;
-    let mut total_size = 0u64;
+        if let Err(e) = write_result {
+            let _ = std::fs::remove_file(&temp_path);
+            return Err(format!("写入输出文件失败: {}", e));
+        }
+        drop(input);
+        std::fs::rename(&temp_path, &target).map_err(|e| {
+            let _ = std::fs::remove_file(&temp_path);
+            format!("无法保存输出文件: {}", e)
+        })?;
+
+        let target_str = target.to_string_lossy().to_string();
+        info!("[FastStart] 已将 moov 移至文件开头: {}", target_str);
+        Ok(target_str)
+    })
+    .await
+    .map_err(|e| format!("处理线程异常: {}", e))?
+}
 
-    // 清理主临时目录
-    cleanup_directory(&temp_dir, &mut deleted_count, &mut total_size)?;
+// 媒体探测结果（error 不为空时其他字段可能缺失）
+#[derive(Debug, Default, Serialize)]
+pub struct MediaProbe {
+    pub path: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub duration_secs: Option<f64>,
+    pub error: Option<String>,
+}
 
-    // 清理专用 temp 子目录（如果存在）
-    let temp_subdir = temp_dir.join("temp");
-    if temp_subdir.exists() {
-        cleanup_directory(&temp_subdir, &mut deleted_count, &mut total_size)?;
-    }
+// 在 box 数据中查找指定类型的子 box，返回其内容
+fn find_mp4_child<'a>(data: &'a [u8], box_type: &[u8; 4]) -> Option<&'a [u8]> {
+    mp4_children(data)
+        .find(|(t, _)| t == box_type)
+        .map(|(_, body)| body)
+}
 
-    println!(
-        "[Cleanup] Cleanup completed: removed {} items, total size {} bytes",
-        deleted_count, total_size
-    );
-    Ok(())
+// 遍历 box 数据中的子 box：(类型, 内容)
+fn mp4_children(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut pos = 0usize;
+    std::iter::from_fn(move || {
+        if pos + 8 > data.len() {
+            return None;
+        }
+        let size = u32::from_be_bytes(data[pos..pos + 4].try_into().ok()?) as usize;
+        let box_type: [u8; 4] = data[pos + 4..pos + 8].try_into().ok()?;
+        if size < 8 || pos + size > data.len() {
+            return None;
+        }
+        let body = &data[pos + 8..pos + size];
+        pos += size;
+        Some((box_type, body))
+    })
 }
 
-// 在文件管理器中打开文件夹并选中文件
-#[command]
-pub async fn show_in_folder(path: String) -> Result<(), String> {
-    // Log the attempt
-    println!("[Rust] Attempting to open path: {}", path);
+// 探测时读取 moov 的上限，防止损坏文件声明超大 box 导致一次性分配过多内存
+const MP4_PROBE_MAX_MOOV_BYTES: u64 = 64 * 1024 * 1024;
 
-    #[cfg(target_os = "windows")]
-    {
-        // Windows trick: "explorer /select, path" opens the folder AND highlights the file
-        // If it's just a folder, just "explorer path"
-        let path_obj = std::path::Path::new(&path);
-        if path_obj.is_file() {
-            std::process::Command::new("explorer")
-                .args(["/select,", &path]) // Comma is important for /select
-                .spawn()
-                .map_err(|e| format!("Failed to open file in explorer: {}", e))?;
+// 从 MP4 的 moov 中读取时长（mvhd）与视频轨道尺寸（tkhd）
+fn probe_mp4(
+    file: &mut std::fs::File,
+    file_len: u64,
+    probe: &mut MediaProbe,
+) -> Result<(), String> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let boxes = read_mp4_top_level_boxes(file, file_len)?;
+    let moov = boxes
+        .iter()
+        .find(|b| &b.box_type == b"moov")
+        .ok_or("文件中没有 moov box")?;
+    if moov.size > MP4_PROBE_MAX_MOOV_BYTES {
+        return Err(format!("moov box 过大: {} 字节", moov.size));
+    }
+    let mut moov_data = vec![0u8; moov.size as usize];
+    file.seek(SeekFrom::Start(moov.offset))
+        .and_then(|_| file.read_exact(&mut moov_data))
+        .map_err(|e| format!("无法读取 moov: {}", e))?;
+    let moov_body = moov_data.get(8..).ok_or("moov 结构无效")?;
+
+    let be_u32 = |b: &[u8], at: usize| {
+        b.get(at..at + 4)
+            .map(|s| u32::from_be_bytes(s.try_into().unwrap()))
+    };
+    let be_u64 = |b: &[u8], at: usize| {
+        b.get(at..at + 8)
+            .map(|s| u64::from_be_bytes(s.try_into().unwrap()))
+    };
+
+    // mvhd: version 0 为 32 位时间字段，version 1 为 64 位
+    if let Some(mvhd) = find_mp4_child(moov_body, b"mvhd") {
+        let (timescale, duration) = if mvhd.first() == Some(&1) {
+            (be_u32(mvhd, 20), be_u64(mvhd, 24))
         } else {
-            std::process::Command::new("explorer")
-                .arg(&path)
-                .spawn()
-                .map_err(|e| format!("Failed to open folder in explorer: {}", e))?;
+            (be_u32(mvhd, 12), be_u32(mvhd, 16).map(u64::from))
+        };
+        if let (Some(timescale), Some(duration)) = (timescale, duration) {
+            if timescale > 0 {
+                probe.duration_secs = Some(duration as f64 / timescale as f64);
+            }
         }
     }
 
-    #[cfg(not(target_os = "windows"))]
-    {
-        // Fallback for Mac/Linux (just open it)
-        open::that(&path).map_err(|e| format!("Failed to open path: {}", e))?;
+    // 取第一个视频轨道的 tkhd 宽高（末尾 8 字节，16.16 定点数）
+    for (_, trak) in mp4_children(moov_body).filter(|(t, _)| t == b"trak") {
+        let is_video = find_mp4_child(trak, b"mdia")
+            .and_then(|mdia| find_mp4_child(mdia, b"hdlr"))
+            .is_some_and(|hdlr| hdlr.get(8..12) == Some(b"vide"));
+        let Some(tkhd) = find_mp4_child(trak, b"tkhd").filter(|_| is_video) else {
+            continue;
+        };
+        if tkhd.len() >= 8 {
+            probe.width = be_u32(tkhd, tkhd.len() - 8).map(|w| w >> 16);
+            probe.height = be_u32(tkhd, tkhd.len() - 4).map(|h| h >> 16);
+            break;
+        }
     }
-
     Ok(())
 }
 
-// 检查生成锁（允许并发生成）
-#[command]
-pub fn check_generation_lock(_state: State<'_, Mutex<bool>>) -> Result<bool, String> {
-    // 允许并发，总是返回 true
-    Ok(true)
+// 遍历 EBML 数据中的子元素：(ID, 内容)；未知大小的元素视为延伸到数据末尾
+fn ebml_children(data: &[u8]) -> impl Iterator<Item = (u64, &[u8])> {
+    let mut pos = 0usize;
+    std::iter::from_fn(move || {
+        let mut cursor = std::io::Cursor::new(data.get(pos..)?);
+        let (id, _) = read_ebml_vint(&mut cursor, true).ok()?;
+        let (size, _) = read_ebml_vint(&mut cursor, false).ok()?;
+        let start = pos + cursor.position() as usize;
+        let end = size
+            .map(|size| start.saturating_add(size as usize))
+            .unwrap_or(data.len())
+            .min(data.len());
+        pos = end;
+        Some((id?, data.get(start..end)?))
+    })
 }
 
-// 释放生成锁（无操作，因为没有锁定）
-#[command]
-pub fn release_generation_lock(_state: State<'_, Mutex<bool>>) -> Result<(), String> {
-    // 无操作，允许并发
-    Ok(())
+// 读取 EBML 无符号整数 / 浮点数元素
+fn ebml_uint(data: &[u8]) -> u64 {
+    data.iter().fold(0, |acc, b| (acc << 8) | *b as u64)
 }
 
-// 下载文件到本地临时目录
-#[command]
-pub async fn download_file(url: String, file_name: String) -> Result<String, String> {
-    let cache_dir = std::env::temp_dir().join("matrix-gen").join("temp");
-
-    // 确保目录存在
-    if let Err(e) = std::fs::create_dir_all(&cache_dir) {
-        println!("[Download] 创建缓存目录失败: {}", e);
-        return Err(format!("无法创建缓存目录: {}", e));
+fn ebml_float(data: &[u8]) -> Option<f64> {
+    match data.len() {
+        4 => Some(f32::from_be_bytes(data.try_into().ok()?) as f64),
+        8 => Some(f64::from_be_bytes(data.try_into().ok()?)),
+        _ => None,
     }
+}
 
-    let file_path = cache_dir.join(&file_name);
-    let file_path_str = file_path.to_string_lossy().to_string();
-
-    println!("[Download] 开始下载到临时文件: {}", file_path_str);
-
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(480)) // 8 分钟请求超时
-        .connect_timeout(std::time::Duration::from_secs(300)) // 5 分钟连接超时
-        .build()
-        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+// 从 WebM/MKV 的 Segment 头部读取 Info（时长）与 Tracks（视频尺寸）
+fn probe_webm(file: &mut std::fs::File, probe: &mut MediaProbe) -> Result<(), String> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    const SEGMENT_ID: u64 = 0x1853_8067;
+    const INFO_ID: u64 = 0x1549_A966;
+    const TRACKS_ID: u64 = 0x1654_AE6B;
+    const CLUSTER_ID: u64 = 0x1F43_B675;
+    // Info 与 Tracks 通常位于文件开头，只读取前 1MB
+    const HEAD_LIMIT: u64 = 1024 * 1024;
+
+    let mut head = Vec::new();
+    file.seek(SeekFrom::Start(0))
+        .and_then(|_| file.take(HEAD_LIMIT).read_to_end(&mut head))
+        .map_err(|e| format!("无法读取文件: {}", e))?;
+
+    let segment = ebml_children(&head)
+        .find(|(id, _)| *id == SEGMENT_ID)
+        .map(|(_, body)| body)
+        .ok_or("未找到 Segment 元素")?;
+
+    for (id, body) in ebml_children(segment) {
+        match id {
+            INFO_ID => {
+                let mut timecode_scale = 1_000_000u64;
+                let mut duration = None;
+                for (child_id, value) in ebml_children(body) {
+                    match child_id {
+                        0x2A_D7B1 => timecode_scale = ebml_uint(value),
+                        0x4489 => duration = ebml_float(value),
+                        _ => {}
+                    }
+                }
+                probe.duration_secs = duration.map(|d| d * timecode_scale as f64 / 1e9);
+            }
+            TRACKS_ID => {
+                let video = ebml_children(body)
+                    .filter(|(id, _)| *id == 0xAE)
+                    .find_map(|(_, entry)| ebml_children(entry).find(|(id, _)| *id == 0xE0))
+                    .map(|(_, video)| video);
+                for (child_id, value) in video.into_iter().flat_map(ebml_children) {
+                    match child_id {
+                        0xB0 => probe.width = Some(ebml_uint(value) as u32),
+                        0xBA => probe.height = Some(ebml_uint(value) as u32),
+                        _ => {}
+                    }
+                }
+            }
+            CLUSTER_ID => break,
+            _ => {}
+        }
+    }
+    Ok(())
+}
 
-    let response = client
-        .get(&url)
-        .send()
-        .await
-        .map_err(|e| format!("Failed to download file: {}", e))?;
+// 探测单个媒体文件的尺寸与时长（MP4/MOV、WebM/MKV 及常见图片格式）
+fn probe_media_file(path: &str) -> MediaProbe {
+    use std::io::Read;
 
-    if !response.status().is_success() {
-        return Err(format!(
-            "Download failed with status: {}",
-            response.status()
-        ));
-    }
+    let mut probe = MediaProbe {
+        path: path.to_string(),
+        ..Default::default()
+    };
+    let result = (|| {
+        let mut file = std::fs::File::open(path).map_err(|e| format!("无法打开文件: {}", e))?;
+        let file_len = file
+            .metadata()
+            .map_err(|e| format!("无法读取文件信息: {}", e))?
+            .len();
+        let mut magic = [0u8; 8];
+        file.read_exact(&mut magic)
+            .map_err(|_| "文件过小，不是有效的媒体文件".to_string())?;
+
+        if &magic[4..8] == b"ftyp" {
+            probe_mp4(&mut file, file_len, &mut probe)
+        } else if magic[..4] == [0x1A, 0x45, 0xDF, 0xA3] {
+            probe_webm(&mut file, &mut probe)
+        } else {
+            let (width, height) =
+                image::image_dimensions(path).map_err(|e| format!("不支持的媒体格式: {}", e))?;
+            probe.width = Some(width);
+            probe.height = Some(height);
+            Ok(())
+        }
+    })();
+    probe.error = result.err();
+    probe
+}
 
-    let content = response
-        .bytes()
-        .await
-        .map_err(|e| format!("Failed to read response body: {}", e))?;
+// 批量探测媒体文件的尺寸与时长（有限并发），单个文件失败不影响其他文件，结果顺序与输入一致
+#[command]
+pub async fn probe_media_batch(paths: Vec<String>) -> Result<Vec<MediaProbe>, String> {
+    const CONCURRENCY: usize = 8;
+
+    let semaphore = std::sync::Arc::new(tokio::sync::Semaphore::new(CONCURRENCY));
+    let mut tasks = tokio::task::JoinSet::new();
+    for (index, path) in paths.iter().cloned().enumerate() {
+        let semaphore = semaphore.clone();
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            let probe = tokio::task::spawn_blocking({
+                let path = path.clone();
+                move || probe_media_file(&path)
+            })
+            .await
+            .unwrap_or_else(|e| MediaProbe {
+                path,
+                error: Some(format!("探测线程异常: {}", e)),
+                ..Default::default()
+            });
+            (index, probe)
+        });
+    }
 
-    std::fs::write(&file_path, &content).map_err(|e| format!("Failed to write file: {}", e))?;
+    let mut results: Vec<Option<MediaProbe>> = paths.iter().map(|_| None).collect();
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok((index, probe)) = joined {
+            results[index] = Some(probe);
+        }
+    }
 
-    println!(
-        "[Download] 文件下载成功: {} ({} bytes)",
-        file_path_str,
-        content.len()
+    let results: Vec<MediaProbe> = results
+        .into_iter()
+        .zip(paths)
+        .map(|(probe, path)| {
+            probe.unwrap_or_else(|| MediaProbe {
+                path,
+                error: Some("探测任务异常退出".to_string()),
+                ..Default::default()
+            })
+        })
+        .collect();
+    debug!(
+        "[ProbeMedia] 批量探测 {} 个文件，失败 {} 个",
+        results.len(),
+        results.iter().filter(|p| p.error.is_some()).count()
     );
+    Ok(results)
+}
 
-    Ok(file_path_str)
+// 查找 ffmpeg：优先使用 MATRIXGEN_FFMPEG 环境变量，其次是可执行文件同目录，最后是 PATH
+fn find_ffmpeg() -> PathBuf {
+    if let Ok(custom) = std::env::var("MATRIXGEN_FFMPEG") {
+        if !custom.is_empty() {
+            return PathBuf::from(custom);
+        }
+    }
+
+    let binary = if cfg!(target_os = "windows") {
+        "ffmpeg.exe"
+    } else {
+        "ffmpeg"
+    };
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join(binary)))
+        .filter(|bundled| bundled.exists())
+        .unwrap_or_else(|| PathBuf::from(binary))
 }
 
-// 执行 PowerShell 命令（用于声音通知）
-#[command]
-pub async fn execute_powershell_command(command: String) -> Result<(), String> {
-    use std::process::Command;
+// 运行 ffmpeg，失败时返回其错误输出
+fn run_ffmpeg(args: &[std::ffi::OsString]) -> Result<(), String> {
+    let ffmpeg = find_ffmpeg();
+    let mut command = std::process::Command::new(&ffmpeg);
+    command
+        .args(["-hide_banner", "-loglevel", "error", "-y"])
+        .args(args);
 
-    let output = Command::new("powershell")
-        .arg("-c")
-        .arg(&command)
-        .output()
-        .map_err(|e| format!("Failed to execute PowerShell command: {}", e))?;
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
 
+    let output = command
+        .output()
+        .map_err(|e| format!("无法启动 ffmpeg ({}): {}", ffmpeg.display(), e))?;
     if output.status.success() {
         Ok(())
     } else {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        Err(format!("PowerShell command failed: {}", stderr))
+        Err(format!(
+            "ffmpeg 执行失败: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
     }
 }
 
-// 打开输出文件夹
-#[command]
-pub fn open_output_folder(app: tauri::AppHandle) -> Result<(), String> {
-    // 获取输出目录路径（与write_output_file使用相同的逻辑）
-    let output_dir = match app.path().video_dir() {
-        Ok(video_dir) => {
-            println!("[OpenFolder] 使用系统视频目录: {:?}", video_dir);
-            video_dir.join("MatrixGen_Output")
+// 批量缩略图选项
+#[derive(Debug, Default, Deserialize)]
+pub struct ThumbnailOptions {
+    pub max_size: Option<u32>,
+    pub format: Option<String>, // "jpeg"（默认）、"png" 或 "webp"
+    pub concurrency: Option<usize>,
+}
+
+// 缩略图生成进度事件
+#[derive(Debug, Clone, Serialize)]
+pub struct ThumbnailProgressEvent {
+    pub done: usize,
+    pub total: usize,
+    pub source: String,
+    pub error: Option<String>,
+}
+
+// 生成单个缩略图：图片直接缩放，视频通过 ffmpeg 截取一帧
+fn generate_thumbnail(
+    source: &std::path::Path,
+    target: &std::path::Path,
+    max_size: u32,
+    format: image::ImageFormat,
+) -> Result<(), String> {
+    if infer_media_type(source) == "video" {
+        let scale = format!(
+            "scale=w={0}:h={0}:force_original_aspect_ratio=decrease",
+            max_size
+        );
+        let extract = |seek: &str| {
+            run_ffmpeg(&[
+                "-ss".into(),
+                seek.into(),
+                "-i".into(),
+                source.as_os_str().to_os_string(),
+                "-frames:v".into(),
+                "1".into(),
+                "-vf".into(),
+                scale.clone().into(),
+                target.as_os_str().to_os_string(),
+            ])
+        };
+        // 先删除旧缩略图，避免 ffmpeg 失败时把上一次的结果当作成功
+        if let Err(e) = std::fs::remove_file(target) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(format!("无法删除旧缩略图: {}", e));
+            }
         }
-        Err(e) => {
-            println!(
-                "[OpenFolder] 获取系统视频目录失败: {}, 使用临时目录作为fallback",
-                e
-            );
-            std::env::temp_dir().join("MatrixGen_Output")
+        // 优先取第 1 秒的画面，过短的视频（ffmpeg 成功但不输出帧）退回到第一帧
+        if extract("1").is_err() || !target.exists() {
+            extract("0")?;
         }
+        return if target.exists() {
+            Ok(())
+        } else {
+            Err("ffmpeg 未能生成缩略图".to_string())
+        };
+    }
+
+    let thumbnail = image::open(source)
+        .map_err(|e| format!("无法读取图像: {}", e))?
+        .thumbnail(max_size, max_size);
+    // JPEG 不支持透明通道
+    let thumbnail = if format == image::ImageFormat::Jpeg {
+        image::DynamicImage::ImageRgb8(thumbnail.to_rgb8())
+    } else {
+        thumbnail
     };
+    thumbnail
+        .save_with_format(target, format)
+        .map_err(|e| format!("无法保存缩略图: {}", e))
+}
 
-    // 确保目录存在
-    if let Err(e) = std::fs::create_dir_all(&output_dir) {
-        println!("[OpenFolder] 创建目录失败: {}", e);
-        return Err(format!("无法创建输出目录: {}", e));
-    }
+// 为目录中的图片和视频批量生成缩略图（保存到 .thumbs 子目录），已是最新的缩略图会跳过
+// 返回 源文件路径 -> 缩略图路径，进度通过 thumbnail-progress 事件推送
+#[command]
+pub async fn generate_thumbnails_for_dir(
+    app: tauri::AppHandle,
+    dir: String,
+    options: Option<ThumbnailOptions>,
+) -> Result<HashMap<String, String>, String> {
+    let options = options.unwrap_or_default();
+    let max_size = options.max_size.unwrap_or(320).clamp(16, 2048);
+    let concurrency = options.concurrency.unwrap_or(4).clamp(1, 16);
+    let (format, extension) = match options.format.as_deref().unwrap_or("jpeg") {
+        "jpeg" | "jpg" => (image::ImageFormat::Jpeg, "jpg"),
+        "png" => (image::ImageFormat::Png, "png"),
+        "webp" => (image::ImageFormat::WebP, "webp"),
+        other => return Err(format!("不支持的缩略图格式: {}", other)),
+    };
 
-    let output_path = output_dir.to_string_lossy().to_string();
-    println!("[OpenFolder] 打开输出文件夹: {}", output_path);
+    let source_dir = PathBuf::from(&dir);
+    let thumbs_dir = source_dir.join(".thumbs");
+    std::fs::create_dir_all(&thumbs_dir).map_err(|e| format!("无法创建缩略图目录: {}", e))?;
 
-    // 在文件管理器中打开文件夹
-    #[cfg(target_os = "windows")]
+    let mut thumbnails = HashMap::new();
+    let mut pending = Vec::new();
+    for entry in std::fs::read_dir(&source_dir)
+        .map_err(|e| format!("无法读取目录: {}", e))?
+        .flatten()
     {
-        std::process::Command::new("explorer")
-            .arg(&output_path)
-            .spawn()
-            .map_err(|e| format!("Failed to open folder in explorer: {}", e))?;
+        let source = entry.path();
+        if !source.is_file() || infer_media_type(&source) == "other" {
+            continue;
+        }
+        let mut thumb_name = source.file_name().unwrap_or_default().to_os_string();
+        thumb_name.push(format!(".{}", extension));
+        let target = thumbs_dir.join(thumb_name);
+
+        // 缩略图不早于源文件时视为最新
+        let modified =
+            |path: &std::path::Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let is_current = matches!(
+            (modified(&source), modified(&target)),
+            (Some(source_time), Some(thumb_time)) if thumb_time >= source_time
+        );
+        if is_current {
+            thumbnails.insert(
+                source.to_string_lossy().to_string(),
+                target.to_string_lossy().to_string(),
+            );
+        } else {
+            pending.push((source, target));
+        }
     }
 
-    #[cfg(not(target_os = "windows"))]
-    {
-        open::that(&output_path).map_err(|e| format!("Failed to open folder: {}", e))?;
+    let total = pending.len();
+    info!(
+        "[Thumbnails] {} 个缩略图已是最新，需生成 {} 个 (并发 {})",
+        thumbnails.len(),
+        total,
+        concurrency
+    );
+
+    let semaphore = std::sync::Arc::new(tokio::sync::Semaphore::new(concurrency));
+    let done = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let mut tasks = tokio::task::JoinSet::new();
+    for (source, target) in pending {
+        let semaphore = semaphore.clone();
+        let done = done.clone();
+        let app = app.clone();
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            let result = {
+                let (source, target) = (source.clone(), target.clone());
+                tokio::task::spawn_blocking(move || {
+                    generate_thumbnail(&source, &target, max_size, format)
+                })
+                .await
+                .unwrap_or_else(|e| Err(format!("缩略图线程异常: {}", e)))
+            };
+
+            let source_str = source.to_string_lossy().to_string();
+            if let Err(e) = &result {
+                warn!("[Thumbnails] 生成失败 {}: {}", source_str, e);
+            }
+            let _ = app.emit(
+                "thumbnail-progress",
+                ThumbnailProgressEvent {
+                    done: done.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1,
+                    total,
+                    source: source_str.clone(),
+                    error: result.as_ref().err().cloned(),
+                },
+            );
+            result.map(|_| (source_str, target.to_string_lossy().to_string()))
+        });
     }
 
-    Ok(())
-}
+    while let Some(joined) = tasks.join_next().await {
+        if let Ok(Ok((source, target))) = joined {
+            thumbnails.insert(source, target);
+        }
+    }
 
-// 缓存远程图像到本地（用于绕过WebView跟踪预防）
-#[derive(Debug, Deserialize)]
-pub struct CacheImageOptions {
-    pub url: String,
-    pub file_name: String,
+    info!("[Thumbnails] 完成，共 {} 个缩略图", thumbnails.len());
+    Ok(thumbnails)
 }
 
-#[derive(Debug, Serialize)]
-pub struct CacheImageResponse {
-    pub success: bool,
-    pub local_path: Option<String>,
-    pub error: Option<String>,
+// 动图预览选项
+#[derive(Debug, Default, Deserialize)]
+pub struct AnimatedPreviewOptions {
+    pub start_secs: Option<f64>,
+    pub duration_secs: Option<f64>,
+    pub width: Option<u32>,
+    pub fps: Option<u32>,
 }
 
+// 从视频截取片段生成动画 WebP 预览（帧提取依赖 ffmpeg），返回预览文件路径
 #[command]
-pub async fn cache_image(options: CacheImageOptions) -> Result<CacheImageResponse, String> {
-    let CacheImageOptions { url, file_name } = options;
+pub async fn generate_animated_preview(
+    path: String,
+    options: Option<AnimatedPreviewOptions>,
+) -> Result<String, String> {
+    let options = options.unwrap_or_default();
+    let start_secs = options.start_secs.unwrap_or(0.0).max(0.0);
+    let duration_secs = options.duration_secs.unwrap_or(3.0).clamp(0.1, 30.0);
+    let width = options.width.unwrap_or(320).clamp(16, 1920);
+    let fps = options.fps.unwrap_or(10).clamp(1, 30);
+
+    let source = PathBuf::from(&path);
+    if !source.is_file() {
+        return Err(format!("视频文件不存在: {}", path));
+    }
 
-    println!("[CacheImage] 开始缓存图像: {} -> {}", url, file_name);
+    let preview_dir = std::env::temp_dir().join("matrix-gen").join("previews");
+    let stem = source
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("preview")
+        .to_string();
+    // 文件名附带完整源路径的哈希，避免同名不同扩展名或不同目录的视频互相覆盖
+    let path_hash = {
+        use sha2::Digest;
+        hex::encode(sha2::Sha256::digest(source.to_string_lossy().as_bytes()))
+    };
+    let output_path = preview_dir.join(format!("{}_{}_preview.webp", stem, &path_hash[..12]));
+    let frames_dir = preview_dir.join(format!(
+        "frames_{}_{}",
+        stem,
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0)
+    ));
+
+    info!(
+        "[AnimatedPreview] 生成预览: {} ({}s + {}s, {}px, {}fps)",
+        path, start_secs, duration_secs, width, fps
+    );
 
-    let cache_dir = std::env::temp_dir().join("matrix-gen").join("images");
+    let result = tokio::task::spawn_blocking({
+        let frames_dir = frames_dir.clone();
+        let output_path = output_path.clone();
+        move || {
+            std::fs::create_dir_all(&frames_dir).map_err(|e| format!("无法创建预览目录: {}", e))?;
+
+            // 1. 用 ffmpeg 抽帧并缩放
+            let frame_pattern = frames_dir.join("frame_%04d.png");
+            run_ffmpeg(&[
+                "-ss".into(),
+                format!("{:.3}", start_secs).into(),
+                "-t".into(),
+                format!("{:.3}", duration_secs).into(),
+                "-i".into(),
+                source.into_os_string(),
+                "-vf".into(),
+                format!("fps={},scale={}:-2", fps, width).into(),
+                frame_pattern.into_os_string(),
+            ])?;
+
+            let mut frame_paths: Vec<PathBuf> = std::fs::read_dir(&frames_dir)
+                .map_err(|e| format!("无法读取帧目录: {}", e))?
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|p| p.extension().and_then(|e| e.to_str()) == Some("png"))
+                .collect();
+            frame_paths.sort();
+
+            let frames = frame_paths
+                .iter()
+                .map(|p| image::open(p).map(|img| img.to_rgba8()))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("无法读取帧图像: {}", e))?;
+            let first = frames.first().ok_or("未能从视频中提取到帧")?;
+            let (frame_width, frame_height) = first.dimensions();
+
+            // 2. 编码为循环播放的动画 WebP
+            let mut config =
+                webp::WebPConfig::new().map_err(|_| "无法初始化 WebP 编码器".to_string())?;
+            config.quality = 75.0;
+            let mut encoder = webp::AnimEncoder::new(frame_width, frame_height, &config);
+            encoder.set_loop_count(0);
+            let frame_ms = 1000 / fps as i32;
+            for (index, frame) in frames.iter().enumerate() {
+                encoder.add_frame(webp::AnimFrame::from_rgba(
+                    frame.as_raw(),
+                    frame.width(),
+                    frame.height(),
+                    index as i32 * frame_ms,
+                ));
+            }
+            let encoded = encoder
+                .try_encode()
+                .map_err(|e| format!("WebP 编码失败: {:?}", e))?;
 
-    // 确保目录存在
-    if let Err(e) = std::fs::create_dir_all(&cache_dir) {
-        println!("[CacheImage] 创建缓存目录失败: {}", e);
-        return Err(format!("无法创建缓存目录: {}", e));
+            std::fs::write(&output_path, &*encoded)
+                .map_err(|e| format!("无法写入预览文件: {}", e))?;
+            Ok::<usize, String>(frames.len())
+        }
+    })
+    .await
+    .map_err(|e| format!("预览生成线程异常: {}", e))?;
+
+    // 清理临时帧
+    if let Err(e) = std::fs::remove_dir_all(&frames_dir) {
+        debug!("[AnimatedPreview] 清理帧目录失败: {}", e);
     }
 
-    let file_path = cache_dir.join(&file_name);
-    let file_path_str = file_path.to_string_lossy().to_string();
+    let frame_count = result?;
+    let output_str = output_path.to_string_lossy().to_string();
+    info!(
+        "[AnimatedPreview] 预览生成成功: {} ({} 帧)",
+        output_str, frame_count
+    );
+    Ok(output_str)
+}
 
-    println!("[CacheImage] 目标路径: {}", file_path_str);
+// 视频裁剪选项
+#[derive(Debug, Deserialize)]
+pub struct ClipVideoOptions {
+    pub source: String,
+    pub dest: String,
+    pub start_secs: f64,
+    pub end_secs: f64,
+}
 
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(120)) // 2分钟超时足够下载图像
-        .connect_timeout(std::time::Duration::from_secs(60)) // 1分钟连接超时
-        .build()
-        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+// 视频裁剪结果（duration_secs 为输出文件的实际时长）
+#[derive(Debug, Serialize)]
+pub struct ClipVideoResult {
+    pub path: String,
+    pub duration_secs: f64,
+}
 
-    let response = client
-        .get(&url)
-        .send()
-        .await
-        .map_err(|e| format!("Failed to download image: {}", e))?;
+// 无重新编码地截取视频片段（依赖 ffmpeg）
+// 流复制只能从关键帧开始，实际起点会落在 start_secs 之前最近的关键帧上
+#[command]
+pub async fn clip_video(options: ClipVideoOptions) -> Result<ClipVideoResult, String> {
+    let ClipVideoOptions {
+        source,
+        dest,
+        start_secs,
+        end_secs,
+    } = options;
 
-    if !response.status().is_success() {
-        return Ok(CacheImageResponse {
-            success: false,
-            local_path: None,
-            error: Some(format!("Download failed with status: {}", response.status())),
-        });
+    if !start_secs.is_finite() || !end_secs.is_finite() || start_secs < 0.0 {
+        return Err("无效的裁剪时间".to_string());
+    }
+    if end_secs <= start_secs {
+        return Err(format!(
+            "结束时间必须晚于开始时间: {} - {}",
+            start_secs, end_secs
+        ));
     }
 
-    let content = response
-        .bytes()
-        .await
-        .map_err(|e| format!("Failed to read response body: {}", e))?;
-
-    std::fs::write(&file_path, &content).map_err(|e| format!("Failed to write image file: {}", e))?;
+    let source_path = PathBuf::from(&source);
+    if !source_path.is_file() {
+        return Err(format!("视频文件不存在: {}", source));
+    }
+    let dest_path = PathBuf::from(&dest);
+    if dest_path.exists() && dest_path.canonicalize().ok() == source_path.canonicalize().ok() {
+        return Err("输出路径不能与源文件相同".to_string());
+    }
+    if let Some(parent) = dest_path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        ensure_writable_dir(parent).map_err(|e| format!("输出目录不可用: {}", e))?;
+    }
 
-    println!(
-        "[CacheImage] 图像缓存成功: {} ({} bytes)",
-        file_path_str,
-        content.len()
+    info!(
+        "[ClipVideo] 裁剪视频: {} [{:.3}s - {:.3}s] -> {}",
+        source, start_secs, end_secs, dest
     );
 
-    Ok(CacheImageResponse {
-        success: true,
-        local_path: Some(file_path_str),
-        error: None,
+    let probe = tokio::task::spawn_blocking({
+        let dest_path = dest_path.clone();
+        move || {
+            run_ffmpeg(&[
+                "-ss".into(),
+                format!("{:.3}", start_secs).into(),
+                "-i".into(),
+                source_path.into_os_string(),
+                "-t".into(),
+                format!("{:.3}", end_secs - start_secs).into(),
+                "-map".into(),
+                "0".into(),
+                "-c".into(),
+                "copy".into(),
+                "-avoid_negative_ts".into(),
+                "make_zero".into(),
+                dest_path.clone().into_os_string(),
+            ])?;
+            Ok::<MediaProbe, String>(probe_media_file(&dest_path.to_string_lossy()))
+        }
+    })
+    .await
+    .map_err(|e| format!("视频裁剪线程异常: {}", e))??;
+
+    // 无法解析容器时长（如非 MP4/WebM 格式）时返回请求的片段长度
+    let duration_secs = probe.duration_secs.unwrap_or(end_secs - start_secs);
+    info!(
+        "[ClipVideo] 裁剪完成: {} ({:.3}s)",
+        probe.path, duration_secs
+    );
+    Ok(ClipVideoResult {
+        path: probe.path,
+        duration_secs,
     })
 }
 
-// 重命名视频文件
+// 复制文本到系统剪贴板
 #[command]
-pub fn rename_video_file(old_path: String, new_base_name: String) -> Result<String, String> {
-    println!("[RenameVideo] 重命名文件: {} -> {}", old_path, new_base_name);
+pub fn copy_text_to_clipboard(text: String) -> Result<(), String> {
+    let mut clipboard = arboard::Clipboard::new().map_err(|e| format!("无法访问剪贴板: {}", e))?;
+    clipboard
+        .set_text(text)
+        .map_err(|e| format!("复制到剪贴板失败: {}", e))?;
 
-    let old_path_obj = std::path::Path::new(&old_path);
+    info!("[Clipboard] 文本已复制到剪贴板");
+    Ok(())
+}
+
+// 剪贴板图片（PNG 编码后的 base64）
+#[derive(Debug, Serialize)]
+pub struct ClipboardImage {
+    pub base64_data: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+// 读取剪贴板中的图片并编码为 PNG
+#[command]
+pub async fn read_clipboard_image() -> Result<ClipboardImage, String> {
+    tokio::task::spawn_blocking(|| {
+        let mut clipboard =
+            arboard::Clipboard::new().map_err(|e| format!("无法访问剪贴板: {}", e))?;
+        let image_data = match clipboard.get_image() {
+            Ok(data) => data,
+            Err(arboard::Error::ContentNotAvailable) => return Err("剪贴板中没有图片".to_string()),
+            Err(e) => return Err(format!("读取剪贴板图片失败: {}", e)),
+        };
+
+        let width = image_data.width as u32;
+        let height = image_data.height as u32;
+        let buffer = image::RgbaImage::from_raw(width, height, image_data.bytes.into_owned())
+            .ok_or("剪贴板图片数据不完整")?;
+
+        let mut png = std::io::Cursor::new(Vec::new());
+        buffer
+            .write_to(&mut png, image::ImageFormat::Png)
+            .map_err(|e| format!("PNG 编码失败: {}", e))?;
+
+        info!("[Clipboard] 已读取剪贴板图片: {}x{}", width, height);
+        Ok(ClipboardImage {
+            base64_data: base64::Engine::encode(
+                &base64::engine::general_purpose::STANDARD,
+                png.into_inner(),
+            ),
+            width,
+            height,
+        })
+    })
+    .await
+    .map_err(|e| format!("剪贴板读取线程异常: {}", e))?
+}
+
+// 计算重命名后的目标路径：保留原扩展名（无扩展名时直接使用新名称），并检查是否与其他文件重名
+fn output_rename_target(
+    old_path: &std::path::Path,
+    new_base_name: &str,
+) -> Result<PathBuf, String> {
+    // 新名称只能是文件名，不能借此移动到其他目录
+    if new_base_name.contains(['/', '\\']) {
+        return Err(format!("文件名不能包含路径分隔符: {}", new_base_name));
+    }
 
     // 获取父目录
-    let parent_dir = old_path_obj.parent().ok_or("无法获取父目录")?;
+    let parent_dir = old_path.parent().ok_or("无法获取父目录")?;
 
-    // 获取原始扩展名
-    let extension = old_path_obj
-        .extension()
-        .and_then(|ext| ext.to_str())
-        .ok_or("无法获取文件扩展名")?;
+    // 获取原始扩展名（可能没有）
+    let extension = old_path.extension().and_then(|ext| ext.to_str());
 
-    // 清理输入：如果用户输入了扩展名，移除它
-    let clean_name = if new_base_name.to_lowercase().ends_with(&format!(".{}", extension.to_lowercase())) {
-        &new_base_name[..new_base_name.len() - extension.len() - 1] // -1 for the dot
-    } else {
-        &new_base_name
-    };
+    // 替换非法字符，避免在文件系统层才报错
+    let new_base_name = normalize_file_name(new_base_name, "_")?;
 
-    // 构造新文件名（确保不重复扩展名）
-    let new_file_name = format!("{}.{}", clean_name, extension);
+    // 构造新文件名：如果用户输入了扩展名，移除它，确保不重复扩展名
+    let new_file_name = match extension {
+        Some(extension) => {
+            let suffix = format!(".{}", extension.to_lowercase());
+            let clean_name = if new_base_name.to_lowercase().ends_with(&suffix) {
+                &new_base_name[..new_base_name.len() - suffix.len()]
+            } else {
+                &new_base_name
+            };
+            format!("{}.{}", clean_name, extension)
+        }
+        None => new_base_name,
+    };
 
     // 构造新路径
     let new_full_path = parent_dir.join(new_file_name);
@@ -1257,22 +9983,314 @@ pub fn rename_video_file(old_path: String, new_base_name: String) -> Result<Stri
     // 检查新文件名是否已存在（排除当前文件本身）
     if new_full_path.exists() {
         // 规范化路径进行比较，确保正确判断是否为同一文件
-        let old_path_canonical = old_path_obj.canonicalize().unwrap_or_else(|_| old_path_obj.to_path_buf());
-        let new_path_canonical = new_full_path.canonicalize().unwrap_or_else(|_| new_full_path.clone());
-        
+        let old_path_canonical = old_path
+            .canonicalize()
+            .unwrap_or_else(|_| old_path.to_path_buf());
+        let new_path_canonical = new_full_path
+            .canonicalize()
+            .unwrap_or_else(|_| new_full_path.clone());
+
         // 如果规范化后的路径不同，说明是另一个文件，报错
         if old_path_canonical != new_path_canonical {
-            println!("[RenameVideo] 文件名已存在: {}", new_full_path.to_string_lossy());
-            return Err(format!("文件名重复，请换一个名字"));
+            warn!(
+                "[RenameOutput] 文件名已存在: {}",
+                new_full_path.to_string_lossy()
+            );
+            return Err("文件名重复，请换一个名字".to_string());
+        }
+    }
+
+    Ok(new_full_path)
+}
+
+// 随输出文件一起重命名其元数据文件并更新其中的 file_name，没有元数据文件时返回 false
+fn rename_output_metadata(old_media: &std::path::Path, new_media: &std::path::Path) -> bool {
+    let (Some(old_sidecar), Some(new_sidecar)) = (
+        output_metadata_path(old_media),
+        output_metadata_path(new_media),
+    ) else {
+        return false;
+    };
+    if !old_sidecar.is_file() {
+        return false;
+    }
+    if let Err(e) = std::fs::rename(&old_sidecar, &new_sidecar) {
+        warn!(
+            "[RenameOutput] 重命名元数据文件失败 {}: {}",
+            old_sidecar.display(),
+            e
+        );
+        return false;
+    }
+
+    let sidecar = std::fs::read_to_string(&new_sidecar)
+        .ok()
+        .and_then(|content| serde_json::from_str::<Value>(&content).ok());
+    if let Some(mut sidecar) = sidecar {
+        if let Some(file_name) = sidecar.get_mut("file_name") {
+            *file_name = Value::from(
+                new_media
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string()),
+            );
+            if let Err(e) = serde_json::to_string_pretty(&sidecar)
+                .map_err(|e| e.to_string())
+                .and_then(|content| {
+                    std::fs::write(&new_sidecar, content).map_err(|e| e.to_string())
+                })
+            {
+                warn!("[RenameOutput] 更新元数据文件名失败: {}", e);
+            }
         }
     }
+    true
+}
+
+// 重命名输出文件（视频、图片等任意类型），同时重命名其 <文件名>.json 元数据
+#[command]
+pub fn rename_output_file(old_path: String, new_base_name: String) -> Result<String, String> {
+    info!(
+        "[RenameOutput] 重命名文件: {} -> {}",
+        old_path, new_base_name
+    );
+
+    let old_path_obj = std::path::Path::new(&old_path);
+    if !old_path_obj.is_file() {
+        return Err(format!("源文件不存在: {}", old_path));
+    }
+
+    let new_full_path = output_rename_target(old_path_obj, &new_base_name)?;
 
     // 执行重命名
     std::fs::rename(&old_path, &new_full_path)
         .map_err(|e| format!("重命名失败: {}", e))?;
+    rename_output_metadata(old_path_obj, &new_full_path);
 
     let new_path_str = new_full_path.to_string_lossy().to_string();
-    println!("[RenameVideo] 重命名成功: {}", new_path_str);
+    info!("[RenameOutput] 重命名成功: {}", new_path_str);
 
     Ok(new_path_str)
 }
+
+// 批量重命名输出文件，pattern 支持 {index}（从 1 开始，按总数补零）、{date}（YYYY-MM-DD）、{original}（原文件名）
+// 按顺序逐个重命名（元数据文件随之重命名），中途失败时将已重命名的文件恢复原名
+#[command]
+pub fn batch_rename_outputs(pattern: String, paths: Vec<String>) -> Result<Vec<String>, String> {
+    info!(
+        "[RenameOutput] 批量重命名 {} 个文件，模式: {}",
+        paths.len(),
+        pattern
+    );
+
+    if let Some(missing) = paths
+        .iter()
+        .find(|path| !std::path::Path::new(path).is_file())
+    {
+        return Err(format!("源文件不存在: {}", missing));
+    }
+
+    let date = chrono::Local::now().format("%Y-%m-%d").to_string();
+    let width = paths.len().to_string().len();
+    let mut renamed: Vec<(PathBuf, PathBuf)> = Vec::with_capacity(paths.len());
+
+    for (i, old_path) in paths.iter().enumerate() {
+        let old_path_obj = std::path::Path::new(old_path);
+        let original = old_path_obj
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or_default();
+        let new_base_name = pattern
+            .replace("{index}", &format!("{:0width$}", i + 1, width = width))
+            .replace("{date}", &date)
+            .replace("{original}", original);
+
+        let result = output_rename_target(old_path_obj, &new_base_name).and_then(|target| {
+            std::fs::rename(old_path_obj, &target)
+                .map(|_| target)
+                .map_err(|e| format!("重命名失败: {}", e))
+        });
+        match result {
+            Ok(target) => {
+                rename_output_metadata(old_path_obj, &target);
+                renamed.push((old_path_obj.to_path_buf(), target));
+            }
+            Err(e) => {
+                warn!(
+                    "[RenameOutput] 批量重命名在 {} 处失败，开始回滚: {}",
+                    old_path, e
+                );
+                for (original_path, new_path) in renamed.iter().rev() {
+                    match std::fs::rename(new_path, original_path) {
+                        Ok(()) => {
+                            rename_output_metadata(new_path, original_path);
+                        }
+                        Err(rollback_err) => error!(
+                            "[RenameOutput] 回滚失败: {} -> {}: {}",
+                            new_path.display(),
+                            original_path.display(),
+                            rollback_err
+                        ),
+                    }
+                }
+                return Err(format!("{}: {}", old_path, e));
+            }
+        }
+    }
+
+    info!("[RenameOutput] 批量重命名成功: {} 个文件", renamed.len());
+    Ok(renamed
+        .into_iter()
+        .map(|(_, new_path)| new_path.to_string_lossy().to_string())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range_request(value: &str) -> String {
+        format!(
+            "GET /video.mp4 HTTP/1.1\r\nHost: 127.0.0.1\r\nRange: {}\r\n\r\n",
+            value
+        )
+    }
+
+    fn mp4_box(box_type: &[u8; 4], body_len: usize) -> Vec<u8> {
+        let mut data = ((body_len + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(box_type);
+        data.resize(body_len + 8, 0);
+        data
+    }
+
+    fn read_boxes(data: &[u8]) -> Result<Vec<Mp4Box>, String> {
+        read_mp4_top_level_boxes(&mut std::io::Cursor::new(data), data.len() as u64)
+    }
+
+    #[test]
+    fn read_mp4_top_level_boxes_lists_boxes() {
+        let mut data = mp4_box(b"ftyp", 8);
+        data.extend(mp4_box(b"moov", 4));
+        // 64 位扩展大小
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(b"mdat");
+        data.extend_from_slice(&20u64.to_be_bytes());
+        data.extend_from_slice(&[0u8; 4]);
+
+        let boxes = read_boxes(&data).unwrap();
+        let summary: Vec<_> = boxes
+            .iter()
+            .map(|b| (&b.box_type, b.offset, b.size))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![(b"ftyp", 0, 16), (b"moov", 16, 12), (b"mdat", 28, 20)]
+        );
+    }
+
+    #[test]
+    fn read_mp4_top_level_boxes_rejects_oversized_boxes() {
+        // 64 位大小接近 u64::MAX 时不能溢出
+        let mut data = mp4_box(b"ftyp", 8);
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(b"mdat");
+        data.extend_from_slice(&u64::MAX.to_be_bytes());
+        data.extend_from_slice(&[0u8; 16]);
+        assert!(read_boxes(&data).is_err());
+
+        // 扩展头不完整
+        let mut data = mp4_box(b"ftyp", 8);
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(b"mdat");
+        data.extend_from_slice(&[0u8; 4]);
+        assert!(read_boxes(&data).is_err());
+
+        // 32 位大小超出文件长度或小于头长度
+        let mut data = mp4_box(b"moov", 8);
+        data[..4].copy_from_slice(&100u32.to_be_bytes());
+        assert!(read_boxes(&data).is_err());
+        data[..4].copy_from_slice(&4u32.to_be_bytes());
+        assert!(read_boxes(&data).is_err());
+    }
+
+    #[test]
+    fn probe_media_file_reports_malformed_mp4() {
+        // 64 位 box 大小溢出的文件只记录错误，不影响批量中的其他文件
+        let mut data = mp4_box(b"ftyp", 8);
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(b"moov");
+        data.extend_from_slice(&u64::MAX.to_be_bytes());
+        let path =
+            std::env::temp_dir().join(format!("matrix-gen-probe-test-{}.mp4", std::process::id()));
+        std::fs::write(&path, &data).unwrap();
+        let probe = probe_media_file(&path.to_string_lossy());
+        let _ = std::fs::remove_file(&path);
+        assert!(probe.error.is_some());
+        assert_eq!(probe.duration_secs, None);
+    }
+
+    #[test]
+    fn parse_range_header_forms() {
+        assert_eq!(
+            parse_range_header(&range_request("bytes=0-99")),
+            Some((Some(0), Some(99)))
+        );
+        assert_eq!(
+            parse_range_header(&range_request("bytes=500-")),
+            Some((Some(500), None))
+        );
+        assert_eq!(
+            parse_range_header(&range_request("bytes=-200")),
+            Some((None, Some(200)))
+        );
+        assert_eq!(parse_range_header(&range_request("bytes=-")), None);
+        assert_eq!(parse_range_header(&range_request("items=0-1")), None);
+        assert_eq!(parse_range_header("GET / HTTP/1.1\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn satisfiable_range_clamps_to_available() {
+        // bytes=a-b
+        let (start, end) = partial_range_bounds((Some(100), Some(199)), 1000, Some(1000));
+        assert_eq!(satisfiable_range(start, end, 1000), Some((100, 200)));
+        // bytes=a-
+        let (start, end) = partial_range_bounds((Some(100), None), 1000, Some(1000));
+        assert_eq!(satisfiable_range(start, end, 1000), Some((100, 1000)));
+        // bytes=-n，按总长度计算起点
+        let (start, end) = partial_range_bounds((None, Some(200)), 1000, Some(1000));
+        assert_eq!(satisfiable_range(start, end, 1000), Some((800, 1000)));
+        // 后缀长度超过文件长度时从头开始
+        let (start, end) = partial_range_bounds((None, Some(5000)), 1000, None);
+        assert_eq!(satisfiable_range(start, end, 1000), Some((0, 1000)));
+        // 终点超出已有数据时截断
+        assert_eq!(satisfiable_range(900, Some(5000), 1000), Some((900, 1000)));
+    }
+
+    #[test]
+    fn file_server_range_requests() {
+        // 浏览器 <video> 的请求：小写头名，bytes=0- 按已完成文件整体返回
+        let request =
+            "GET /a.mp4 HTTP/1.1\r\nhost: 127.0.0.1\r\naccept: */*\r\nrange: bytes=0-\r\n\r\n";
+        let range = parse_range_header(request).unwrap();
+        let (start, end) = partial_range_bounds(range, 4096, Some(4096));
+        assert_eq!(satisfiable_range(start, end, 4096), Some((0, 4096)));
+
+        // 反向区间返回 416，不会进入发送逻辑
+        let request = "GET /a.mp4 HTTP/1.1\r\nrange: bytes=4000-10\r\n\r\n";
+        let range = parse_range_header(request).unwrap();
+        let (start, end) = partial_range_bounds(range, 4096, Some(4096));
+        assert_eq!(satisfiable_range(start, end, 4096), None);
+    }
+
+    #[test]
+    fn satisfiable_range_rejects_invalid_ranges() {
+        // 终点在起点之前
+        let range = parse_range_header(&range_request("bytes=500-100")).unwrap();
+        let (start, end) = partial_range_bounds(range, 1000, Some(1000));
+        assert_eq!(satisfiable_range(start, end, 1000), None);
+        // 起点超出文件末尾
+        let range = parse_range_header(&range_request("bytes=1000-")).unwrap();
+        let (start, end) = partial_range_bounds(range, 1000, Some(1000));
+        assert_eq!(satisfiable_range(start, end, 1000), None);
+        assert_eq!(satisfiable_range(0, None, 0), None);
+    }
+}
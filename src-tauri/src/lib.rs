@@ -3,77 +3,122 @@ mod commands;
 
 use std::fs;
 use std::path::PathBuf;
-use std::sync::Mutex;
-use tauri::{Manager, State};
+use tauri::State;
 
-fn extract_default_plugin(app: &tauri::App) -> Result<(), String> {
-    // 1. Resolve source path (bundled resource or development path)
-    let exe_path =
-        std::env::current_exe().map_err(|e| format!("Failed to get executable path: {}", e))?;
-
-    let exe_dir = exe_path
-        .parent()
-        .ok_or("Failed to get executable directory")?;
-
-    // Check if in development mode (target/debug exists)
-    let is_dev_mode = exe_dir
-        .parent()
-        .and_then(|p| p.parent())
-        .map_or(false, |project_root| {
-            project_root.join("src-tauri").exists() && project_root.join("src").exists()
-        });
+use log::{debug, info, warn};
 
-    let resource_path = if is_dev_mode {
-        // Development mode: copy from source
-        let project_root = exe_dir.parent().and_then(|p| p.parent()).unwrap();
-        project_root
-            .join("src-tauri")
-            .join("resources")
-            .join("default-provider.js")
-    } else {
-        // Production mode: resolve from bundled resources
-        app.path()
-            .resolve(
-                "resources/default-provider.js",
-                tauri::path::BaseDirectory::Resource,
-            )
-            .map_err(|e| format!("Failed to resolve resource path: {}", e))?
-    };
+// 上次提取的官方插件信息（保存在插件目录的 .default-provider.json 中）
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct ExtractedPluginInfo {
+    version: Option<String>,
+    sha256: String,
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    use sha2::Digest;
+    hex::encode(sha2::Sha256::digest(bytes))
+}
+
+// 将内置的官方插件复制到用户插件目录，返回是否恢复/更新/已是最新
+// 已安装的文件被用户修改过时，只有内置版本更新或 force 为 true 才会覆盖
+pub(crate) fn extract_default_plugin(
+    app: &tauri::AppHandle,
+    force: bool,
+) -> Result<commands::PluginRepairStatus, String> {
+    // 1. Resolve source path (bundled resource or development path)
+    let is_dev_mode = commands::dev_project_root().is_some();
+    let resource_path = commands::default_plugin_source(app)?;
 
     // Also ensure the source file exists in dev mode
     if is_dev_mode && !resource_path.exists() {
-        println!("[PluginExtract] Source file doesn't exist yet in dev mode, skipping copy");
-        return Ok(());
+        warn!("[PluginExtract] Source file doesn't exist yet in dev mode, skipping copy");
+        return Ok(commands::PluginRepairStatus::SourceMissing);
     }
 
-    // 2. Resolve target path (user plugins folder)
-    let plugins_dir = if is_dev_mode {
-        // Development mode: use project root plugins folder
-        let project_root = exe_dir.parent().and_then(|p| p.parent()).unwrap();
-        project_root.join("plugins")
-    } else {
-        // Production mode: use executable directory plugins folder
-        exe_dir.join("plugins")
-    };
-
-    // Ensure plugins dir exists
-    if !plugins_dir.exists() {
-        fs::create_dir_all(&plugins_dir)
-            .map_err(|e| format!("Failed to create plugins directory: {}", e))?;
-    }
+    // 2. Resolve target path (same plugins folder the loader reads from)
+    let plugins_dir = commands::resolve_plugins_dir(app)?;
 
     let target_path = plugins_dir.join("default-provider.js");
+    let info_path = plugins_dir.join(".default-provider.json");
 
-    // 3. Copy/Overwrite logic (always overwrite for official plugin)
-    println!("[PluginExtract] Copying from: {:?}", resource_path);
-    println!("[PluginExtract] Copying to: {:?}", target_path);
+    // 3. Copy/Overwrite logic (keep user edits unless the bundled version is newer)
+    debug!("[PluginExtract] Copying from: {:?}", resource_path);
+    debug!("[PluginExtract] Copying to: {:?}", target_path);
 
     if resource_path.exists() {
-        fs::copy(&resource_path, &target_path)
-            .map_err(|e| format!("Failed to copy default plugin: {}", e))?;
-        println!("[PluginExtract] Default plugin extracted successfully");
+        let source = fs::read(&resource_path)
+            .map_err(|e| format!("Failed to read default plugin: {}", e))?;
+        let source_version = commands::plugin_declared_version(&String::from_utf8_lossy(&source));
+        let last_extracted: Option<ExtractedPluginInfo> = fs::read_to_string(&info_path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok());
+
+        let status = match fs::read(&target_path) {
+            Ok(existing) if existing == source => {
+                debug!("[PluginExtract] Default plugin is up to date");
+                commands::PluginRepairStatus::AlreadyOk
+            }
+            Ok(existing) => {
+                // 与上次提取的内容一致说明用户没有修改，可以直接更新
+                let unmodified = last_extracted
+                    .as_ref()
+                    .is_some_and(|info| info.sha256 == sha256_hex(&existing));
+                let installed_version = last_extracted
+                    .as_ref()
+                    .and_then(|info| info.version.clone())
+                    .or_else(|| {
+                        commands::plugin_declared_version(&String::from_utf8_lossy(&existing))
+                    });
+                let newer = match (
+                    source_version
+                        .as_deref()
+                        .and_then(commands::parse_app_version),
+                    installed_version
+                        .as_deref()
+                        .and_then(commands::parse_app_version),
+                ) {
+                    (Some(source), Some(installed)) => source > installed,
+                    _ => false,
+                };
+                if !(force || unmodified || newer) {
+                    info!(
+                        "[PluginExtract] Default plugin was modified by the user, keeping it (bundled {:?}, installed {:?})",
+                        source_version, installed_version
+                    );
+                    return Ok(commands::PluginRepairStatus::Kept);
+                }
+                commands::PluginRepairStatus::Updated
+            }
+            Err(_) => commands::PluginRepairStatus::Restored,
+        };
+
+        if !matches!(status, commands::PluginRepairStatus::AlreadyOk) {
+            fs::write(&target_path, &source)
+                .map_err(|e| format!("Failed to copy default plugin: {}", e))?;
+            info!("[PluginExtract] Default plugin extracted successfully");
+        }
+
+        // 记录本次提取的版本与哈希，供下次启动判断用户是否修改过
+        let info = ExtractedPluginInfo {
+            version: source_version,
+            sha256: sha256_hex(&source),
+        };
+        let up_to_date = last_extracted
+            .as_ref()
+            .is_some_and(|last| last.sha256 == info.sha256 && last.version == info.version);
+        if !up_to_date {
+            match serde_json::to_string_pretty(&info) {
+                Ok(content) => {
+                    if let Err(e) = fs::write(&info_path, content) {
+                        warn!("[PluginExtract] Failed to save extraction info: {}", e);
+                    }
+                }
+                Err(e) => warn!("[PluginExtract] Failed to serialize extraction info: {}", e),
+            }
+        }
+        Ok(status)
     } else {
-        println!(
+        warn!(
             "[PluginExtract] Source plugin file not found: {:?}",
             resource_path
         );
@@ -81,13 +126,40 @@ fn extract_default_plugin(app: &tauri::App) -> Result<(), String> {
         if !is_dev_mode {
             return Err(format!("Plugin resource not found: {:?}", resource_path));
         }
+        Ok(commands::PluginRepairStatus::SourceMissing)
+    }
+}
+
+// 初始化日志：本 crate 默认 debug 构建输出 Debug、发布构建输出 Info，可通过 RUST_LOG 或 set_log_level 调整
+// 依赖库只输出 Warn 及以上，避免刷屏
+fn init_logging() {
+    let default_level = if cfg!(debug_assertions) {
+        log::LevelFilter::Debug
+    } else {
+        log::LevelFilter::Info
+    };
+    let max_level = std::env::var("RUST_LOG")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default_level);
+
+    if let Err(e) = env_logger::Builder::new()
+        .filter_level(log::LevelFilter::Warn)
+        .filter_module("matrix_gen_pro_lib", log::LevelFilter::Trace)
+        .format_timestamp_millis()
+        .try_init()
+    {
+        eprintln!("[Setup] Failed to initialize logger: {}", e);
     }
 
-    Ok(())
+    // 实际输出级别由全局 max level 控制，set_log_level 在运行时修改它
+    log::set_max_level(max_level);
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    init_logging();
+
     tauri::Builder::default()
         .plugin(tauri_plugin_fs::init())
         // HTTP 插件（用于网络请求）
@@ -99,10 +171,41 @@ pub fn run() {
         // 对话框插件（用于文件选择）
         .plugin(tauri_plugin_dialog::init())
         // 管理状态（防止并发生成）
-        .manage(Mutex::new(false)) // generation_lock: Mutex<bool>
+        .manage(commands::GenerationQueue::default()) // 生成队列（并发名额、暂停）
+        // 全局默认请求头
+        .manage(commands::DefaultHeaders::default())
+        // 输出目录监听器
+        .manage(commands::OutputWatcher::default())
+        // 进行中的网络操作（可统一取消）
+        .manage(commands::ActiveOperations::default())
+        // 按路径的文件写入锁
+        .manage(commands::FileWriteLocks::default())
+        // 持久化上传队列
+        .manage(commands::UploadQueue::default())
+        // 定时缓存维护任务
+        .manage(commands::CacheMaintenance::default())
+        // 分块写入中的输出文件
+        .manage(commands::ChunkedOutputFiles::default())
+        // WebSocket 连接
+        .manage(commands::WebSocketConnections::default())
+        // 运行中的文件服务器
+        .manage(commands::FileServers::default())
+        // 等待确认的输出写入
+        .manage(commands::WriteConfirmations::default())
+        // 退出前等待进行中的写入
+        .manage(commands::ShutdownCoordinator::default())
+        // 已下载待安装的更新包
+        .manage(commands::PendingUpdate::default())
+        // token 自动刷新
+        .manage(commands::AuthRefresh::default())
+        // 可取消的进行中下载
+        .manage(commands::ActiveDownloads::default())
+        // 插件目录监听器（插件热重载）
+        .manage(commands::PluginWatcher::default())
         // 注册所有命令
         .invoke_handler(tauri::generate_handler![
             commands::proxy_http_request,
+            commands::set_default_headers,
             commands::download_file,
             commands::upload_file,
             // commands::upload_video_to_oss, // 已迁移到 Supabase Storage
@@ -122,26 +225,157 @@ pub fn run() {
             commands::show_in_folder,
             commands::check_generation_lock,
             commands::release_generation_lock,
-            commands::execute_powershell_command,
-            commands::rename_video_file,
-            commands::cache_image
+            commands::rename_output_file,
+            commands::cache_image,
+            commands::copy_text_to_clipboard,
+            commands::start_output_watch,
+            commands::stop_output_watch,
+            commands::probe_image,
+            commands::cleanup_temp_files_report,
+            commands::open_app_data_folder,
+            commands::open_config_folder,
+            commands::poll_until,
+            commands::abort_all_operations,
+            commands::perceptual_hash,
+            commands::hamming_distance,
+            commands::get_version_info,
+            commands::set_log_level,
+            commands::test_plugin_request,
+            commands::cache_video_progressive,
+            commands::generate_animated_preview,
+            commands::sanitize_filename,
+            commands::read_file_tail,
+            commands::create_profile,
+            commands::set_active_profile,
+            commands::list_output_profiles,
+            commands::enqueue_upload,
+            commands::get_upload_queue,
+            commands::cancel_upload,
+            commands::verify_media_complete,
+            commands::start_cache_maintenance,
+            commands::stop_cache_maintenance,
+            commands::begin_output_file,
+            commands::append_output_chunk,
+            commands::finish_output_file,
+            commands::ws_connect,
+            commands::ws_send,
+            commands::ws_close,
+            commands::get_output_stats,
+            commands::ensure_app_directories,
+            commands::repair_default_plugin,
+            commands::test_writable,
+            commands::optimize_mp4_faststart,
+            commands::list_file_servers,
+            commands::stop_all_file_servers,
+            commands::acquire_generation_slot,
+            commands::pause_generations,
+            commands::resume_generations,
+            commands::set_max_concurrent_generations,
+            commands::generation_state,
+            commands::export_profile,
+            commands::import_profile,
+            commands::generate_thumbnails_for_dir,
+            commands::diagnose_plugins,
+            commands::probe_media_batch,
+            commands::download_file_hashed,
+            commands::hash_file,
+            commands::save_provider_preset,
+            commands::get_provider_presets,
+            commands::proxy_with_preset,
+            commands::clear_image_cache,
+            commands::set_max_concurrent_requests,
+            commands::network_stats,
+            commands::clip_video,
+            commands::read_clipboard_image,
+            commands::set_confirm_output_writes,
+            commands::get_output_write_settings,
+            commands::confirm_write,
+            commands::list_notification_sounds,
+            commands::play_sound,
+            commands::find_duplicate_outputs,
+            commands::remove_duplicates,
+            commands::request_shutdown,
+            commands::plugin_storage_get,
+            commands::plugin_storage_set,
+            commands::test_proxy,
+            commands::upload_folder,
+            commands::download_update,
+            commands::apply_update,
+            commands::list_recoverable_temp_files,
+            commands::recover_temp_file,
+            commands::set_auth_refresh,
+            commands::get_directory_tree,
+            commands::set_mirror_directory,
+            commands::load_plugins,
+            commands::stop_file_server,
+            commands::cancel_download,
+            commands::proxy_http_stream,
+            commands::upload_to_oss,
+            commands::load_plugin,
+            commands::watch_plugins,
+            commands::stop_watching_plugins,
+            commands::set_plugin_enabled,
+            commands::list_plugins,
+            commands::write_output_file_binary,
+            commands::list_outputs,
+            commands::set_image_cache_limit,
+            commands::get_image_cache_stats,
+            commands::cleanup_temp_files_cmd,
+            commands::play_notification_sound,
+            commands::batch_rename_outputs,
+            commands::abort_output_file
         ])
         .setup(|app| {
+            // 写入实例锁，避免其他实例启动清理时删除本实例的临时文件
+            if let Err(e) = crate::commands::acquire_instance_lock() {
+                warn!("[Setup] Failed to create instance lock: {}", e);
+            }
+
             // 在应用启动时清理临时文件
-            if let Err(e) = crate::commands::cleanup_temp_files() {
-                println!("[Setup] Temp file cleanup failed: {}", e);
+            if let Err(e) = crate::commands::cleanup_temp_files_on_startup() {
+                warn!("[Setup] Temp file cleanup failed: {}", e);
+            }
+
+            // 清理后创建并检查应用目录，目录不可写时尽早暴露问题
+            let directory_report = crate::commands::check_app_directories(app.handle());
+            if !directory_report.all_ok {
+                warn!("[Setup] Some app directories are not writable, see [EnsureDirs] logs");
+            }
+
+            // 恢复已保存的定时缓存维护
+            if let Err(e) = crate::commands::resume_cache_maintenance(app.handle()) {
+                warn!("[Setup] Failed to resume cache maintenance: {}", e);
+            }
+
+            // 恢复上传队列并启动后台上传
+            if let Err(e) = crate::commands::start_upload_queue(app.handle()) {
+                warn!("[Setup] Failed to start upload queue: {}", e);
             }
 
             // 自动提取默认插件到用户插件文件夹
-            println!("[Setup] Extracting default provider plugin...");
-            if let Err(e) = extract_default_plugin(app) {
-                println!("[Setup] Failed to extract default plugin: {}", e);
-            } else {
-                println!("[Setup] Default plugin extracted successfully");
+            info!("[Setup] Extracting default provider plugin...");
+            match extract_default_plugin(app.handle(), false) {
+                Ok(status) => info!("[Setup] Default plugin check finished: {:?}", status),
+                Err(e) => warn!("[Setup] Failed to extract default plugin: {}", e),
             }
 
             Ok(())
         })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| match event {
+            tauri::RunEvent::ExitRequested { api, .. } => {
+                // 有进行中的写入时推迟退出，写入完成后自动退出
+                if crate::commands::defer_exit_for_pending_writes(app) {
+                    api.prevent_exit();
+                }
+            }
+            tauri::RunEvent::Exit => {
+                // 退出时释放实例锁
+                crate::commands::release_instance_lock();
+                // 停止插件目录监听
+                crate::commands::stop_plugin_watcher(app);
+            }
+            _ => {}
+        });
 }
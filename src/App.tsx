@@ -1121,9 +1121,18 @@ function App() {
     const checkForUpdates = async () => {
       try {
         console.log('[App] 检查更新中...');
-        const updateResult = await invoke<{ shouldUpdate: boolean; manifest?: { version: string; body: string; date: string } }>('check_for_updates');
-        
-        if (updateResult?.shouldUpdate && updateResult.manifest?.version) {
+        const updateResult = await invoke<{
+          status: 'update_available' | 'up_to_date' | 'no_updater' | 'release_not_found' | 'network_error' | 'signature_error' | 'unknown';
+          should_update: boolean;
+          manifest?: { version: string; body: string; date: string };
+          error?: string;
+        }>('check_for_updates');
+
+        if (updateResult?.error) {
+          console.log(`[App] 更新检查未完成 (${updateResult.status}):`, updateResult.error);
+        }
+
+        if (updateResult?.should_update && updateResult.manifest?.version) {
           console.log(`[App] 发现新版本: ${updateResult.manifest.version}`);
           setUpdateAvailable(updateResult.manifest.version);
         }
@@ -1349,7 +1358,9 @@ function App() {
 
       // 直接下载到临时文件
       const tempFileName = `temp_${jobId}_${Date.now()}.mp4`;
-      const tempPath = await invoke<string>('download_file', { url, fileName: tempFileName });
+      const download = await invoke<{ path: string; size: number; resumed: boolean }>('download_file', { url, fileName: tempFileName });
+      const tempPath = download.path;
+      console.log(`[Download] 下载完成: ${download.size} bytes${download.resumed ? '（断点续传）' : ''}`);
 
       // 读取临时文件为base64
       const base64Data = await invoke<string>('read_file_base64', { options: { path: tempPath } });
@@ -1360,7 +1371,7 @@ function App() {
       const fileName = `video_${jobId}.mp4`;
 
       // 使用 Tauri 命令保存到输出目录
-      const localPath = await invoke<string>('write_output_file', {
+      const { path: localPath } = await invoke<{ path: string; metadata_path: string | null }>('write_output_file', {
         options: {
           file_name: fileName,
           data: base64Data,
@@ -1629,7 +1640,7 @@ function App() {
     try {
 
       // Call backend to rename the file
-      const newFullPath = await invoke<string>('rename_video_file', {
+      const newFullPath = await invoke<string>('rename_output_file', {
         oldPath: jobToRename.filePath,
         newBaseName: sanitizedName
       });
@@ -16,7 +16,7 @@ export class FileService {
       console.log(`[FileService] 开始保存 ${mediaType} 文件: ${fileName}`);
 
       // 调用Rust后端保存文件
-      const absolutePath = await invoke<string>('write_output_file', {
+      const { path: absolutePath } = await invoke<{ path: string; metadata_path: string | null }>('write_output_file', {
         options: {
           file_name: fileName,
           data: base64Data,
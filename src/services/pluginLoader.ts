@@ -1,6 +1,22 @@
 import { invoke } from '@tauri-apps/api/core';
 import { AIPlugin, PluginManifest } from '../types/plugin';
 
+/**
+ * Plugin file returned by the `load_plugins` command
+ */
+interface PluginFile {
+  id: string;
+  file_name: string;
+  content: string;
+  size_bytes: number;
+  modified: number | null;
+  name: string | null;
+  version: string | null;
+  compatible: boolean;
+  required_version: string | null;
+  current_version: string;
+}
+
 /**
  * Load external plugins from the plugins directory
  * @returns Array of validated AIPlugin objects
@@ -9,14 +25,14 @@ export async function loadExternalPlugins(): Promise<AIPlugin[]> {
   try {
     console.log('[PluginLoader] Loading external plugins...');
 
-    // Call Rust command to get raw plugin file contents
-    const pluginContents: string[] = await invoke('load_plugins_raw');
+    // Call Rust command to get plugin files; plugins requiring a newer app version are skipped
+    const pluginFiles: PluginFile[] = await invoke('load_plugins', { skipIncompatible: true });
 
-    console.log(`[PluginLoader] Found ${pluginContents.length} plugin files`);
+    console.log(`[PluginLoader] Found ${pluginFiles.length} compatible plugin files`);
 
     const loadedPlugins: AIPlugin[] = [];
 
-    for (const script of pluginContents) {
+    for (const { file_name, content: script } of pluginFiles) {
       try {
         // Safely evaluate the JavaScript code
         // Using new Function to create an isolated scope
@@ -31,7 +47,7 @@ export async function loadExternalPlugins(): Promise<AIPlugin[]> {
           console.warn('[PluginLoader] Invalid plugin structure, skipping:', loadedPlugin);
         }
       } catch (error) {
-        console.error('[PluginLoader] Failed to load plugin:', error);
+        console.error(`[PluginLoader] Failed to load plugin ${file_name}:`, error);
       }
     }
 
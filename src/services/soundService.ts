@@ -63,13 +63,10 @@ export class SoundService {
 
     this.debouncedPlay(async () => {
       try {
-        // Use Windows system sound for success/completion
-        await invoke('execute_powershell_command', {
-          command: 'powershell -c "(New-Object Media.SoundPlayer \'C:\\Windows\\Media\\notify.wav\').PlaySync();"'
-        });
+        await invoke('play_notification_sound', { kind: 'done' });
       } catch (error) {
         console.warn('[SoundService] Failed to play job complete sound:', error);
-        // Fallback to Web Audio API if PowerShell fails
+        // Fallback to Web Audio API if no system sound is available
         this.fallbackBeep();
       }
     });
@@ -83,9 +80,7 @@ export class SoundService {
 
     this.debouncedPlay(async () => {
       try {
-        await invoke('execute_powershell_command', {
-          command: 'powershell -c "(New-Object Media.SoundPlayer \'C:\\Windows\\Media\\notify.wav\').PlaySync();"'
-        });
+        await invoke('play_notification_sound', { kind: 'success' });
       } catch (error) {
         console.warn('[SoundService] Failed to play success sound:', error);
         this.fallbackBeep();
@@ -100,9 +95,7 @@ export class SoundService {
     if (!this.isEnabled) return;
 
     try {
-      await invoke('execute_powershell_command', {
-        command: 'powershell -c "(New-Object Media.SoundPlayer \'C:\\Windows\\Media\\Windows Error.wav\').PlaySync();"'
-      });
+      await invoke('play_notification_sound', { kind: 'error' });
     } catch (error) {
       console.warn('[SoundService] Failed to play error sound:', error);
       this.fallbackErrorBeep();
@@ -116,9 +109,7 @@ export class SoundService {
     if (!this.isEnabled) return;
 
     try {
-      await invoke('execute_powershell_command', {
-        command: 'powershell -c "(New-Object Media.SoundPlayer \'C:\\Windows\\Media\\Windows Exclamation.wav\').PlaySync();"'
-      });
+      await invoke('play_notification_sound', { kind: 'warning' });
     } catch (error) {
       console.warn('[SoundService] Failed to play warning sound:', error);
       this.fallbackBeep();